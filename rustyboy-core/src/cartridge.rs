@@ -1,10 +1,57 @@
+use crate::mbc;
+
 const TITLE_START_ADDR: u16 = 0x0134;
 const TITLE_END_ADDR: u16 = 0x0143;
+const CGB_FLAG_ADDR: u16 = 0x0143;
+const SGB_FLAG_ADDR: u16 = 0x0146;
 const CART_TYPE_ADDR: u16 = 0x0147;
 const ROM_SIZE_ADDR: u16 = 0x0148;
 const RAM_SIZE_ADDR: u16 = 0x0149;
+const OLD_LICENSEE_ADDR: u16 = 0x014B;
+
+/// The smallest ROM there is, two 16 KiB banks
+const MIN_ROM_SIZE: usize = 0x8000;
+
+/// Errors that can happen while loading a cartridge
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(std::io::Error),
+    /// The ROM is smaller than the 32 KiB every cartridge has
+    TooSmall(usize),
+    InvalidCartridgeType(u8),
+    InvalidRomSize(u8),
+    /// The ROM is shorter than the size in its header
+    RomSizeMismatch { header: usize, actual: usize },
+    InvalidRamSize(u8),
+    /// The cartridge uses a memory bank controller that isn't emulated yet
+    Unsupported(CartridgeType),
+}
+
+impl std::fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::TooSmall(size) => write!(f, "ROM is too small ({} bytes)", size),
+            Self::InvalidCartridgeType(value) => write!(f, "Invalid cartridge type {:#04X}", value),
+            Self::InvalidRomSize(value) => write!(f, "Invalid ROM size {:#04X}", value),
+            Self::RomSizeMismatch { header, actual } => {
+                write!(f, "ROM is {} bytes but its header says {} bytes", actual, header)
+            }
+            Self::InvalidRamSize(value) => write!(f, "Invalid RAM size {:#04X}", value),
+            Self::Unsupported(cart_type) => write!(f, "Unsupported cartridge type {}", cart_type),
+        }
+    }
+}
 
-#[derive(PartialEq)]
+impl std::error::Error for CartridgeError {}
+
+impl From<std::io::Error> for CartridgeError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub enum CartridgeType {
     RomOnly = 0x00,
     Mbc1 = 0x01,
@@ -36,6 +83,26 @@ pub enum CartridgeType {
     HuC1RamBattery = 0xFF,
 }
 
+impl CartridgeType {
+    /// Does the cartridge have a battery that keeps its RAM when the power is off
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            Self::Mbc1RamBattery
+                | Self::Mbc2Battery
+                | Self::RomRamBattery
+                | Self::Mmm01RamBattery
+                | Self::Mbc3TimerBattery
+                | Self::Mbc3TimerRamBattery
+                | Self::Mbc3RamBattery
+                | Self::Mbc5RamBattery
+                | Self::Mbc5RumbleRamBattery
+                | Self::Mbc7SensorRumbleRamBattery
+                | Self::HuC1RamBattery
+        )
+    }
+}
+
 impl std::fmt::Display for CartridgeType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -109,6 +176,7 @@ impl TryFrom<u8> for CartridgeType {
     }
 }
 
+#[derive(Clone, Copy)]
 enum RomSize {
     K32 = 0x00,
     K64 = 0x01,
@@ -143,6 +211,26 @@ impl std::fmt::Display for RomSize {
     }
 }
 
+impl From<RomSize> for usize {
+    fn from(value: RomSize) -> Self {
+        let banks = match value {
+            RomSize::K32 => 2,
+            RomSize::K64 => 4,
+            RomSize::K128 => 8,
+            RomSize::K256 => 16,
+            RomSize::K512 => 32,
+            RomSize::M1 => 64,
+            RomSize::M2 => 128,
+            RomSize::M4 => 256,
+            RomSize::M8 => 512,
+            RomSize::M1_1 => 72,
+            RomSize::M1_2 => 80,
+            RomSize::M1_5 => 96,
+        };
+        banks * 0x4000
+    }
+}
+
 impl TryFrom<u8> for RomSize {
     type Error = ();
 
@@ -165,6 +253,27 @@ impl TryFrom<u8> for RomSize {
     }
 }
 
+/// Level of Gameboy Color support declared in the cartridge header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgbSupport {
+    /// The game only uses original Gameboy features
+    None,
+    /// The game uses CGB features but also works on the original Gameboy
+    Compatible,
+    /// The game only works on the CGB
+    Only,
+}
+
+impl From<u8> for CgbSupport {
+    fn from(value: u8) -> Self {
+        match value {
+            0xC0 => Self::Only,
+            x if x & 0x80 != 0 => Self::Compatible,
+            _ => Self::None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 enum RamSize {
     None = 0x00,
@@ -220,6 +329,10 @@ impl Into<usize> for RamSize {
 pub struct Cartridge {
     pub cart_type: CartridgeType,
     pub title: String,
+    /// CGB support from the header
+    pub cgb_support: CgbSupport,
+    /// Whether the header enables SGB functions
+    pub sgb_support: bool,
     pub rom: Vec<u8>,
     pub ram: Vec<u8>,
 }
@@ -230,17 +343,45 @@ impl Cartridge {
         Cartridge {
             cart_type: CartridgeType::RomOnly,
             title: String::from(""),
+            cgb_support: CgbSupport::None,
+            sgb_support: false,
             rom: vec![0xFF; 0x8000],
             ram: vec![0xFF; 0x2000],
         }
     }
 
     /// Create a new Cartridge from a ROM file
-    pub fn new_from_rom(rom_name: &str) -> Self {
-        let rom = std::fs::read(rom_name).expect("Failed to open ROM file");
-        let cart_type = CartridgeType::try_from(rom[CART_TYPE_ADDR as usize]).unwrap();
-        let rom_size = RomSize::try_from(rom[ROM_SIZE_ADDR as usize]).unwrap();
-        let ram_size = RamSize::try_from(rom[RAM_SIZE_ADDR as usize]).unwrap();
+    pub fn new_from_rom(rom_name: &str) -> Result<Self, CartridgeError> {
+        let rom = std::fs::read(rom_name)?;
+        log::info!("Loaded ROM from {}", rom_name);
+        Self::new_from_bytes(rom)
+    }
+
+    /// Create a new Cartridge from the contents of a ROM file
+    pub fn new_from_bytes(mut rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        if rom.len() < MIN_ROM_SIZE {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+        let cart_type = rom[CART_TYPE_ADDR as usize];
+        let cart_type =
+            CartridgeType::try_from(cart_type).map_err(|_| CartridgeError::InvalidCartridgeType(cart_type))?;
+        if !mbc::is_supported(&cart_type) {
+            return Err(CartridgeError::Unsupported(cart_type));
+        }
+        let rom_size = rom[ROM_SIZE_ADDR as usize];
+        let rom_size = RomSize::try_from(rom_size).map_err(|_| CartridgeError::InvalidRomSize(rom_size))?;
+        // Banks are read by offset, so a truncated ROM would read past its end
+        // Longer ROMs (padded or overdumped) are fine once they're padded to whole banks
+        let header_size = rom_size.into();
+        if rom.len() < header_size {
+            return Err(CartridgeError::RomSizeMismatch {
+                header: header_size,
+                actual: rom.len(),
+            });
+        }
+        rom.resize(rom.len().next_multiple_of(mbc::ROM_BANK_SIZE), 0xFF);
+        let ram_size = rom[RAM_SIZE_ADDR as usize];
+        let ram_size = RamSize::try_from(ram_size).map_err(|_| CartridgeError::InvalidRamSize(ram_size))?;
         // For some reason some games try writing to SRAM even if they don't have any
         // This causes a panic if we don't allocate any RAM
         // So we allocate 2KB of RAM if the game doesn't have any
@@ -253,19 +394,26 @@ impl Cartridge {
             }
         ];
         let title = Self::get_title(&rom);
+        let cgb_support = CgbSupport::from(rom[CGB_FLAG_ADDR as usize]);
+        // SGB functions are only enabled if the old licensee code is also set to 0x33
+        let sgb_support =
+            rom[SGB_FLAG_ADDR as usize] == 0x03 && rom[OLD_LICENSEE_ADDR as usize] == 0x33;
 
-        log::info!("Loaded ROM from {}", rom_name);
         log::debug!("Title: {}", Self::get_title(&rom));
         log::debug!("Cartridge Type: {}", cart_type);
         log::debug!("ROM Size: {}", rom_size);
-        log::debug!("RAM Size: {}\n", ram_size);
+        log::debug!("RAM Size: {}", ram_size);
+        log::debug!("CGB Support: {:?}", cgb_support);
+        log::debug!("SGB Support: {}\n", sgb_support);
 
-        Cartridge {
+        Ok(Cartridge {
             cart_type,
             title,
+            cgb_support,
+            sgb_support,
             rom,
             ram,
-        }
+        })
     }
 
     /// Get the title of the ROM from the header
@@ -295,8 +443,70 @@ mod tests {
 
     #[test]
     fn test_new_from_rom() {
-        let cart = Cartridge::new_from_rom("resources/test-rom.gb");
+        let cart = Cartridge::new_from_rom("resources/test-rom.gb").unwrap();
         assert_eq!(cart.rom.len(), 0x8000);
         assert_eq!(cart.ram.len(), 0x2000);
+        assert_eq!(cart.cgb_support, CgbSupport::Compatible);
+        assert!(!cart.sgb_support);
+    }
+
+    #[test]
+    fn test_new_from_rom_missing() {
+        let result = Cartridge::new_from_rom("resources/missing.gb");
+        assert!(matches!(result, Err(CartridgeError::Io(_))));
+    }
+
+    #[test]
+    fn test_new_from_bytes_errors() {
+        let rom = std::fs::read("resources/test-rom.gb").unwrap();
+        assert!(matches!(
+            Cartridge::new_from_bytes(rom[..0x4000].to_vec()),
+            Err(CartridgeError::TooSmall(0x4000))
+        ));
+
+        let mut bad_type = rom.clone();
+        bad_type[CART_TYPE_ADDR as usize] = 0x04;
+        assert!(matches!(
+            Cartridge::new_from_bytes(bad_type),
+            Err(CartridgeError::InvalidCartridgeType(0x04))
+        ));
+
+        let mut unsupported = rom.clone();
+        unsupported[CART_TYPE_ADDR as usize] = 0x19;
+        assert!(matches!(
+            Cartridge::new_from_bytes(unsupported),
+            Err(CartridgeError::Unsupported(CartridgeType::Mbc5))
+        ));
+
+        let mut bad_rom_size = rom.clone();
+        bad_rom_size[ROM_SIZE_ADDR as usize] = 0x01;
+        assert!(matches!(
+            Cartridge::new_from_bytes(bad_rom_size),
+            Err(CartridgeError::RomSizeMismatch { header: 0x10000, actual: 0x8000 })
+        ));
+
+        let mut padded = rom.clone();
+        padded.resize(0xA000, 0xFF);
+        assert_eq!(Cartridge::new_from_bytes(padded).unwrap().rom.len(), 0xC000);
+
+        let mut bad_ram = rom;
+        bad_ram[RAM_SIZE_ADDR as usize] = 0x06;
+        assert!(matches!(
+            Cartridge::new_from_bytes(bad_ram),
+            Err(CartridgeError::InvalidRamSize(0x06))
+        ));
+    }
+
+    #[test]
+    fn test_has_battery() {
+        assert!(CartridgeType::Mbc1RamBattery.has_battery());
+        assert!(!CartridgeType::Mbc1Ram.has_battery());
+    }
+
+    #[test]
+    fn test_cgb_support_from_header() {
+        assert_eq!(CgbSupport::from(0x00), CgbSupport::None);
+        assert_eq!(CgbSupport::from(0x80), CgbSupport::Compatible);
+        assert_eq!(CgbSupport::from(0xC0), CgbSupport::Only);
     }
 }
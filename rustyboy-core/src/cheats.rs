@@ -0,0 +1,294 @@
+use std::str::FromStr;
+
+/// Errors while decoding cheat codes or loading a cheat file
+#[derive(Debug)]
+pub enum CheatError {
+    Io(std::io::Error),
+    /// A code that is neither a Game Genie nor a GameShark code
+    InvalidCode(String),
+    /// A line of a cheat file with an invalid code
+    InvalidLine { line: usize, code: String },
+}
+
+impl std::fmt::Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::InvalidCode(code) => write!(f, "Invalid cheat code {}", code),
+            Self::InvalidLine { line, code } => write!(f, "Invalid cheat code {} on line {}", code, line),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+impl From<std::io::Error> for CheatError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A single decoded cheat code
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheatCode {
+    /// Game Genie, replaces a byte read from ROM
+    /// With a compare byte the patch only applies while the ROM has that byte there, which
+    /// limits it to a single ROM bank
+    GameGenie { addr: u16, value: u8, compare: Option<u8> },
+    /// GameShark, writes a byte to RAM every frame
+    GameShark { bank: u8, addr: u16, value: u8 },
+}
+
+impl CheatCode {
+    /// The WRAM bank a GameShark code writes to, `None` for the bank currently mapped
+    /// Types 0x90-0x97 select a WRAM bank on the CGB, other types write to whatever is mapped
+    fn ram_bank(bank: u8, addr: u16) -> Option<usize> {
+        match addr {
+            0xD000..=0xDFFF if bank & 0xF8 == 0x90 => Some(((bank & 0x07) as usize).max(1)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for CheatCode {
+    type Err = CheatError;
+
+    /// Decode a Game Genie code (`ABC-DEF` or `ABC-DEF-GHI`) or a GameShark code (`TTVVLLHH`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CheatError::InvalidCode(s.to_string());
+        let digits = s
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+
+        match digits.len() {
+            // Game Genie codes are ABC-DEF-GHI, AB is the new value and FCDE the address XORed with 0xF000
+            // GI is the compare byte XORed with 0xBA and rotated left by 2, H is a checksum that's ignored
+            6 | 9 => {
+                let addr = ((digits[5] ^ 0xF) as u16) << 12
+                    | (digits[2] as u16) << 8
+                    | (digits[3] as u16) << 4
+                    | digits[4] as u16;
+                if addr > 0x7FFF {
+                    return Err(invalid());
+                }
+                let compare = (digits.len() == 9).then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+                Ok(CheatCode::GameGenie {
+                    addr,
+                    value: byte(0),
+                    compare,
+                })
+            }
+            // GameShark codes are a type byte, the value and the little endian address
+            8 if !s.contains('-') => Ok(CheatCode::GameShark {
+                bank: byte(0),
+                value: byte(2),
+                addr: u16::from_le_bytes([byte(4), byte(6)]),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A named cheat made up of one or more codes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    /// The codes as they were entered, separated by `+`
+    pub code: String,
+    pub codes: Vec<CheatCode>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Create an enabled cheat from one or more codes separated by `+`
+    pub fn new(code: &str, name: &str) -> Result<Self, CheatError> {
+        let codes = code.split('+').map(|c| c.trim().parse()).collect::<Result<Vec<_>, _>>()?;
+        Ok(Cheat {
+            name: name.to_string(),
+            code: code.to_string(),
+            codes,
+            enabled: true,
+        })
+    }
+}
+
+/// The cheats of the running game
+///
+/// Cheat files have a cheat on every line, written as the codes separated by `+` followed by a
+/// name. Lines starting with `#` are comments, and cheats starting with `!` are disabled:
+///
+/// ```text
+/// # Infinite lives
+/// 00A-17B-C49 Lives
+/// !01FF12C1+01FF13C1 Max money
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cheats {
+    pub cheats: Vec<Cheat>,
+    /// Turns all cheats off without losing which ones are enabled
+    pub enabled: bool,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats {
+            cheats: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    /// Parse the contents of a cheat file
+    pub fn parse(s: &str) -> Result<Self, CheatError> {
+        let mut cheats = Cheats::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (enabled, line) = match line.strip_prefix('!') {
+                Some(line) => (false, line),
+                None => (true, line),
+            };
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let mut cheat = Cheat::new(code, name.trim()).map_err(|_| CheatError::InvalidLine {
+                line: i + 1,
+                code: code.to_string(),
+            })?;
+            cheat.enabled = enabled;
+            cheats.add(cheat);
+        }
+        Ok(cheats)
+    }
+
+    pub fn load(path: &str) -> Result<Self, CheatError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    fn active_codes(&self) -> impl Iterator<Item = &CheatCode> {
+        self.cheats
+            .iter()
+            .filter(|cheat| self.enabled && cheat.enabled)
+            .flat_map(|cheat| &cheat.codes)
+    }
+
+    /// Apply the Game Genie codes to a byte read from ROM
+    pub fn patch_rom(&self, addr: u16, value: u8) -> u8 {
+        self.active_codes()
+            .find_map(|code| match *code {
+                CheatCode::GameGenie {
+                    addr: patch_addr,
+                    value: patch,
+                    compare,
+                } if patch_addr == addr && compare.is_none_or(|compare| compare == value) => Some(patch),
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+
+    /// The address, bank and value of every GameShark write, done once per frame
+    pub fn ram_writes(&self) -> impl Iterator<Item = (u16, Option<usize>, u8)> + '_ {
+        self.active_codes().filter_map(|code| match *code {
+            CheatCode::GameShark { bank, addr, value } => Some((addr, CheatCode::ram_bank(bank, addr), value)),
+            _ => None,
+        })
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_game_genie() {
+        assert_eq!(
+            "3EA-14F".parse::<CheatCode>().unwrap(),
+            CheatCode::GameGenie {
+                addr: 0x0A14,
+                value: 0x3E,
+                compare: None,
+            }
+        );
+        assert_eq!(
+            "3ea-14e-e6e".parse::<CheatCode>().unwrap(),
+            CheatCode::GameGenie {
+                addr: 0x1A14,
+                value: 0x3E,
+                compare: Some(0x01),
+            }
+        );
+        // Addresses outside of ROM can't be patched
+        assert!("3EA-140".parse::<CheatCode>().is_err());
+    }
+
+    #[test]
+    fn test_decode_game_shark() {
+        assert_eq!(
+            "0163A3C2".parse::<CheatCode>().unwrap(),
+            CheatCode::GameShark {
+                bank: 0x01,
+                addr: 0xC2A3,
+                value: 0x63,
+            }
+        );
+        assert!("0163-A3C2".parse::<CheatCode>().is_err());
+        assert!("0163A3CG".parse::<CheatCode>().is_err());
+        assert!("0163A".parse::<CheatCode>().is_err());
+    }
+
+    #[test]
+    fn test_patch_rom() {
+        let mut cheats = Cheats::new();
+        cheats.add(Cheat::new("3EA-14F", "").unwrap());
+        cheats.add(Cheat::new("3EA-14E-E6E", "").unwrap());
+        assert_eq!(cheats.patch_rom(0x0A14, 0x00), 0x3E);
+        assert_eq!(cheats.patch_rom(0x0A15, 0x00), 0x00);
+        assert_eq!(cheats.patch_rom(0x1A14, 0x01), 0x3E);
+        assert_eq!(cheats.patch_rom(0x1A14, 0x02), 0x02);
+
+        cheats.cheats[0].enabled = false;
+        assert_eq!(cheats.patch_rom(0x0A14, 0x00), 0x00);
+        cheats.enabled = false;
+        assert_eq!(cheats.patch_rom(0x1A14, 0x01), 0x01);
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let cheats = Cheats::parse(
+            "# Comment\n\
+             \n\
+             3EA-14F Infinite lives\n\
+             !0163A3C2+9263A3D2 Max money\n",
+        )
+        .unwrap();
+        assert_eq!(cheats.cheats.len(), 2);
+        assert_eq!(cheats.cheats[0].name, "Infinite lives");
+        assert!(cheats.cheats[0].enabled);
+        assert!(!cheats.cheats[1].enabled);
+        assert_eq!(cheats.cheats[1].codes.len(), 2);
+        assert_eq!(cheats.ram_writes().count(), 0);
+
+        let mut cheats = cheats;
+        cheats.cheats[1].enabled = true;
+        let writes: Vec<_> = cheats.ram_writes().collect();
+        assert_eq!(writes, [(0xC2A3, None, 0x63), (0xD2A3, Some(2), 0x63)]);
+
+        assert!(matches!(
+            Cheats::parse("3EA-14F Lives\nnope Broken"),
+            Err(CheatError::InvalidLine { line: 2, .. })
+        ));
+    }
+}
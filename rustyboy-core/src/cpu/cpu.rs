@@ -2,6 +2,8 @@ use crate::cpu::instructions::{get_instruction_by_opcode, Instruction, Instructi
 use crate::cpu::interrupts::{handle_interrupts, pending_interrupt};
 use crate::cpu::registers::Registers;
 use crate::mmu::Memory;
+use crate::model::Model;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 /// Emulation of the Gameboy CPU
 #[derive(Clone, Debug, PartialEq)]
@@ -14,20 +16,37 @@ pub struct Cpu {
     pub ei: bool,
     /// Boolean to track if the CPU is halted
     pub halted: bool,
+    /// Boolean to track if HALT was executed with an interrupt pending while IME was off
+    /// The byte after HALT is then read twice since PC fails to increment
+    pub halt_bug: bool,
+    /// Boolean to track if the CPU is stopped, only a joypad line going low wakes it up
+    pub stopped: bool,
+    /// Boolean to track if the CPU locked up by executing an illegal opcode
+    /// Nothing but a reset gets it out of this state
+    pub locked_up: bool,
 }
 
 impl Cpu {
     /// Create a new CPU
     pub fn new() -> Self {
+        Self::new_with_model(Model::Dmg)
+    }
+
+    /// Create a new CPU with the register values left behind by the boot ROM of a given model
+    pub fn new_with_model(model: Model) -> Self {
+        let (af, bc, de, hl) = match model {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::Agb => (0x1100, 0x0100, 0xFF56, 0x000D),
+        };
         let mut registers = Registers::new();
-        registers.a = 0x01;
-        registers.f = 0xB0;
-        registers.b = 0x00;
-        registers.c = 0x13;
-        registers.d = 0x00;
-        registers.e = 0xD8;
-        registers.h = 0x01;
-        registers.l = 0x4D;
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
         registers.sp = 0xFFFE;
         registers.pc = 0x0100;
         Cpu {
@@ -35,46 +54,50 @@ impl Cpu {
             ime: false,
             ei: false,
             halted: false,
+            halt_bug: false,
+            stopped: false,
+            locked_up: false,
         }
     }
 
     /// Step through the emulator
+    /// Every memory access ticks the rest of the system by one M-cycle as it happens
     /// Returns the number of cycles used
     pub fn step(&mut self, mmu: &mut Memory) -> u8 {
         log::trace!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:02X} PC: {:04X} ({:02X} {:02X} {:02X} {:02X})",
-        self.reg.a, self.reg.f, self.reg.b, self.reg.c, self.reg.d, self.reg.e, self.reg.h, self.reg.l, self.reg.sp, self.reg.pc, mmu.get_byte(self.reg.pc), mmu.get_byte(self.reg.pc + 1), mmu.get_byte(self.reg.pc + 2), mmu.get_byte(self.reg.pc + 3));
+        self.reg.a, self.reg.f, self.reg.b, self.reg.c, self.reg.d, self.reg.e, self.reg.h, self.reg.l, self.reg.sp, self.reg.pc, mmu.get_byte(self.reg.pc), mmu.get_byte(self.reg.pc.wrapping_add(1)), mmu.get_byte(self.reg.pc.wrapping_add(2)), mmu.get_byte(self.reg.pc.wrapping_add(3)));
 
-        let op_code = self.read_opcode(mmu);
-
-        let instruction = match get_instruction_by_opcode(&op_code) {
-            Some(instruction) => instruction,
-            None => {
-                match op_code {
-                    OpCode::CB(value) => panic!(
-                        "Unimplemented CB instruction! {:#04X} PC: {:#06X}",
-                        value, self.reg.pc,
-                    ),
-                    OpCode::Regular(value) => panic!(
-                        "Unimplemented instruction! {:#04X} PC: {:#06X}",
-                        value, self.reg.pc,
-                    ),
-                };
+        // The whole system is paused while stopped
+        if self.stopped {
+            if !mmu.joypad.selected_line_low() {
+                return 4;
             }
-        };
+            log::trace!("Exiting STOP");
+            self.stopped = false;
+        }
 
-        if self.halted && pending_interrupt(mmu) {
-            self.halted = false;
-            log::trace!("Exiting HALT");
-            if !self.ime {
-                // HALT bug
-                // If interrupts are disabled but there's a pending interrupt, HALT ends
-                // but the PC isn't incremented
-                self.reg.pc -= 1;
-            }
+        let start = mmu.clock_cycles();
+        let cycles = self.step_instruction(mmu);
+
+        // Whatever the instruction didn't spend on memory accesses is spent on internal operations
+        while mmu.clock_cycles() - start < cycles as u64 {
+            mmu.tick();
+        }
+        cycles
+    }
+
+    /// Handle HALT and interrupts, then fetch and execute the next instruction
+    fn step_instruction(&mut self, mmu: &mut Memory) -> u8 {
+        if self.locked_up {
+            return 4;
         }
 
-        // If we're halted and there are no pending interrupts, exit early
         if self.halted {
+            if pending_interrupt(mmu) {
+                // Waking up takes an M-cycle before the next instruction or interrupt dispatch
+                self.halted = false;
+                log::trace!("Exiting HALT");
+            }
             return 4;
         }
 
@@ -84,6 +107,24 @@ impl Cpu {
             return cycles_used;
         }
 
+        let op_code = self.read_opcode(mmu);
+        if self.halt_bug {
+            // The instruction runs as if it started a byte earlier, so its operands and length
+            // are applied to the address of the opcode itself
+            self.halt_bug = false;
+            self.reg.pc = self.reg.pc.wrapping_sub(1);
+        }
+
+        let instruction = match get_instruction_by_opcode(&op_code) {
+            Some(instruction) => instruction,
+            None => {
+                // The illegal opcodes hang the CPU, the rest of the system keeps running
+                log::error!("Illegal instruction {:?} at PC: {:#06X}, locking up", op_code, self.reg.pc);
+                self.locked_up = true;
+                return 4;
+            }
+        };
+
         log::trace!("Executing instruction: {}", instruction.description);
 
         self.execute_instruction(mmu, instruction, &op_code)
@@ -95,7 +136,6 @@ impl Cpu {
         if self.ime {
             match handle_interrupts(self, mmu) {
                 Some(cycles) => {
-                    self.ime = false;
                     return cycles;
                 }
                 _ => {
@@ -132,25 +172,53 @@ impl Cpu {
                 }
             },
             _ => {
-                self.reg.pc += instruction.length;
+                self.reg.pc = self.reg.pc.wrapping_add(instruction.length);
                 return instruction.clock_cycles;
             }
         }
     }
 
-    /// Read an opcode from memory
-    fn read_opcode(&mut self, mmu: &Memory) -> OpCode {
-        let opcode = mmu.get_byte(self.reg.pc);
+    /// Fetch an opcode from memory, taking one M-cycle per byte
+    fn read_opcode(&mut self, mmu: &mut Memory) -> OpCode {
+        let opcode = mmu.fetch_cycle(self.reg.pc);
         match opcode {
-            0xCB => OpCode::CB(mmu.get_byte(self.reg.pc + 1)),
+            0xCB => OpCode::CB(mmu.read_cycle(self.reg.pc.wrapping_add(1))),
             _ => OpCode::Regular(opcode),
         }
     }
 }
 
+impl Savestate for Cpu {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.reg.save_state(state);
+        state.write_bool(self.ime);
+        state.write_bool(self.ei);
+        state.write_bool(self.halted);
+        state.write_bool(self.halt_bug);
+        state.write_bool(self.stopped);
+        state.write_bool(self.locked_up);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.reg.load_state(state)?;
+        self.ime = state.read_bool()?;
+        self.ei = state.read_bool()?;
+        self.halted = state.read_bool()?;
+        self.halt_bug = state.read_bool()?;
+        self.stopped = state.read_bool()?;
+        self.locked_up = state.read_bool()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::registers::Flag;
+    use crate::io::joypad::{Key, JOYPAD_ADDR};
+    use crate::io::timer::{DIV_ADDR, TAC_ADDR};
+    use crate::io::IoDevice;
 
     #[test]
     fn test_new() {
@@ -168,13 +236,38 @@ mod tests {
         assert_eq!(cpu.ime, false)
     }
 
+    #[test]
+    fn test_new_with_model_cgb() {
+        let cpu = Cpu::new_with_model(Model::Cgb);
+        assert_eq!(cpu.reg.a, 0x11);
+        assert_eq!(cpu.reg.f, 0x80);
+        assert_eq!(cpu.reg.de(), 0xFF56);
+        assert_eq!(cpu.reg.hl(), 0x000D);
+        assert_eq!(cpu.reg.sp, 0xFFFE);
+        assert_eq!(cpu.reg.pc, 0x100);
+    }
+
+    #[test]
+    fn test_new_with_model_agb() {
+        let cpu = Cpu::new_with_model(Model::Agb);
+        assert_eq!(cpu.reg.a, 0x11);
+        assert_eq!(cpu.reg.b, 0x01);
+    }
+
+    #[test]
+    fn test_new_with_model_mgb() {
+        let cpu = Cpu::new_with_model(Model::Mgb);
+        assert_eq!(cpu.reg.a, 0xFF);
+        assert_eq!(cpu.reg.bc(), 0x0013);
+    }
+
     #[test]
     fn test_read_opcode() {
         let mut mmu = Memory::new();
         let mut cpu = Cpu::new();
         cpu.reg.pc = 0xC000;
         mmu.set_byte(0xC000 as usize, 0x00 as u8);
-        assert_eq!(cpu.read_opcode(&mmu), OpCode::Regular(0));
+        assert_eq!(cpu.read_opcode(&mut mmu), OpCode::Regular(0));
     }
 
     #[test]
@@ -184,7 +277,8 @@ mod tests {
         cpu.reg.pc = 0xC000;
         mmu.set_byte(0xC000 as usize, 0xCB);
         mmu.set_byte(0xC001 as usize, 0x00);
-        assert_eq!(cpu.read_opcode(&mmu), OpCode::CB(0));
+        assert_eq!(cpu.read_opcode(&mut mmu), OpCode::CB(0));
+        assert_eq!(mmu.clock_cycles(), 8);
     }
 
     #[test]
@@ -248,12 +342,142 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Unimplemented instruction! 0xD3 PC: 0xC000")]
-    fn test_step_unimplemented_instruction() {
+    fn test_step_memory_timing() {
+        let mut mmu = Memory::new();
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xC000;
+        cpu.reg.sp = 0xD000;
+        cpu.reg.set_bc(0x1234);
+        mmu.set_byte(0xC000 as usize, 0xC5); // PUSH BC
+        mmu.timer.write(DIV_ADDR, 0x00);
+        mmu.timer.write(TAC_ADDR, 0x05);
+        assert_eq!(cpu.step(&mut mmu), 16);
+        assert_eq!(mmu.clock_cycles(), 16);
+        assert_eq!(mmu.timer.counter, 0x01);
+        assert_eq!(mmu.get_word(0xCFFE as usize), 0x1234);
+    }
+
+    #[test]
+    fn test_step_stop() {
+        let mut mmu = Memory::new();
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xC000;
+        mmu.set_byte(0xC000 as usize, 0x10); // STOP
+        mmu.set_byte(0xC002 as usize, 0x00); // NOP
+        mmu.set_byte(JOYPAD_ADDR, 0x10);
+        cpu.step(&mut mmu);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.reg.pc, 0xC002);
+        assert_eq!(mmu.get_byte(DIV_ADDR), 0x00);
+
+        let clock_cycles = mmu.clock_cycles();
+        cpu.step(&mut mmu);
+        assert!(cpu.stopped);
+        assert_eq!(mmu.clock_cycles(), clock_cycles);
+
+        mmu.joypad.push_key(Key::Start);
+        cpu.step(&mut mmu);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.reg.pc, 0xC003);
+    }
+
+    #[test]
+    fn test_step_stop_speed_switch() {
+        let mut mmu = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        let mut cpu = Cpu::new_with_model(Model::Cgb);
+        cpu.reg.pc = 0xC000;
+        mmu.set_byte(0xC000 as usize, 0x10); // STOP
+        mmu.set_byte(0xFF4D as usize, 0x01);
+        cpu.step(&mut mmu);
+        assert!(!cpu.stopped);
+        assert!(mmu.double_speed());
+        assert_eq!(mmu.get_byte(0xFF4D as usize), 0xFE);
+    }
+
+    #[test]
+    fn test_step_halt_wake_latency() {
+        let mut mmu = Memory::new();
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xC000;
+        cpu.ime = true;
+        mmu.set_byte(0xC000 as usize, 0x76); // HALT
+        mmu.interrupts.requested_interrupts = 0x00;
+        mmu.interrupts.enabled_interrupts = 0x01;
+        cpu.step(&mut mmu);
+        assert!(cpu.halted);
+        assert_eq!(cpu.step(&mut mmu), 4);
+
+        mmu.interrupts.requested_interrupts = 0x01;
+        assert_eq!(cpu.step(&mut mmu), 4);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.reg.pc, 0xC001);
+        assert_eq!(cpu.step(&mut mmu), 20);
+        assert_eq!(cpu.reg.pc, 0x0040);
+    }
+
+    #[test]
+    fn test_step_halt_bug() {
+        let mut mmu = Memory::new();
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xC000;
+        cpu.reg.d = 0x00;
+        mmu.set_byte(0xC000 as usize, 0x76); // HALT
+        mmu.set_byte(0xC001 as usize, 0x3E); // LD A, n
+        mmu.set_byte(0xC002 as usize, 0x14); // INC D
+        mmu.interrupts.requested_interrupts = 0x01;
+        mmu.interrupts.enabled_interrupts = 0x01;
+        cpu.step(&mut mmu);
+        assert!(!cpu.halted);
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.reg.a, 0x3E);
+        assert_eq!(cpu.reg.pc, 0xC002);
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.reg.d, 0x01);
+    }
+
+    #[test]
+    fn test_step_conditional_timing() {
+        let mut mmu = Memory::new();
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xC000;
+        mmu.set_byte(0xC000 as usize, 0xC0); // RET NZ
+        cpu.reg.set_flag(Flag::Zero);
+        assert_eq!(cpu.step(&mut mmu), 8);
+        assert_eq!(mmu.clock_cycles(), 8);
+    }
+
+    #[test]
+    fn test_step_pc_wraps() {
+        let mut mmu = Memory::new();
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xFFFF;
+        mmu.set_byte(0xFFFF as usize, 0x00); // NOP in IE
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.reg.pc, 0x0000);
+
+        cpu.reg.pc = 0xFFFE;
+        mmu.set_byte(0xFFFE as usize, 0x18); // JR n
+        mmu.set_byte(0xFFFF as usize, 0x02);
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.reg.pc, 0x0002);
+    }
+
+    #[test]
+    fn test_step_illegal_instruction() {
         let mut mmu = Memory::new();
         let mut cpu = Cpu::new();
         cpu.reg.pc = 0xC000;
         mmu.set_byte(0xC000 as usize, 0xD3 as u8);
         cpu.step(&mut mmu);
+        assert!(cpu.locked_up);
+        assert_eq!(cpu.reg.pc, 0xC000);
+
+        // Interrupts don't get the CPU out of a lock up
+        cpu.ime = true;
+        mmu.interrupts.enabled_interrupts = 0xFF;
+        mmu.interrupts.requested_interrupts = 0xFF;
+        assert_eq!(cpu.step(&mut mmu), 4);
+        assert!(cpu.locked_up);
+        assert_eq!(cpu.reg.pc, 0xC000);
     }
 }
@@ -83,7 +83,7 @@ const RLC_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::rlc(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -181,7 +181,7 @@ const RRC_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::rrc(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -279,7 +279,7 @@ const RL_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::rl(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -377,7 +377,7 @@ const RR_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::rr(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -475,7 +475,7 @@ const SLA_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::sla(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -573,7 +573,7 @@ const SRA_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::sra(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -671,7 +671,7 @@ const SWAP_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::swap(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -769,7 +769,7 @@ const SRL_HL: Instruction = Instruction {
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let value = functions::get_hl(cpu, mmu);
         let result = functions::srl(cpu, value);
-        mmu.set_byte(cpu.reg.hl(), result);
+        mmu.write_cycle(cpu.reg.hl(), result);
         InstructionType::ActionTaken
     },
 };
@@ -1634,8 +1634,8 @@ const RES_0_HL: Instruction = Instruction {
     description: "RES 0 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 0));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 0));
         InstructionType::ActionTaken
     },
 };
@@ -1732,8 +1732,8 @@ const RES_1_HL: Instruction = Instruction {
     description: "RES 1 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 1));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 1));
         InstructionType::ActionTaken
     },
 };
@@ -1830,8 +1830,8 @@ const RES_2_HL: Instruction = Instruction {
     description: "RES 2 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 2));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 2));
         InstructionType::ActionTaken
     },
 };
@@ -1928,8 +1928,8 @@ const RES_3_HL: Instruction = Instruction {
     description: "RES 3 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 3));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 3));
         InstructionType::ActionTaken
     },
 };
@@ -2026,8 +2026,8 @@ const RES_4_HL: Instruction = Instruction {
     description: "RES 4 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 4));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 4));
         InstructionType::ActionTaken
     },
 };
@@ -2124,8 +2124,8 @@ const RES_5_HL: Instruction = Instruction {
     description: "RES 5 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 5));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 5));
         InstructionType::ActionTaken
     },
 };
@@ -2222,8 +2222,8 @@ const RES_6_HL: Instruction = Instruction {
     description: "RES 6 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 6));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 6));
         InstructionType::ActionTaken
     },
 };
@@ -2320,8 +2320,8 @@ const RES_7_HL: Instruction = Instruction {
     description: "RES 7 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::res(value, 7));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::res(value, 7));
         InstructionType::ActionTaken
     },
 };
@@ -2418,8 +2418,8 @@ const SET_0_HL: Instruction = Instruction {
     description: "SET 0 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 0));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 0));
         InstructionType::ActionTaken
     },
 };
@@ -2516,8 +2516,8 @@ const SET_1_HL: Instruction = Instruction {
     description: "SET 1 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 1));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 1));
         InstructionType::ActionTaken
     },
 };
@@ -2614,8 +2614,8 @@ const SET_2_HL: Instruction = Instruction {
     description: "SET 2 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 2));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 2));
         InstructionType::ActionTaken
     },
 };
@@ -2712,8 +2712,8 @@ const SET_3_HL: Instruction = Instruction {
     description: "SET 3 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 3));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 3));
         InstructionType::ActionTaken
     },
 };
@@ -2810,8 +2810,8 @@ const SET_4_HL: Instruction = Instruction {
     description: "SET 4 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 4));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 4));
         InstructionType::ActionTaken
     },
 };
@@ -2908,8 +2908,8 @@ const SET_5_HL: Instruction = Instruction {
     description: "SET 5 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 5));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 5));
         InstructionType::ActionTaken
     },
 };
@@ -3006,8 +3006,8 @@ const SET_6_HL: Instruction = Instruction {
     description: "SET 6 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 6));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 6));
         InstructionType::ActionTaken
     },
 };
@@ -3104,8 +3104,8 @@ const SET_7_HL: Instruction = Instruction {
     description: "SET 7 (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let hl = cpu.reg.hl();
-        let value = mmu.get_byte(hl);
-        mmu.set_byte(hl, functions::set(value, 7));
+        let value = mmu.read_cycle(hl);
+        mmu.write_cycle(hl, functions::set(value, 7));
         InstructionType::ActionTaken
     },
 };
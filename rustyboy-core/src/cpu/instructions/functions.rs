@@ -2,37 +2,45 @@ use crate::cpu::cpu::Cpu;
 use crate::cpu::registers::Flag;
 use crate::mmu::Memory;
 
-/// Get the n'th 8 bit instruction operand
-pub fn get_op8(cpu: &Cpu, mmu: &Memory, n: u8) -> u8 {
-    mmu.get_byte(cpu.reg.pc + n as u16)
+/// Read the n'th 8 bit instruction operand, taking one M-cycle
+pub fn get_op8(cpu: &Cpu, mmu: &mut Memory, n: u8) -> u8 {
+    mmu.read_cycle(cpu.reg.pc.wrapping_add(n as u16))
 }
 
-/// Get a 16 bit instruction operand
-pub fn get_op16(cpu: &Cpu, mmu: &Memory) -> u16 {
-    mmu.get_word(cpu.reg.pc + 1 as u16)
+/// Read a 16 bit instruction operand, taking two M-cycles
+pub fn get_op16(cpu: &Cpu, mmu: &mut Memory) -> u16 {
+    let l = get_op8(cpu, mmu, 1);
+    let h = get_op8(cpu, mmu, 2);
+    ((h as u16) << 8) | (l as u16)
 }
 
-/// Get the value in memory pointed to by HL
-pub fn get_hl(cpu: &Cpu, mmu: &Memory) -> u8 {
-    mmu.get_byte(cpu.reg.hl())
+/// Read the value in memory pointed to by HL, taking one M-cycle
+pub fn get_hl(cpu: &Cpu, mmu: &mut Memory) -> u8 {
+    mmu.read_cycle(cpu.reg.hl())
 }
 
-/// Set the value in memory pointed to by HL
+/// Write the value in memory pointed to by HL, taking one M-cycle
 pub fn set_hl(cpu: &mut Cpu, mmu: &mut Memory, value: u8) {
-    mmu.set_byte(cpu.reg.hl(), value);
+    mmu.write_cycle(cpu.reg.hl(), value);
 }
 
 /// Push a value to the stack
+/// Takes an internal M-cycle to decrement SP, then writes the high byte before the low byte
 pub fn push(cpu: &mut Cpu, mmu: &mut Memory, value: u16) {
-    cpu.reg.sp = cpu.reg.sp.wrapping_sub(2);
-    mmu.set_word(cpu.reg.sp, value);
+    mmu.tick();
+    cpu.reg.sp = cpu.reg.sp.wrapping_sub(1);
+    mmu.write_cycle(cpu.reg.sp, (value >> 8) as u8);
+    cpu.reg.sp = cpu.reg.sp.wrapping_sub(1);
+    mmu.write_cycle(cpu.reg.sp, value as u8);
 }
 
-/// Pop a value from the stack
+/// Pop a value from the stack, taking two M-cycles
 pub fn pop(cpu: &mut Cpu, mmu: &mut Memory) -> u16 {
-    let value = mmu.get_word(cpu.reg.sp);
-    cpu.reg.sp = cpu.reg.sp.wrapping_add(2);
-    value
+    let l = mmu.read_cycle(cpu.reg.sp);
+    cpu.reg.sp = cpu.reg.sp.wrapping_add(1);
+    let h = mmu.read_cycle(cpu.reg.sp);
+    cpu.reg.sp = cpu.reg.sp.wrapping_add(1);
+    ((h as u16) << 8) | (l as u16)
 }
 
 /// Call a subroutine.
@@ -40,13 +48,13 @@ pub fn pop(cpu: &mut Cpu, mmu: &mut Memory) -> u16 {
 /// Sets the program counter to the address of the subroutine.
 pub fn call(cpu: &mut Cpu, mmu: &mut Memory) {
     let addr = get_op16(cpu, mmu);
-    push(cpu, mmu, cpu.reg.pc + 3);
+    push(cpu, mmu, cpu.reg.pc.wrapping_add(3));
     cpu.reg.pc = addr;
 }
 
 /// Similar to call but to a specifc vector.
 pub fn rst(cpu: &mut Cpu, mmu: &mut Memory, vec: u16) {
-    push(cpu, mmu, cpu.reg.pc + 1);
+    push(cpu, mmu, cpu.reg.pc.wrapping_add(1));
     cpu.reg.pc = vec;
 }
 
@@ -54,7 +62,7 @@ pub fn rst(cpu: &mut Cpu, mmu: &mut Memory, vec: u16) {
 /// The operand is treated as a signed byte, added to the memory address of the next instruction.
 pub fn jr(cpu: &mut Cpu, mmu: &mut Memory) {
     let op = get_op8(cpu, mmu, 1) as i8;
-    cpu.reg.pc = ((cpu.reg.pc + 2) as i16 + op as i16) as u16;
+    cpu.reg.pc = cpu.reg.pc.wrapping_add(2).wrapping_add_signed(op as i16);
 }
 
 /// Add a byte to the A register with the carry flag
@@ -475,7 +483,8 @@ mod tests {
         let mut mmu = Memory::new();
         cpu.reg.pc = 0xC000;
         mmu.set_byte(0xC001 as usize, 0x01);
-        assert_eq!(get_op8(&cpu, &mmu, 1), 0x01);
+        assert_eq!(get_op8(&cpu, &mut mmu, 1), 0x01);
+        assert_eq!(mmu.clock_cycles(), 4);
     }
 
     #[test]
@@ -484,7 +493,8 @@ mod tests {
         let mut mmu = Memory::new();
         cpu.reg.pc = 0xC000;
         mmu.set_word(0xC001 as usize, 0x0001);
-        assert_eq!(get_op16(&cpu, &mmu), 0x0001);
+        assert_eq!(get_op16(&cpu, &mut mmu), 0x0001);
+        assert_eq!(mmu.clock_cycles(), 8);
     }
 
     #[test]
@@ -494,7 +504,7 @@ mod tests {
         cpu.reg.h = 0xC0;
         cpu.reg.l = 0x00;
         mmu.set_byte(0xC000 as usize, 0x01);
-        assert_eq!(get_hl(&cpu, &mmu), 0x01);
+        assert_eq!(get_hl(&cpu, &mut mmu), 0x01);
     }
 
     #[test]
@@ -512,9 +522,11 @@ mod tests {
         let mut cpu = Cpu::new();
         let mut mmu = Memory::new();
         cpu.reg.sp = 0xFFFE;
-        push(&mut cpu, &mut mmu, 0x0001);
+        push(&mut cpu, &mut mmu, 0x0201);
         assert_eq!(cpu.reg.sp, 0xFFFC);
-        assert_eq!(mmu.get_byte(0xFFFC as usize), 0x0001);
+        assert_eq!(mmu.get_byte(0xFFFC as usize), 0x01);
+        assert_eq!(mmu.get_byte(0xFFFD as usize), 0x02);
+        assert_eq!(mmu.clock_cycles(), 12);
     }
 
     #[test]
@@ -1,5 +1,6 @@
 use crate::cpu::cpu::Cpu;
 use crate::cpu::instructions::{functions, Instruction, InstructionType, OpCode};
+use crate::cpu::interrupts::pending_interrupt;
 use crate::cpu::registers::Flag;
 use crate::mmu::Memory;
 
@@ -31,7 +32,7 @@ const LD_BC_A: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD (BC) A",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        mmu.set_byte(cpu.reg.bc(), cpu.reg.a);
+        mmu.write_cycle(cpu.reg.bc(), cpu.reg.a);
         InstructionType::ActionTaken
     },
 };
@@ -106,7 +107,8 @@ const LD_NN_SP: Instruction = Instruction {
     description: "LD (nn) SP",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         let address = functions::get_op16(cpu, mmu);
-        mmu.set_word(address, cpu.reg.sp);
+        mmu.write_cycle(address, cpu.reg.sp as u8);
+        mmu.write_cycle(address.wrapping_add(1), (cpu.reg.sp >> 8) as u8);
         InstructionType::ActionTaken
     },
 };
@@ -130,7 +132,7 @@ const LD_A_BC: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD A (BC)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(cpu.reg.bc());
+        cpu.reg.a = mmu.read_cycle(cpu.reg.bc());
         InstructionType::ActionTaken
     },
 };
@@ -197,6 +199,25 @@ const RRCA: Instruction = Instruction {
     },
 };
 
+/// 0x10 - STOP
+const STOP: Instruction = Instruction {
+    length: 2,
+    clock_cycles: 4,
+    clock_cycles_condition: None,
+    description: "STOP",
+    handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
+        if mmu.speed_switch_armed() {
+            // On the CGB STOP is also used to switch between normal and double speed
+            mmu.switch_speed();
+        } else {
+            log::trace!("STOP");
+            cpu.stopped = true;
+            mmu.stop();
+        }
+        InstructionType::ActionTaken
+    },
+};
+
 /// 0x11 - LD DE, nn
 const LD_DE_NN: Instruction = Instruction {
     length: 3,
@@ -216,7 +237,7 @@ const LD_DE_A: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD (DE) A",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        mmu.set_byte(cpu.reg.de(), cpu.reg.a);
+        mmu.write_cycle(cpu.reg.de(), cpu.reg.a);
         InstructionType::ActionTaken
     },
 };
@@ -314,7 +335,7 @@ const LD_A_DE: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD A (DE)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(cpu.reg.de());
+        cpu.reg.a = mmu.read_cycle(cpu.reg.de());
         InstructionType::ActionTaken
     },
 };
@@ -515,7 +536,7 @@ const LD_A_HL_INC: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD A (HL+)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(cpu.reg.hl());
+        cpu.reg.a = mmu.read_cycle(cpu.reg.hl());
         cpu.reg.set_hl(cpu.reg.hl().wrapping_add(1));
         InstructionType::ActionTaken
     },
@@ -642,8 +663,8 @@ const INC_HL_PTR: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "INC (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        let value = functions::inc(cpu, mmu.get_byte(cpu.reg.hl()));
-        mmu.set_byte(cpu.reg.hl(), value);
+        let value = functions::inc(cpu, mmu.read_cycle(cpu.reg.hl()));
+        mmu.write_cycle(cpu.reg.hl(), value);
         InstructionType::ActionTaken
     },
 };
@@ -655,8 +676,8 @@ const DEC_HL_PTR: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "DEC (HL)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        let value = functions::dec(cpu, mmu.get_byte(cpu.reg.hl()));
-        mmu.set_byte(cpu.reg.hl(), value);
+        let value = functions::dec(cpu, mmu.read_cycle(cpu.reg.hl()));
+        mmu.write_cycle(cpu.reg.hl(), value);
         InstructionType::ActionTaken
     },
 };
@@ -668,7 +689,8 @@ const LD_HL_N: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD (HL) n",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        mmu.set_byte(cpu.reg.hl(), functions::get_op8(cpu, mmu, 1));
+        let value = functions::get_op8(cpu, mmu, 1);
+        mmu.write_cycle(cpu.reg.hl(), value);
         InstructionType::ActionTaken
     },
 };
@@ -721,7 +743,7 @@ const LD_A_HL_DEC: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD A (HL-)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(cpu.reg.hl());
+        cpu.reg.a = mmu.read_cycle(cpu.reg.hl());
         cpu.reg.set_hl(cpu.reg.hl().wrapping_sub(1));
         InstructionType::ActionTaken
     },
@@ -1447,9 +1469,14 @@ const HALT: Instruction = Instruction {
     clock_cycles: 4,
     clock_cycles_condition: None,
     description: "HALT",
-    handler: |cpu: &mut Cpu, _: &mut Memory, _: &OpCode| {
+    handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
         log::trace!("HALT");
-        cpu.halted = true;
+        if !cpu.ime && pending_interrupt(mmu) {
+            // HALT bug, HALT ends immediately and the next byte is read twice
+            cpu.halt_bug = true;
+        } else {
+            cpu.halted = true;
+        }
         InstructionType::ActionTaken
     },
 };
@@ -2337,6 +2364,8 @@ const RET_NZ: Instruction = Instruction {
     clock_cycles_condition: Some(20),
     description: "RET NZ",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
+        // Checking the condition takes an extra M-cycle
+        mmu.tick();
         if !cpu.reg.check_flag(Flag::Zero) {
             cpu.reg.pc = functions::pop(cpu, mmu);
             return InstructionType::Jumped;
@@ -2443,6 +2472,8 @@ const RET_Z: Instruction = Instruction {
     clock_cycles_condition: Some(20),
     description: "RET Z",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
+        // Checking the condition takes an extra M-cycle
+        mmu.tick();
         if cpu.reg.check_flag(Flag::Zero) {
             cpu.reg.pc = functions::pop(cpu, mmu);
             return InstructionType::Jumped;
@@ -2536,6 +2567,8 @@ const RET_NC: Instruction = Instruction {
     clock_cycles_condition: Some(20),
     description: "RET NC",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
+        // Checking the condition takes an extra M-cycle
+        mmu.tick();
         if !cpu.reg.check_flag(Flag::Carry) {
             cpu.reg.pc = functions::pop(cpu, mmu);
             return InstructionType::Jumped;
@@ -2630,6 +2663,8 @@ const RET_C: Instruction = Instruction {
     clock_cycles_condition: Some(20),
     description: "RET C",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
+        // Checking the condition takes an extra M-cycle
+        mmu.tick();
         if cpu.reg.check_flag(Flag::Carry) {
             cpu.reg.pc = functions::pop(cpu, mmu);
             return InstructionType::Jumped;
@@ -2712,7 +2747,8 @@ const LDH_N_A: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LDH (n) A",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        mmu.set_byte(0xFF00 + functions::get_op8(cpu, mmu, 1) as u16, cpu.reg.a);
+        let offset = functions::get_op8(cpu, mmu, 1);
+        mmu.write_cycle(0xFF00 + offset as u16, cpu.reg.a);
         InstructionType::ActionTaken
     },
 };
@@ -2737,7 +2773,7 @@ const LDH_C_A: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LDH (C) A",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        mmu.set_byte(0xFF00 + cpu.reg.c as u16, cpu.reg.a);
+        mmu.write_cycle(0xFF00 + cpu.reg.c as u16, cpu.reg.a);
         InstructionType::ActionTaken
     },
 };
@@ -2809,7 +2845,8 @@ const LD_NN_A: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD (nn) A",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        mmu.set_byte(functions::get_op16(cpu, mmu), cpu.reg.a);
+        let address = functions::get_op16(cpu, mmu);
+        mmu.write_cycle(address, cpu.reg.a);
         InstructionType::ActionTaken
     },
 };
@@ -2845,7 +2882,8 @@ const LDH_A_N: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LDH A (n)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(0xFF00 + functions::get_op8(cpu, mmu, 1) as u16);
+        let offset = functions::get_op8(cpu, mmu, 1);
+        cpu.reg.a = mmu.read_cycle(0xFF00 + offset as u16);
         InstructionType::ActionTaken
     },
 };
@@ -2870,7 +2908,7 @@ const LDH_A_C: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LDH A (C)",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(0xFF00 + cpu.reg.c as u16);
+        cpu.reg.a = mmu.read_cycle(0xFF00 + cpu.reg.c as u16);
         InstructionType::ActionTaken
     },
 };
@@ -2956,7 +2994,8 @@ const LD_A_NN: Instruction = Instruction {
     clock_cycles_condition: None,
     description: "LD A [nn]",
     handler: |cpu: &mut Cpu, mmu: &mut Memory, _: &OpCode| {
-        cpu.reg.a = mmu.get_byte(functions::get_op16(cpu, mmu));
+        let address = functions::get_op16(cpu, mmu);
+        cpu.reg.a = mmu.read_cycle(address);
         InstructionType::ActionTaken
     },
 };
@@ -3017,10 +3056,7 @@ pub fn get_instruction(op_code: &u8) -> Option<&Instruction> {
         0x0E => Some(&LD_C_N),
         0x0F => Some(&RRCA),
 
-        0x10 => {
-            log::warn!("STOP instruction called");
-            return Some(&NOP);
-        }
+        0x10 => Some(&STOP),
         0x11 => Some(&LD_DE_NN),
         0x12 => Some(&LD_DE_A),
         0x13 => Some(&INC_DE),
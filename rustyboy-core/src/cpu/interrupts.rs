@@ -1,5 +1,7 @@
 use crate::cpu::cpu::Cpu;
+use crate::io::IoDevice;
 use crate::mmu::Memory;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 pub const INTERRUPT_ENABLE_ADDR: usize = 0xFFFF;
 pub const INTERRUPT_FLAG_ADDR: usize = 0xFF0F;
@@ -10,6 +12,11 @@ const TIMER_ISR: u16 = 0x0050;
 const SERIAL_ISR: u16 = 0x0058;
 const JOYPAD_ISR: u16 = 0x0060;
 
+/// The top three bits of IF are unused and always read as 1
+const INTERRUPT_FLAG_UNUSED: u8 = 0xE0;
+/// The bits of IF and IE that correspond to an interrupt
+const INTERRUPT_MASK: u8 = 0x1F;
+
 /// The types of interrupts the Gameboy can handle
 #[derive(Copy, Clone, Debug)]
 pub enum Interrupt {
@@ -48,53 +55,92 @@ impl InterruptState {
     }
 }
 
+impl IoDevice for InterruptState {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            INTERRUPT_FLAG_ADDR => self.requested_interrupts | INTERRUPT_FLAG_UNUSED,
+            INTERRUPT_ENABLE_ADDR => self.enabled_interrupts,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: usize, v: u8) {
+        match addr {
+            INTERRUPT_FLAG_ADDR => self.requested_interrupts = v | INTERRUPT_FLAG_UNUSED,
+            INTERRUPT_ENABLE_ADDR => self.enabled_interrupts = v,
+            _ => {}
+        }
+    }
+}
+
 /// Check if there are any pending interrupts
 pub fn pending_interrupt(mmu: &Memory) -> bool {
     let interrupt_flag = mmu.get_byte(INTERRUPT_FLAG_ADDR);
     let interrupt_enable = mmu.get_byte(INTERRUPT_ENABLE_ADDR);
 
-    (interrupt_flag & interrupt_enable) != 0
+    // The unused bits of IF always read as 1, so they must not count as pending interrupts
+    (interrupt_flag & interrupt_enable & INTERRUPT_MASK) != 0
 }
 
-/// Handle all interrupts
+/// The interrupts in order of priority, with the address of their handler
+const INTERRUPT_VECTORS: [(Interrupt, u16); 5] = [
+    (Interrupt::VBlank, VBLANK_ISR),
+    (Interrupt::LcdStat, LCD_STAT_ISR),
+    (Interrupt::Timer, TIMER_ISR),
+    (Interrupt::Serial, SERIAL_ISR),
+    (Interrupt::Joypad, JOYPAD_ISR),
+];
+
+/// Dispatch the highest priority pending interrupt
+/// Returns the number of cycles used if an interrupt was dispatched
 pub fn handle_interrupts(cpu: &mut Cpu, mmu: &mut Memory) -> Option<u8> {
-    if handle_interrupt(cpu, mmu, &Interrupt::VBlank, VBLANK_ISR) {
-        return Some(12);
-    }
-
-    if handle_interrupt(cpu, mmu, &Interrupt::LcdStat, LCD_STAT_ISR) {
-        return Some(12);
-    }
-
-    if handle_interrupt(cpu, mmu, &Interrupt::Timer, TIMER_ISR) {
-        return Some(12);
-    }
-
-    if handle_interrupt(cpu, mmu, &Interrupt::Joypad, JOYPAD_ISR) {
-        return Some(12);
+    if !pending_interrupt(mmu) {
+        return None;
     }
 
-    if handle_interrupt(cpu, mmu, &Interrupt::Serial, SERIAL_ISR) {
-        return Some(12);
-    }
-
-    return None;
+    cpu.ime = false;
+    // Dispatch starts with two internal M-cycles
+    mmu.tick();
+    mmu.tick();
+    cpu.reg.sp = cpu.reg.sp.wrapping_sub(1);
+    mmu.write_cycle(cpu.reg.sp, (cpu.reg.pc >> 8) as u8);
+
+    // The interrupt is only picked after the high byte of PC is pushed, if that push
+    // overwrote IE and disabled the pending interrupts the dispatch jumps to 0x0000 instead
+    let vector = INTERRUPT_VECTORS
+        .iter()
+        .find(|(interrupt, _)| mmu.interrupts.interrupt_fired(interrupt));
+
+    cpu.reg.sp = cpu.reg.sp.wrapping_sub(1);
+    mmu.write_cycle(cpu.reg.sp, cpu.reg.pc as u8);
+
+    cpu.reg.pc = match vector {
+        Some((interrupt, isr_addr)) => {
+            log::trace!("Handling {:?} interrupt", interrupt);
+            mmu.interrupts.clear_interrupt(interrupt);
+            *isr_addr
+        }
+        None => {
+            log::trace!("Interrupt dispatch cancelled");
+            0x0000
+        }
+    };
+    // Setting PC takes the last M-cycle
+    mmu.tick();
+    Some(20)
 }
 
-/// Handle a specific interrupt
-fn handle_interrupt(cpu: &mut Cpu, mmu: &mut Memory, interrupt: &Interrupt, isr_addr: u16) -> bool {
-    if !mmu.interrupts.interrupt_fired(interrupt) {
-        return false;
+impl Savestate for InterruptState {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.enabled_interrupts);
+        state.write_u8(self.requested_interrupts);
     }
 
-    log::trace!("Handling {:?} interrupt", interrupt);
-
-    cpu.ime = false;
-    cpu.reg.sp -= 2;
-    mmu.set_word(cpu.reg.sp, cpu.reg.pc);
-    cpu.reg.pc = isr_addr;
-    mmu.interrupts.clear_interrupt(interrupt);
-    true
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled_interrupts = state.read_u8()?;
+        self.requested_interrupts = state.read_u8()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -132,17 +178,61 @@ mod tests {
         assert_eq!(interrupt_state.interrupt_fired(&Interrupt::VBlank), false);
     }
 
+    #[test]
+    fn test_write_interrupt_flag() {
+        let mut interrupt_state = InterruptState::new();
+        interrupt_state.write(INTERRUPT_FLAG_ADDR, 0x00);
+        assert_eq!(interrupt_state.read(INTERRUPT_FLAG_ADDR), 0xE0);
+        interrupt_state.write(INTERRUPT_ENABLE_ADDR, 0xFF);
+        assert_eq!(interrupt_state.read(INTERRUPT_ENABLE_ADDR), 0xFF);
+    }
+
+    #[test]
+    fn test_pending_interrupt_unused_bits() {
+        let mut mmu = Memory::new();
+        mmu.interrupts.requested_interrupts = 0xE0;
+        mmu.interrupts.enabled_interrupts = 0xFF;
+        assert!(!pending_interrupt(&mmu));
+    }
+
     #[test]
     fn test_handle_interrupts() {
         let mut cpu = Cpu::new();
         let mut mmu = Memory::new();
         mmu.interrupts.requested_interrupts = 0xFF;
         mmu.interrupts.enabled_interrupts = 0xFF;
-        handle_interrupts(&mut cpu, &mut mmu);
+        assert_eq!(handle_interrupts(&mut cpu, &mut mmu), Some(20));
         assert_eq!(cpu.ime, false);
         assert_eq!(cpu.reg.sp, 0xFFFC);
         assert_eq!(cpu.reg.pc, 0x0040);
         assert_eq!(mmu.interrupts.requested_interrupts, 0xFE);
+        assert_eq!(mmu.clock_cycles(), 20);
+    }
+
+    #[test]
+    fn test_handle_interrupts_priority() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Memory::new();
+        mmu.interrupts.requested_interrupts = 0x18;
+        mmu.interrupts.enabled_interrupts = 0xFF;
+        handle_interrupts(&mut cpu, &mut mmu);
+        assert_eq!(cpu.reg.pc, 0x0058);
+        assert_eq!(mmu.interrupts.requested_interrupts, 0x10);
+    }
+
+    #[test]
+    fn test_handle_interrupts_ie_push() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Memory::new();
+        cpu.reg.sp = 0x0000;
+        cpu.reg.pc = 0x0200;
+        mmu.interrupts.requested_interrupts = 0x01;
+        mmu.interrupts.enabled_interrupts = 0x01;
+        assert_eq!(handle_interrupts(&mut cpu, &mut mmu), Some(20));
+        // Pushing the high byte of PC to 0xFFFF cleared IE
+        assert_eq!(mmu.interrupts.enabled_interrupts, 0x02);
+        assert_eq!(cpu.reg.pc, 0x0000);
+        assert_eq!(mmu.interrupts.requested_interrupts, 0x01);
     }
 
     #[test]
@@ -1,4 +1,4 @@
 pub mod cpu;
 mod instructions;
 pub mod interrupts;
-mod registers;
+pub mod registers;
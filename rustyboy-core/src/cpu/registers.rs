@@ -1,3 +1,5 @@
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
 /// The CPU flags
 pub enum Flag {
     /// Set when an arithmetic operation results in a zero value
@@ -111,6 +113,29 @@ impl Registers {
     }
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Savestate for Registers {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l]);
+        state.write_u16(self.pc);
+        state.write_u16(self.sp);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut bytes = [0; 8];
+        state.read_bytes(&mut bytes)?;
+        [self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l] = bytes;
+        self.pc = state.read_u16()?;
+        self.sp = state.read_u16()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
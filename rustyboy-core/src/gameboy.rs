@@ -1,7 +1,31 @@
+use crate::cartridge::{Cartridge, CartridgeError};
 use crate::cpu::cpu::Cpu;
+use crate::cpu::registers::Registers;
+use crate::hooks::Hit;
 use crate::mmu::Memory;
+use crate::model::Model;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 use crate::sysclock::{Clock, CYCLES_PER_FRAME};
 
+/// Errors that stop the emulation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmulationError {
+    /// The CPU locked up after executing an illegal opcode
+    IllegalOpcode { opcode: u8, pc: u16 },
+}
+
+impl std::fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalOpcode { opcode, pc } => {
+                write!(f, "CPU locked up on illegal opcode {:#04X} at {:#06X}", opcode, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmulationError {}
+
 pub struct Gameboy {
     pub mmu: Memory,
     cpu: Cpu,
@@ -10,32 +34,339 @@ pub struct Gameboy {
 
 impl Gameboy {
     /// Create a new Gameboy
-    pub fn new(rom_name: &str) -> Self {
-        let mmu = Memory::new_with_rom(rom_name);
-        let cpu: Cpu = Cpu::new();
+    /// The model is picked based on the cartridge header
+    pub fn new(rom_name: &str) -> Result<Self, CartridgeError> {
+        Self::new_with_model(rom_name, None)
+    }
+
+    /// Create a new Gameboy emulating a given model
+    /// If no model is given one is picked based on the cartridge header
+    pub fn new_with_model(rom_name: &str, model: Option<Model>) -> Result<Self, CartridgeError> {
+        Ok(Self::new_with_cartridge(Cartridge::new_from_rom(rom_name)?, model))
+    }
+
+    /// Create a new Gameboy with a loaded cartridge
+    /// If no model is given one is picked based on the cartridge header
+    pub fn new_with_cartridge(cart: Cartridge, model: Option<Model>) -> Self {
+        let model = model.unwrap_or_else(|| Model::detect(&cart));
+        log::info!("Emulating model {}", model);
+        let mmu = Memory::new_with_cartridge(cart, model);
+        let cpu: Cpu = Cpu::new_with_model(model);
         let clock = Clock::new();
         Self { cpu, mmu, clock }
     }
 
     /// Step through the emulation
-    pub fn step(&mut self) {
-        while self.clock.clock_cycles_passed < CYCLES_PER_FRAME {
-            let cycles = self.cpu.step(&mut self.mmu);
-            self.mmu.step(cycles);
-            self.clock.cycle(cycles);
+    /// Runs a single frame, stopping early if the CPU locks up
+    pub fn step(&mut self) -> Result<(), EmulationError> {
+        self.step_with_hooks(|_, _| {})
+    }
+
+    /// Run a single frame like `step`, calling `hook` after every instruction that accessed an
+    /// address hooked in `mmu.hooks`, once for every access
+    pub fn step_with_hooks<F: FnMut(&mut Gameboy, Hit)>(&mut self, mut hook: F) -> Result<(), EmulationError> {
+        self.mmu.apply_ram_cheats();
+        while !self.step_instruction(&mut hook)?.1 {}
+        self.check_locked_up()
+    }
+
+    /// Run for at least a number of clock cycles, stopping at the end of the instruction that
+    /// reaches it, or earlier if the CPU locks up
+    /// Frames carry on where `step` left off, so the two can be mixed
+    /// Returns the number of clock cycles that were run
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<u32, EmulationError> {
+        let mut cycles_run = 0;
+        while cycles_run < cycles {
+            let (instruction_cycles, frame_done) = self.step_instruction(&mut |_, _| {})?;
+            cycles_run += instruction_cycles as u32;
+            if frame_done {
+                self.mmu.apply_ram_cheats();
+            }
         }
+        Ok(cycles_run)
+    }
 
+    /// Run a single instruction, leaving the hooked accesses it made in `mmu.hooks` to be taken
+    /// Running instructions until one finishes the frame does the same as `step`, for callers that
+    /// need to borrow the Gameboy themselves between instructions
+    /// Returns whether the instruction finished the current frame
+    pub fn run_instruction(&mut self) -> Result<bool, EmulationError> {
+        if self.clock.clock_cycles_passed == 0 {
+            self.mmu.apply_ram_cheats();
+        }
+        let (_, frame_done) = self.execute_instruction()?;
+        if frame_done {
+            self.check_locked_up()?;
+        }
+        Ok(frame_done)
+    }
+
+    /// Run a single instruction, then pass the hooked accesses it made to `hook`
+    /// Returns the clock cycles it took and whether it finished the current frame
+    fn step_instruction<F: FnMut(&mut Gameboy, Hit)>(&mut self, hook: &mut F) -> Result<(u8, bool), EmulationError> {
+        let result = self.execute_instruction()?;
+        if self.mmu.hooks.has_hits() {
+            for hit in self.mmu.hooks.take_hits() {
+                hook(self, hit);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Run a single instruction, returning the clock cycles it took and whether it finished the
+    /// current frame
+    fn execute_instruction(&mut self) -> Result<(u8, bool), EmulationError> {
+        self.check_locked_up()?;
+        // The CPU ticks the rest of the system as it accesses memory
+        let cycles = self.cpu.step(&mut self.mmu);
+        // A frame takes a fixed amount of PPU cycles, which pass at half the CPU rate in double speed
+        self.clock.cycle(if self.mmu.double_speed() { cycles / 2 } else { cycles });
+        if self.clock.clock_cycles_passed < CYCLES_PER_FRAME {
+            return Ok((cycles, false));
+        }
         self.clock.reset();
+        Ok((cycles, true))
+    }
+
+    /// The CPU registers
+    pub fn registers(&self) -> &Registers {
+        &self.cpu.reg
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.cpu.reg
+    }
+
+    /// Save the state of the whole machine
+    /// Cheats, hooks and registered IO devices are left out, they belong to the frontend
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.mmu.model(), self.mmu.rom_checksum());
+        self.cpu.save_state(&mut state);
+        self.clock.save_state(&mut state);
+        self.mmu.save_state(&mut state);
+        state.finish()
+    }
+
+    /// Restore a state made by `save_state` with the same ROM and model
+    /// Nothing changes if the state can't be loaded
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        self.load_state_unchecked(bytes).inspect_err(|_| {
+            self.load_state_unchecked(&backup).expect("Failed to restore the state before loading");
+        })
+    }
+
+    fn load_state_unchecked(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(bytes, self.mmu.model(), self.mmu.rom_checksum())?;
+        self.cpu.load_state(&mut state)?;
+        self.clock.load_state(&mut state)?;
+        self.mmu.load_state(&mut state)?;
+        state.finish()
+    }
+
+    fn check_locked_up(&self) -> Result<(), EmulationError> {
+        if self.cpu.locked_up {
+            return Err(EmulationError::IllegalOpcode {
+                opcode: self.mmu.peek(self.cpu.reg.pc, None),
+                pc: self.cpu.reg.pc,
+            });
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hooks::Access;
 
     #[test]
     fn test_new() {
-        let gameboy = Gameboy::new("resources/test-rom.gb");
+        let gameboy = Gameboy::new("resources/test-rom.gb").unwrap();
         assert_eq!(gameboy.mmu.get_word(0x0100 as usize), 0xC300);
+        assert_eq!(gameboy.mmu.model(), Model::Dmg);
+    }
+
+    #[test]
+    fn test_new_with_model() {
+        let gameboy = Gameboy::new_with_model("resources/test-rom.gb", Some(Model::Cgb)).unwrap();
+        assert_eq!(gameboy.mmu.model(), Model::Cgb);
+        assert_eq!(gameboy.cpu.reg.a, 0x11);
+    }
+
+    #[test]
+    fn test_step_illegal_opcode() {
+        let mut gameboy = Gameboy::new("resources/test-rom.gb").unwrap();
+        gameboy.cpu.reg.pc = 0xC000;
+        gameboy.mmu.set_byte(0xC000 as usize, 0xDD);
+        assert_eq!(
+            gameboy.step(),
+            Err(EmulationError::IllegalOpcode { opcode: 0xDD, pc: 0xC000 })
+        );
+        assert!(gameboy.step().is_err());
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        for model in [Model::Dmg, Model::Sgb, Model::Cgb] {
+            let mut gameboy = Gameboy::new_with_model("resources/test-rom.gb", Some(model)).unwrap();
+            for _ in 0..10 {
+                gameboy.step().unwrap();
+            }
+            let state = gameboy.save_state();
+            for _ in 0..10 {
+                gameboy.step().unwrap();
+            }
+            let frame_buffer = gameboy.mmu.ppu.frame_buffer;
+            let clock_cycles = gameboy.mmu.clock_cycles();
+
+            gameboy.load_state(&state).unwrap();
+            assert_eq!(gameboy.save_state(), state);
+            for _ in 0..10 {
+                gameboy.step().unwrap();
+            }
+            assert!(gameboy.mmu.ppu.frame_buffer == frame_buffer);
+            assert_eq!(gameboy.mmu.clock_cycles(), clock_cycles);
+        }
+    }
+
+    #[test]
+    fn test_load_state_errors() {
+        let mut gameboy = Gameboy::new("resources/test-rom.gb").unwrap();
+        let state = gameboy.save_state();
+        gameboy.step().unwrap();
+        let before = gameboy.save_state();
+
+        assert!(matches!(
+            gameboy.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+        let mut corrupt = state.clone();
+        corrupt[12 + 10 + 4] = 2;
+        assert!(matches!(gameboy.load_state(&corrupt), Err(StateError::Corrupt)));
+        assert_eq!(gameboy.save_state(), before);
+
+        let cgb = Gameboy::new_with_model("resources/test-rom.gb", Some(Model::Cgb)).unwrap();
+        assert!(matches!(
+            gameboy.load_state(&cgb.save_state()),
+            Err(StateError::ModelMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let mut gameboy = Gameboy::new("resources/test-rom.gb").unwrap();
+        let cycles = gameboy.run_cycles(100).unwrap();
+        assert!((100..124).contains(&cycles));
+        assert_eq!(gameboy.mmu.clock_cycles(), cycles as u64);
+
+        // Running a frame worth of cycles ends up where stepping a frame does
+        let mut stepped = Gameboy::new("resources/test-rom.gb").unwrap();
+        stepped.step().unwrap();
+        stepped.step().unwrap();
+        gameboy.run_cycles(CYCLES_PER_FRAME - cycles).unwrap();
+        gameboy.step().unwrap();
+        assert_eq!(gameboy.mmu.clock_cycles(), stepped.mmu.clock_cycles());
+        assert!(gameboy.mmu.ppu.frame_buffer == stepped.mmu.ppu.frame_buffer);
+    }
+
+    #[test]
+    fn test_step_with_hooks() {
+        let mut gameboy = Gameboy::new("resources/test-rom.gb").unwrap();
+        // LD A,$42; LD ($C100),A; JR -2 loops on the store
+        for (i, byte) in [0x3E, 0x42, 0xEA, 0x00, 0xC1, 0x18, 0xFB].into_iter().enumerate() {
+            gameboy.mmu.set_byte(0xC000 + i, byte);
+        }
+        gameboy.registers_mut().pc = 0xC000;
+        gameboy.mmu.hooks.add(Access::Execute, 0xC000);
+        gameboy.mmu.hooks.add(Access::Read, 0xC001);
+        gameboy.mmu.hooks.add(Access::Write, 0xC100);
+
+        let mut hits = Vec::new();
+        gameboy
+            .step_with_hooks(|gb, hit| {
+                // Hooks run between instructions, so the state can be changed from them
+                if hit.access == Access::Write {
+                    gb.mmu.poke(0xC101, None, hit.value);
+                }
+                hits.push((hit, gb.registers().pc));
+            })
+            .unwrap();
+        assert_eq!(hits[0], (Hit { access: Access::Execute, addr: 0xC000, value: 0x3E }, 0xC002));
+        assert_eq!(hits[1], (Hit { access: Access::Read, addr: 0xC001, value: 0x42 }, 0xC002));
+        assert_eq!(hits[2], (Hit { access: Access::Write, addr: 0xC100, value: 0x42 }, 0xC005));
+        // The store runs in a loop, the load only once
+        assert!(hits[3..].iter().all(|(hit, _)| hit.access == Access::Write));
+        assert!(hits.len() > 100);
+        assert_eq!(gameboy.mmu.peek(0xC101, None), 0x42);
+
+        gameboy.step().unwrap();
+        assert!(!gameboy.mmu.hooks.has_hits());
+    }
+
+    #[test]
+    fn test_run_instruction() {
+        let mut gameboy = Gameboy::new("resources/test-rom.gb").unwrap();
+        let mut stepped = Gameboy::new("resources/test-rom.gb").unwrap();
+        gameboy.mmu.hooks.add(Access::Execute, 0x0100);
+        let mut instructions = 0;
+        while !gameboy.run_instruction().unwrap() {
+            instructions += 1;
+        }
+        stepped.step().unwrap();
+        assert!(instructions > 1000);
+        assert_eq!(gameboy.mmu.clock_cycles(), stepped.mmu.clock_cycles());
+        assert!(gameboy.mmu.ppu.frame_buffer == stepped.mmu.ppu.frame_buffer);
+        // The hits are left for the caller
+        assert_eq!(gameboy.mmu.hooks.take_hits()[0].addr, 0x0100);
+    }
+
+    /// Run a Mooneye test ROM until it executes LD B, B, which every test does once it's done
+    /// The test passed if the registers then hold the start of the Fibonacci sequence
+    fn run_mooneye_rom(path: &str) -> Result<(), String> {
+        let mut gameboy = Gameboy::new(path).map_err(|e| e.to_string())?;
+        // The timer tests finish in well under a second
+        let mut frames = 0;
+        while frames < 600 {
+            if gameboy.mmu.peek(gameboy.cpu.reg.pc, None) == 0x40 {
+                let reg = &gameboy.cpu.reg;
+                let result = [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l];
+                return if result == [3, 5, 8, 13, 21, 34] {
+                    Ok(())
+                } else {
+                    Err(format!("failed with registers {:02X?}", result))
+                };
+            }
+            if gameboy.run_instruction().map_err(|e| e.to_string())? {
+                frames += 1;
+            }
+        }
+        Err("timed out".to_owned())
+    }
+
+    /// The Mooneye acceptance/timer ROMs aren't distributed with the emulator, copy them into
+    /// resources/mooneye/timer and run `cargo test -- --ignored` to check them
+    #[test]
+    #[ignore]
+    fn test_mooneye_timer() {
+        let entries = std::fs::read_dir("resources/mooneye/timer")
+            .expect("Copy the Mooneye acceptance/timer ROMs into resources/mooneye/timer");
+        let mut roms: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "gb"))
+            .collect();
+        roms.sort();
+        assert!(!roms.is_empty(), "No ROMs found in resources/mooneye/timer");
+
+        let failures: Vec<_> = roms
+            .iter()
+            .filter_map(|rom| {
+                let rom = rom.to_str().unwrap();
+                let result = run_mooneye_rom(rom);
+                println!("{}: {}", rom, if result.is_ok() { "ok" } else { "FAILED" });
+                result.err().map(|e| format!("{}: {}", rom, e))
+            })
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
     }
 }
@@ -0,0 +1,114 @@
+/// The kinds of CPU memory accesses that can be hooked
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read = 0x01,
+    Write = 0x02,
+    /// The first byte of an instruction being fetched
+    Execute = 0x04,
+}
+
+/// An access by the CPU to a hooked address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub access: Access,
+    pub addr: u16,
+    /// The byte read, written or fetched as an opcode
+    pub value: u8,
+}
+
+/// Addresses the frontend wants to hear about when the CPU accesses them
+/// Only accesses made by instructions are recorded, OAM DMA and debugger reads are not
+pub struct MemoryHooks {
+    /// The hooked access kinds of each address, as bits of `Access`
+    hooked: Box<[u8; 0x10000]>,
+    /// Accesses recorded since the hits were last taken
+    hits: Vec<Hit>,
+}
+
+impl MemoryHooks {
+    pub fn new() -> Self {
+        MemoryHooks {
+            hooked: Box::new([0; 0x10000]),
+            hits: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, access: Access, addr: u16) {
+        self.hooked[addr as usize] |= access as u8;
+    }
+
+    pub fn remove(&mut self, access: Access, addr: u16) {
+        self.hooked[addr as usize] &= !(access as u8);
+    }
+
+    /// Remove every hook, recorded hits are dropped as well
+    pub fn clear(&mut self) {
+        self.hooked.fill(0);
+        self.hits.clear();
+    }
+
+    pub fn is_hooked(&self, access: Access, addr: u16) -> bool {
+        self.hooked[addr as usize] & access as u8 != 0
+    }
+
+    /// Record an access if its address is hooked
+    pub(crate) fn record(&mut self, access: Access, addr: u16, value: u8) {
+        if self.is_hooked(access, addr) {
+            self.hits.push(Hit { access, addr, value });
+        }
+    }
+
+    /// The accesses recorded so far, in the order they happened
+    pub fn take_hits(&mut self) -> Vec<Hit> {
+        std::mem::take(&mut self.hits)
+    }
+
+    pub fn has_hits(&self) -> bool {
+        !self.hits.is_empty()
+    }
+}
+
+impl Default for MemoryHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut hooks = MemoryHooks::new();
+        hooks.add(Access::Read, 0xC000);
+        hooks.add(Access::Write, 0xC000);
+        hooks.record(Access::Read, 0xC000, 0x12);
+        hooks.record(Access::Execute, 0xC000, 0x00);
+        hooks.record(Access::Read, 0xC001, 0x34);
+        hooks.record(Access::Write, 0xC000, 0x56);
+        assert_eq!(
+            hooks.take_hits(),
+            [
+                Hit { access: Access::Read, addr: 0xC000, value: 0x12 },
+                Hit { access: Access::Write, addr: 0xC000, value: 0x56 },
+            ]
+        );
+        assert!(!hooks.has_hits());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut hooks = MemoryHooks::new();
+        hooks.add(Access::Read, 0xC000);
+        hooks.add(Access::Write, 0xC000);
+        hooks.remove(Access::Read, 0xC000);
+        assert!(!hooks.is_hooked(Access::Read, 0xC000));
+        assert!(hooks.is_hooked(Access::Write, 0xC000));
+
+        hooks.record(Access::Write, 0xC000, 0x12);
+        hooks.clear();
+        assert!(!hooks.is_hooked(Access::Write, 0xC000));
+        assert!(!hooks.has_hits());
+    }
+}
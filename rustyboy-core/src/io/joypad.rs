@@ -1,5 +1,17 @@
+use crate::io::sgb::Sgb;
+use crate::io::IoDevice;
+use crate::model::Model;
 use crate::util::binaryutils::is_bit_set;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
+pub const JOYPAD_ADDR: usize = 0xFF00;
+
+/// The top two bits of the joypad register are unused and always read as 1
+const JOYPAD_UNUSED: u8 = 0xC0;
+/// The key line selection bits, the only writable bits of the joypad register
+const JOYPAD_SELECT: u8 = 0x30;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Key {
     A,
     B,
@@ -11,20 +23,46 @@ pub enum Key {
     Select,
 }
 
+/// The keys in the bit order of `Joypad::pressed_keys`
+const KEY_BITS: [Key; 8] = [
+    Key::A,
+    Key::B,
+    Key::Select,
+    Key::Start,
+    Key::Right,
+    Key::Left,
+    Key::Up,
+    Key::Down,
+];
+
 pub struct Joypad {
     direction_keys: u8,
     button_keys: u8,
+    /// The key lines selected by the last write to the joypad register
+    select: u8,
+    /// SGB command packet receiver, only present when emulating an SGB
+    pub sgb: Option<Sgb>,
+    /// Has an interrupt been fired
+    pub interrupt_fired: bool,
 }
 
 impl Joypad {
     pub fn new() -> Self {
+        Self::new_with_model(Model::Dmg)
+    }
+
+    pub fn new_with_model(model: Model) -> Self {
         Self {
             direction_keys: 0x0F,
             button_keys: 0x0F,
+            select: JOYPAD_SELECT,
+            sgb: if model.is_sgb() { Some(Sgb::new()) } else { None },
+            interrupt_fired: false,
         }
     }
 
     pub fn push_key(&mut self, key: Key) {
+        let old_keys = self.selected_keys();
         match key {
             Key::A => self.button_keys &= 0x01 ^ 0x0F,
             Key::B => self.button_keys &= 0x02 ^ 0x0F,
@@ -35,6 +73,7 @@ impl Joypad {
             Key::Up => self.direction_keys &= 0x04 ^ 0x0F,
             Key::Down => self.direction_keys &= 0x08 ^ 0x0F,
         }
+        self.check_interrupt(old_keys);
     }
 
     pub fn release_key(&mut self, key: Key) {
@@ -50,13 +89,190 @@ impl Joypad {
         }
     }
 
-    pub fn read_value(&self, value: u8) -> u8 {
-        if !is_bit_set(&value, 4) {
-            return self.direction_keys;
-        } else if !is_bit_set(&value, 5) {
-            return self.button_keys;
+    /// The keys currently held down as a bitmask, a bit is set when the key is pressed
+    /// The lower nibble holds A, B, Select and Start and the upper nibble Right, Left, Up and Down,
+    /// the same order as the bits of the joypad register
+    pub fn pressed_keys(&self) -> u8 {
+        (!self.button_keys & 0x0F) | ((!self.direction_keys & 0x0F) << 4)
+    }
+
+    /// Press and release keys to match a bitmask in the format of `pressed_keys`
+    pub fn set_pressed_keys(&mut self, keys: u8) {
+        for (bit, key) in KEY_BITS.into_iter().enumerate() {
+            let pressed = is_bit_set(&keys, bit as u8);
+            if pressed != is_bit_set(&self.pressed_keys(), bit as u8) {
+                if pressed {
+                    self.push_key(key);
+                } else {
+                    self.release_key(key);
+                }
+            }
+        }
+    }
+
+    /// The state of the keys on the selected lines, a key reads as 0 when pressed
+    /// The keys of both lines are combined if both of them are selected
+    pub fn selected_keys(&self) -> u8 {
+        let mut keys = 0x0F;
+        if !is_bit_set(&self.select, 4) {
+            keys &= self.direction_keys;
+        }
+        if !is_bit_set(&self.select, 5) {
+            keys &= self.button_keys;
         }
+        keys
+    }
 
-        0xFF
+    /// Fire an interrupt if any of the selected key lines went from high to low
+    fn check_interrupt(&mut self, old_keys: u8) {
+        if old_keys & !self.selected_keys() & 0x0F != 0 {
+            self.interrupt_fired = true;
+        }
     }
-}
\ No newline at end of file
+
+    /// Is any of the selected key lines pulled low by a pressed key
+    /// Used to wake the CPU from STOP
+    pub fn selected_line_low(&self) -> bool {
+        self.selected_keys() != 0x0F
+    }
+
+    /// The value of the joypad register
+    pub fn read_value(&self) -> u8 {
+        let mut keys = self.selected_keys();
+        if self.select == JOYPAD_SELECT {
+            if let Some(sgb) = &self.sgb {
+                keys = sgb.player_id();
+            }
+        }
+
+        JOYPAD_UNUSED | self.select | keys
+    }
+}
+
+impl IoDevice for Joypad {
+    fn read(&self, _addr: usize) -> u8 {
+        self.read_value()
+    }
+
+    fn write(&mut self, _addr: usize, v: u8) {
+        // Selecting a line with a key held down also pulls it low
+        let old_keys = self.selected_keys();
+        self.select = v & JOYPAD_SELECT;
+        self.check_interrupt(old_keys);
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.write(v);
+        }
+    }
+}
+
+impl Savestate for Joypad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.direction_keys);
+        state.write_u8(self.button_keys);
+        state.write_u8(self.select);
+        state.write_bool(self.interrupt_fired);
+        // Whether there is an SGB follows from the model, which the state header already checks
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.direction_keys = state.read_u8()?;
+        self.button_keys = state.read_u8()?;
+        self.select = state.read_u8()?;
+        self.interrupt_fired = state.read_bool()?;
+        if let Some(sgb) = &mut self.sgb {
+            sgb.load_state(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_nothing_selected() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.write(JOYPAD_ADDR, 0x30);
+        assert_eq!(joypad.read(JOYPAD_ADDR), 0xFF);
+    }
+
+    #[test]
+    fn test_read_direction_keys() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.push_key(Key::Down);
+        joypad.write(JOYPAD_ADDR, 0x20);
+        assert_eq!(joypad.read(JOYPAD_ADDR), 0xE7);
+    }
+
+    #[test]
+    fn test_read_button_keys() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.push_key(Key::Down);
+        joypad.write(JOYPAD_ADDR, 0x10);
+        assert_eq!(joypad.read(JOYPAD_ADDR), 0xDE);
+    }
+
+    #[test]
+    fn test_selected_line_low() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.write(JOYPAD_ADDR, 0x20);
+        assert!(!joypad.selected_line_low());
+        joypad.write(JOYPAD_ADDR, 0x10);
+        assert!(joypad.selected_line_low());
+    }
+
+    #[test]
+    fn test_interrupt_on_press() {
+        let mut joypad = Joypad::new();
+        joypad.write(JOYPAD_ADDR, 0x20);
+        joypad.push_key(Key::A);
+        assert!(!joypad.interrupt_fired);
+        joypad.push_key(Key::Up);
+        assert!(joypad.interrupt_fired);
+    }
+
+    #[test]
+    fn test_interrupt_on_select() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.write(JOYPAD_ADDR, 0x30);
+        assert!(!joypad.interrupt_fired);
+        joypad.write(JOYPAD_ADDR, 0x10);
+        assert!(joypad.interrupt_fired);
+    }
+
+    #[test]
+    fn test_read_both_selected() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.push_key(Key::Down);
+        joypad.write(JOYPAD_ADDR, 0x00);
+        assert_eq!(joypad.read(JOYPAD_ADDR), 0xC6);
+    }
+
+    #[test]
+    fn test_pressed_keys() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::B);
+        joypad.push_key(Key::Up);
+        assert_eq!(joypad.pressed_keys(), 0x42);
+    }
+
+    #[test]
+    fn test_set_pressed_keys() {
+        let mut joypad = Joypad::new();
+        joypad.push_key(Key::A);
+        joypad.set_pressed_keys(0x88);
+        assert_eq!(joypad.pressed_keys(), 0x88);
+        joypad.write(JOYPAD_ADDR, 0x10);
+        assert_eq!(joypad.read(JOYPAD_ADDR), 0xD7);
+    }
+}
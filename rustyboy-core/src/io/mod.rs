@@ -1,2 +1,18 @@
 pub mod joypad;
+pub mod serial;
+pub mod sgb;
+pub mod sound;
 pub mod timer;
+
+/// A device with registers mapped into the IO address range
+pub trait IoDevice: Send {
+    /// Read the register at a given address
+    fn read(&self, addr: usize) -> u8;
+    /// Write a value to the register at a given address
+    fn write(&mut self, addr: usize, v: u8);
+    /// Set the register at a given address without the side effects of a CPU write, for debuggers
+    /// Devices whose writes have no side effects can rely on the default, which calls `write`
+    fn poke(&mut self, addr: usize, v: u8) {
+        self.write(addr, v);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::io::IoDevice;
+use crate::util::binaryutils::is_bit_set;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub const SB_ADDR: usize = 0xFF01;
+pub const SC_ADDR: usize = 0xFF02;
+
+/// Only the transfer start and clock select bits of SC are used, the rest always read as 1
+const SC_UNUSED: u8 = 0x7E;
+
+/// Clock cycles needed to shift out a byte using the internal 8192Hz clock
+const TRANSFER_CYCLES: u32 = 4096;
+
+/// The serial port
+/// There's never anything connected to the other end of the link cable, so every
+/// transfer shifts in 0xFF
+pub struct Serial {
+    /// SB register
+    pub data: u8,
+    /// SC register
+    pub control: u8,
+    /// Has an interrupt been fired
+    pub interrupt_fired: bool,
+    /// The amount of clock cycles that have passed since the current transfer started
+    clock_cycles: u32,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            data: 0x00,
+            control: SC_UNUSED,
+            interrupt_fired: false,
+            clock_cycles: 0,
+        }
+    }
+
+    pub fn step(&mut self, clock_cycles: u8) {
+        // Only transfers using the internal clock can complete without a link partner
+        if !is_bit_set(&self.control, 7) || !is_bit_set(&self.control, 0) {
+            return;
+        }
+
+        self.clock_cycles += clock_cycles as u32;
+        if self.clock_cycles >= TRANSFER_CYCLES {
+            log::debug!("Serial: Sent {:02X}", self.data);
+            self.clock_cycles = 0;
+            self.data = 0xFF;
+            self.control &= 0x7F;
+            self.interrupt_fired = true;
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoDevice for Serial {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            SB_ADDR => self.data,
+            SC_ADDR => self.control | SC_UNUSED,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: usize, v: u8) {
+        match addr {
+            SB_ADDR => self.data = v,
+            SC_ADDR => {
+                self.control = v | SC_UNUSED;
+                self.clock_cycles = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn poke(&mut self, addr: usize, v: u8) {
+        match addr {
+            // Doesn't restart a running transfer
+            SC_ADDR => self.control = v | SC_UNUSED,
+            _ => self.write(addr, v),
+        }
+    }
+}
+
+impl Savestate for Serial {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.data);
+        state.write_u8(self.control);
+        state.write_bool(self.interrupt_fired);
+        state.write_u32(self.clock_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.interrupt_fired = state.read_bool()?;
+        self.clock_cycles = state.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_internal_clock() {
+        let mut serial = Serial::new();
+        serial.write(SB_ADDR, 0x42);
+        serial.write(SC_ADDR, 0x81);
+        for _ in 0..(TRANSFER_CYCLES / 4 - 1) {
+            serial.step(4);
+        }
+        assert!(!serial.interrupt_fired);
+        serial.step(4);
+        assert!(serial.interrupt_fired);
+        assert_eq!(serial.read(SB_ADDR), 0xFF);
+        assert_eq!(serial.read(SC_ADDR), 0x7F);
+    }
+
+    #[test]
+    fn test_transfer_external_clock() {
+        let mut serial = Serial::new();
+        serial.write(SB_ADDR, 0x42);
+        serial.write(SC_ADDR, 0x80);
+        serial.step(0xFF);
+        for _ in 0..TRANSFER_CYCLES {
+            serial.step(4);
+        }
+        assert!(!serial.interrupt_fired);
+        assert_eq!(serial.read(SB_ADDR), 0x42);
+    }
+}
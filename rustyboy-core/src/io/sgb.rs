@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// Size of a single SGB command packet in bytes
+pub const PACKET_SIZE: usize = 16;
+
+/// Command sent to request multiplayer joypad reading
+const MLT_REQ: u8 = 0x11;
+
+/// Maximum number of packets kept around before the oldest ones are dropped
+const MAX_QUEUED_PACKETS: usize = 64;
+
+/// Receives the command packets an SGB game sends by pulsing the P14/P15 lines of the joypad register
+pub struct Sgb {
+    /// The packet currently being received
+    packet: [u8; PACKET_SIZE],
+    /// The number of bits of the current packet received so far
+    bits_received: usize,
+    /// Whether a reset pulse has started a transfer
+    receiving: bool,
+    /// Whether both lines went high since the last pulse
+    ready_for_pulse: bool,
+    /// The number of packets still to come for a multi packet command
+    remaining_packets: u8,
+    /// The last value of the P14/P15 lines
+    last_select: u8,
+    /// Packets that have been fully received
+    pub packets: VecDeque<[u8; PACKET_SIZE]>,
+    /// The number of joypads enabled through MLT_REQ
+    pub player_count: u8,
+    /// The joypad currently being read
+    pub current_player: u8,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            packet: [0; PACKET_SIZE],
+            bits_received: 0,
+            receiving: false,
+            ready_for_pulse: false,
+            remaining_packets: 0,
+            last_select: 0x30,
+            packets: VecDeque::new(),
+            player_count: 1,
+            current_player: 0,
+        }
+    }
+
+    /// Handle a write to the joypad register
+    pub fn write(&mut self, value: u8) {
+        let select = value & 0x30;
+        match select {
+            0x00 => {
+                // Reset pulse, starts a new packet
+                self.receiving = true;
+                self.ready_for_pulse = false;
+                self.bits_received = 0;
+                self.packet = [0; PACKET_SIZE];
+            }
+            0x10 | 0x20 if self.receiving && self.ready_for_pulse => {
+                // P15 low sends a 1, P14 low sends a 0
+                self.ready_for_pulse = false;
+                let bit = select == 0x10;
+                if self.bits_received == PACKET_SIZE * 8 {
+                    // The stop bit, which must always be a 0
+                    self.receiving = false;
+                    if !bit {
+                        self.packet_received();
+                    }
+                } else {
+                    if bit {
+                        self.packet[self.bits_received / 8] |= 1 << (self.bits_received % 8);
+                    }
+                    self.bits_received += 1;
+                }
+            }
+            0x30 => {
+                self.ready_for_pulse = true;
+                // The next joypad is selected every time P15 goes from low to high
+                if self.last_select & 0x20 == 0 && !self.receiving && self.player_count > 1 {
+                    self.current_player = (self.current_player + 1) % self.player_count;
+                }
+            }
+            _ => {}
+        }
+        self.last_select = select;
+    }
+
+    /// Handle a fully received packet
+    fn packet_received(&mut self) {
+        if self.remaining_packets == 0 {
+            // First packet of a command, the lower three bits hold the packet count
+            self.remaining_packets = (self.packet[0] & 0x07).max(1);
+            let command = self.packet[0] >> 3;
+            log::debug!("SGB: Received command {:02X}", command);
+            if command == MLT_REQ {
+                self.player_count = match self.packet[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            }
+        }
+        self.remaining_packets -= 1;
+
+        if self.packets.len() == MAX_QUEUED_PACKETS {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(self.packet);
+    }
+
+    /// The value of the lower nibble of the joypad register when no keys are selected
+    pub fn player_id(&self) -> u8 {
+        0x0F - self.current_player
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Savestate for Sgb {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.packet);
+        state.write_u16(self.bits_received as u16);
+        state.write_bool(self.receiving);
+        state.write_bool(self.ready_for_pulse);
+        state.write_u8(self.remaining_packets);
+        state.write_u8(self.last_select);
+        // Every queue slot is saved so that the state always has the same size
+        state.write_u8(self.packets.len() as u8);
+        for i in 0..MAX_QUEUED_PACKETS {
+            state.write_bytes(self.packets.get(i).unwrap_or(&[0; PACKET_SIZE]));
+        }
+        state.write_u8(self.player_count);
+        state.write_u8(self.current_player);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.packet)?;
+        self.bits_received = state.read_u16()? as usize;
+        if self.bits_received > PACKET_SIZE * 8 {
+            return Err(StateError::Corrupt);
+        }
+        self.receiving = state.read_bool()?;
+        self.ready_for_pulse = state.read_bool()?;
+        self.remaining_packets = state.read_u8()?;
+        self.last_select = state.read_u8()?;
+        let packet_count = state.read_u8()? as usize;
+        if packet_count > MAX_QUEUED_PACKETS {
+            return Err(StateError::Corrupt);
+        }
+        self.packets.clear();
+        for i in 0..MAX_QUEUED_PACKETS {
+            let mut packet = [0; PACKET_SIZE];
+            state.read_bytes(&mut packet)?;
+            if i < packet_count {
+                self.packets.push_back(packet);
+            }
+        }
+        self.player_count = state.read_u8()?;
+        self.current_player = state.read_u8()?;
+        if !(1..=4).contains(&self.player_count) || self.current_player >= self.player_count {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(sgb: &mut Sgb, packet: &[u8; PACKET_SIZE]) {
+        sgb.write(0x00);
+        sgb.write(0x30);
+        for byte in packet {
+            for bit in 0..8 {
+                sgb.write(if byte & (1 << bit) != 0 { 0x10 } else { 0x20 });
+                sgb.write(0x30);
+            }
+        }
+        // Stop bit
+        sgb.write(0x20);
+        sgb.write(0x30);
+    }
+
+    #[test]
+    fn test_receive_packet() {
+        let mut sgb = Sgb::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = 0x01;
+        packet[1] = 0xA5;
+        packet[15] = 0x80;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.packets.len(), 1);
+        assert_eq!(sgb.packets[0], packet);
+    }
+
+    #[test]
+    fn test_mlt_req() {
+        let mut sgb = Sgb::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = (MLT_REQ << 3) | 0x01;
+        packet[1] = 0x01;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.player_count, 2);
+        assert_eq!(sgb.player_id(), 0x0F);
+
+        sgb.write(0x20);
+        sgb.write(0x30);
+        assert_eq!(sgb.player_id(), 0x0F);
+        sgb.write(0x10);
+        sgb.write(0x30);
+        assert_eq!(sgb.player_id(), 0x0E);
+    }
+
+    #[test]
+    fn test_packet_without_reset_ignored() {
+        let mut sgb = Sgb::new();
+        sgb.write(0x10);
+        sgb.write(0x30);
+        assert_eq!(sgb.packets.len(), 0);
+        assert_eq!(sgb.bits_received, 0);
+    }
+}
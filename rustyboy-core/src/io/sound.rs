@@ -0,0 +1,102 @@
+use crate::io::IoDevice;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub const SOUND_START: usize = 0xFF10;
+pub const SOUND_END: usize = 0xFF3F;
+pub const NR52_ADDR: usize = 0xFF26;
+
+const WAVE_RAM_START: usize = 0xFF30;
+
+/// Bits of each sound register that always read as 1, from NR10 up to the start of wave RAM
+/// Write only bits (frequencies, lengths, triggers) and unused registers read as 1 too
+const READ_MASKS: [u8; WAVE_RAM_START - SOUND_START] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // Unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // Unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Unused
+];
+
+/// The sound registers
+/// No audio is generated yet, this only stores the registers so games read back what they wrote
+pub struct Sound {
+    registers: [u8; SOUND_END - SOUND_START + 1],
+}
+
+impl Sound {
+    pub fn new() -> Self {
+        let mut registers = [0x00; SOUND_END - SOUND_START + 1];
+        // The values the boot ROM leaves behind after playing the startup sound
+        registers[0xFF11 - SOUND_START] = 0x80;
+        registers[0xFF12 - SOUND_START] = 0xF3;
+        registers[0xFF24 - SOUND_START] = 0x77;
+        registers[0xFF25 - SOUND_START] = 0xF3;
+        registers[NR52_ADDR - SOUND_START] = 0x80;
+        Sound { registers }
+    }
+}
+
+impl Default for Sound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoDevice for Sound {
+    fn read(&self, addr: usize) -> u8 {
+        let value = self.registers[addr - SOUND_START];
+        if addr >= WAVE_RAM_START {
+            value
+        } else {
+            value | READ_MASKS[addr - SOUND_START]
+        }
+    }
+
+    fn write(&mut self, addr: usize, v: u8) {
+        // Only the power bit of NR52 is writable, the channel status bits are read only
+        let v = if addr == NR52_ADDR { v & 0x80 } else { v };
+        self.registers[addr - SOUND_START] = v;
+    }
+}
+
+impl Savestate for Sound {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.registers);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.registers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_masks() {
+        let mut sound = Sound::new();
+        sound.write(0xFF10, 0x00);
+        assert_eq!(sound.read(0xFF10), 0x80);
+        sound.write(0xFF13, 0x12);
+        assert_eq!(sound.read(0xFF13), 0xFF);
+        sound.write(0xFF24, 0x12);
+        assert_eq!(sound.read(0xFF24), 0x12);
+    }
+
+    #[test]
+    fn test_nr52() {
+        let mut sound = Sound::new();
+        assert_eq!(sound.read(NR52_ADDR), 0xF0);
+        sound.write(NR52_ADDR, 0x0F);
+        assert_eq!(sound.read(NR52_ADDR), 0x70);
+    }
+
+    #[test]
+    fn test_wave_ram() {
+        let mut sound = Sound::new();
+        sound.write(0xFF30, 0x12);
+        assert_eq!(sound.read(0xFF30), 0x12);
+    }
+}
@@ -1,19 +1,25 @@
+use crate::io::IoDevice;
+use crate::model::Model;
 use crate::util::binaryutils;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
-const DIV_CYCLES: u32 = 256;
-const SPEED_ZERO_CYCLES: u32 = 1024;
-const SPEED_ONE_CYCLES: u32 = 16;
-const SPEED_TWO_CYCLES: u32 = 64;
-const SPEED_THREE_CYCLES: u32 = 256;
+/// The system counter is incremented once per clock cycle, but the timer only looks at it once per M-cycle
+const M_CYCLE: u32 = 4;
 
 pub const DIV_ADDR: usize = 0xFF04;
 pub const TIMA_ADDR: usize = 0xFF05;
 pub const TMA_ADDR: usize = 0xFF06;
 pub const TAC_ADDR: usize = 0xFF07;
 
+/// The top five bits of TAC are unused and always read as 1
+const TAC_UNUSED: u8 = 0xF8;
+
+/// The timer, DIV and TIMA are both driven by a single 16 bit counter
+/// DIV is the upper byte of the counter, TIMA is incremented whenever the counter bit selected
+/// by TAC (ANDed with the enable bit) goes from 1 to 0
 pub struct Timer {
-    /// DIV register
-    pub divider: u8,
+    /// The internal system counter, incremented every clock cycle
+    system_counter: u16,
     /// TIMA register
     pub counter: u8,
     /// TMA register
@@ -22,88 +28,207 @@ pub struct Timer {
     pub control: u8,
     /// Has an interrupt been fired
     pub interrupt_fired: bool,
-    /// The amount of clock cycles that have passed since the last divider update
-    clock_cycles_div: u32,
-    /// The amount of clock cycles that have passed since the last TIMA update
-    clock_cycles_tima: u32,
+    /// TIMA overflowed during the last M-cycle, it reads as 0 until TMA is loaded in the next one
+    overflow: bool,
+    /// TMA was loaded into TIMA during the last M-cycle
+    reloading: bool,
+    /// Clock cycles that don't add up to a full M-cycle yet
+    clock_cycles: u32,
 }
 
 impl Timer {
     pub fn new() -> Self {
+        Self::new_with_model(Model::Dmg)
+    }
+
+    /// Create a timer in the state the boot ROM of a given model leaves it in
+    pub fn new_with_model(model: Model) -> Self {
+        // The SGB and CGB values depend on how long the boot ROM ran, so these are approximate
+        let system_counter = match model {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb | Model::Sgb => 0xABCC,
+            Model::Cgb | Model::Agb => 0x1EA0,
+        };
         Timer {
-            divider: 0x18,
+            system_counter,
             counter: 0x00,
             modulo: 0x00,
-            control: 0xF8,
+            control: TAC_UNUSED,
             interrupt_fired: false,
-            clock_cycles_div: 0,
-            clock_cycles_tima: 0,
+            overflow: false,
+            reloading: false,
+            clock_cycles: 0,
         }
     }
 
+    /// DIV register, the upper byte of the system counter
+    pub fn divider(&self) -> u8 {
+        (self.system_counter >> 8) as u8
+    }
+
     pub fn step(&mut self, clock_cycles: u8) {
-        // Update the divider register
-        self.clock_cycles_div += clock_cycles as u32;
-        if self.clock_cycles_div >= DIV_CYCLES {
-            self.divider = self.divider.wrapping_add(1);
-            self.clock_cycles_div -= DIV_CYCLES;
+        self.clock_cycles += clock_cycles as u32;
+        while self.clock_cycles >= M_CYCLE {
+            self.clock_cycles -= M_CYCLE;
+            self.step_m_cycle();
         }
+    }
 
-        let speed = self.control & 0x03;
-        if !binaryutils::is_bit_set(&self.control, 2) {
-            return;
+    /// Advance the timer by a single M-cycle
+    fn step_m_cycle(&mut self) {
+        self.reloading = false;
+        if self.overflow {
+            // TMA is loaded one M-cycle after TIMA overflowed
+            self.overflow = false;
+            self.counter = self.modulo;
+            self.interrupt_fired = true;
+            self.reloading = true;
         }
 
-        self.clock_cycles_tima += clock_cycles as u32;
+        let old_signal = self.timer_signal();
+        self.system_counter = self.system_counter.wrapping_add(M_CYCLE as u16);
+        if old_signal && !self.timer_signal() {
+            self.increment_counter();
+        }
+    }
 
-        let timer_cycles = match speed {
-            0x00 => SPEED_ZERO_CYCLES,
-            0x01 => SPEED_ONE_CYCLES,
-            0x02 => SPEED_TWO_CYCLES,
-            0x03 => SPEED_THREE_CYCLES,
-            _ => panic!("Unknown timer speed! {:02X}", speed),
+    /// The bit of the system counter selected by TAC, ANDed with the timer enable bit
+    fn timer_signal(&self) -> bool {
+        if !binaryutils::is_bit_set(&self.control, 2) {
+            return false;
+        }
+
+        let bit = match self.control & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            0x03 => 7,
+            _ => unreachable!(),
         };
+        self.system_counter & (1 << bit) != 0
+    }
+
+    fn increment_counter(&mut self) {
+        let (counter, overflow) = self.counter.overflowing_add(1);
+        self.counter = counter;
+        self.overflow = overflow;
+    }
+}
 
-        while self.clock_cycles_tima >= timer_cycles {
-            if self.counter == 0xFF {
-                self.counter = self.modulo;
-                self.interrupt_fired = true;
-                return;
+impl IoDevice for Timer {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            DIV_ADDR => self.divider(),
+            TIMA_ADDR => self.counter,
+            TMA_ADDR => self.modulo,
+            TAC_ADDR => self.control | TAC_UNUSED,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: usize, v: u8) {
+        match addr {
+            DIV_ADDR => {
+                // All writes to DIV reset the whole system counter, which can cause a falling edge
+                let old_signal = self.timer_signal();
+                self.system_counter = 0;
+                if old_signal {
+                    self.increment_counter();
+                }
+            }
+            // Writes during the reload cycle are overwritten by TMA, writes during the
+            // overflow cycle cancel the reload and the interrupt
+            TIMA_ADDR if !self.reloading => {
+                self.counter = v;
+                self.overflow = false;
             }
+            TMA_ADDR => {
+                self.modulo = v;
+                // A TMA write during the reload cycle is also loaded into TIMA
+                if self.reloading {
+                    self.counter = v;
+                }
+            }
+            TAC_ADDR => {
+                // Disabling the timer or switching to a bit that is 0 can cause a falling edge
+                let old_signal = self.timer_signal();
+                self.control = v | TAC_UNUSED;
+                if old_signal && !self.timer_signal() {
+                    self.increment_counter();
+                }
+            }
+            _ => {}
+        }
+    }
 
-            self.counter += 1;
-            self.clock_cycles_tima -= timer_cycles;
+    fn poke(&mut self, addr: usize, v: u8) {
+        match addr {
+            // Only the upper byte of the system counter is visible through DIV
+            DIV_ADDR => self.system_counter = (v as u16) << 8 | (self.system_counter & 0xFF),
+            TIMA_ADDR => self.counter = v,
+            TMA_ADDR => self.modulo = v,
+            TAC_ADDR => self.control = v | TAC_UNUSED,
+            _ => {}
         }
     }
 }
 
+impl Savestate for Timer {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.system_counter);
+        state.write_u8(self.counter);
+        state.write_u8(self.modulo);
+        state.write_u8(self.control);
+        state.write_bool(self.interrupt_fired);
+        state.write_bool(self.overflow);
+        state.write_bool(self.reloading);
+        state.write_u32(self.clock_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.system_counter = state.read_u16()?;
+        self.counter = state.read_u8()?;
+        self.modulo = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.interrupt_fired = state.read_bool()?;
+        self.overflow = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        self.clock_cycles = state.read_u32()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A timer with the system counter cleared and TIMA counting every 16 clock cycles
+    fn fast_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.system_counter = 0;
+        timer.write(TAC_ADDR, 0x05);
+        timer
+    }
+
     #[test]
     fn test_new() {
         let timer = Timer::new();
-        assert_eq!(timer.divider, 0x18);
+        assert_eq!(timer.divider(), 0xAB);
         assert_eq!(timer.counter, 0x00);
         assert_eq!(timer.modulo, 0x00);
         assert_eq!(timer.control, 0xF8);
         assert_eq!(timer.interrupt_fired, false);
-        assert_eq!(timer.clock_cycles_div, 0);
-        assert_eq!(timer.clock_cycles_tima, 0);
+        assert_eq!(timer.system_counter, 0xABCC);
     }
 
     #[test]
     fn test_step() {
         let mut timer = Timer::new();
         timer.step(4);
-        assert_eq!(timer.divider, 0x18);
+        assert_eq!(timer.divider(), 0xAB);
         assert_eq!(timer.counter, 0x00);
-        assert_eq!(timer.modulo, 0x00);
-        assert_eq!(timer.control, 0xF8);
         assert_eq!(timer.interrupt_fired, false);
-        assert_eq!(timer.clock_cycles_div, 4);
-        assert_eq!(timer.clock_cycles_tima, 0);
+        assert_eq!(timer.system_counter, 0xABD0);
     }
 
     #[test]
@@ -111,12 +236,118 @@ mod tests {
         let mut timer = Timer::new();
         timer.step(0xFF);
         timer.step(0xFF);
-        assert_eq!(timer.divider, 0x19);
+        assert_eq!(timer.divider(), 0xAD);
         assert_eq!(timer.counter, 0x00);
-        assert_eq!(timer.modulo, 0x00);
-        assert_eq!(timer.control, 0xF8);
         assert_eq!(timer.interrupt_fired, false);
-        assert_eq!(timer.clock_cycles_div, 0xFE);
-        assert_eq!(timer.clock_cycles_tima, 0);
+        assert_eq!(timer.system_counter, 0xADC8);
+        assert_eq!(timer.clock_cycles, 2);
+    }
+
+    #[test]
+    fn test_step_disabled() {
+        let mut timer = Timer::new();
+        timer.write(TAC_ADDR, 0x01);
+        for _ in 0..0x100 {
+            timer.step(16);
+        }
+        assert_eq!(timer.counter, 0x00);
+    }
+
+    #[test]
+    fn test_step_counter_speeds() {
+        for (control, clock_cycles) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let mut timer = Timer::new();
+            timer.system_counter = 0;
+            timer.write(TAC_ADDR, control);
+            for _ in 0..(clock_cycles / 4 - 1) {
+                timer.step(4);
+            }
+            assert_eq!(timer.counter, 0x00);
+            timer.step(4);
+            assert_eq!(timer.counter, 0x01);
+        }
+    }
+
+    #[test]
+    fn test_overflow_delayed_reload() {
+        let mut timer = fast_timer();
+        timer.counter = 0xFF;
+        timer.modulo = 0x23;
+        timer.step(16);
+        assert_eq!(timer.counter, 0x00);
+        assert_eq!(timer.interrupt_fired, false);
+        timer.step(4);
+        assert_eq!(timer.counter, 0x23);
+        assert_eq!(timer.interrupt_fired, true);
+    }
+
+    #[test]
+    fn test_tima_write_cancels_reload() {
+        let mut timer = fast_timer();
+        timer.counter = 0xFF;
+        timer.modulo = 0x23;
+        timer.step(16);
+        timer.write(TIMA_ADDR, 0x42);
+        timer.step(4);
+        assert_eq!(timer.counter, 0x42);
+        assert_eq!(timer.interrupt_fired, false);
+    }
+
+    #[test]
+    fn test_tima_write_during_reload_ignored() {
+        let mut timer = fast_timer();
+        timer.counter = 0xFF;
+        timer.modulo = 0x23;
+        timer.step(20);
+        timer.write(TIMA_ADDR, 0x42);
+        assert_eq!(timer.counter, 0x23);
+    }
+
+    #[test]
+    fn test_tma_write_during_reload() {
+        let mut timer = fast_timer();
+        timer.counter = 0xFF;
+        timer.modulo = 0x23;
+        timer.step(20);
+        timer.write(TMA_ADDR, 0x42);
+        assert_eq!(timer.counter, 0x42);
+        assert_eq!(timer.modulo, 0x42);
+    }
+
+    #[test]
+    fn test_write_div() {
+        let mut timer = Timer::new();
+        timer.write(DIV_ADDR, 0x12);
+        assert_eq!(timer.read(DIV_ADDR), 0x00);
+        assert_eq!(timer.system_counter, 0x0000);
+    }
+
+    #[test]
+    fn test_write_div_falling_edge() {
+        let mut timer = fast_timer();
+        timer.step(8);
+        timer.write(DIV_ADDR, 0x00);
+        assert_eq!(timer.counter, 0x01);
+    }
+
+    #[test]
+    fn test_write_tac_falling_edge() {
+        let mut timer = fast_timer();
+        timer.step(8);
+        timer.write(TAC_ADDR, 0x00);
+        assert_eq!(timer.counter, 0x01);
+    }
+
+    #[test]
+    fn test_write_tac() {
+        let mut timer = Timer::new();
+        timer.write(TAC_ADDR, 0x05);
+        assert_eq!(timer.read(TAC_ADDR), 0xFD);
+    }
+
+    #[test]
+    fn test_new_with_model() {
+        assert_eq!(Timer::new_with_model(Model::Dmg0).divider(), 0x18);
+        assert_eq!(Timer::new_with_model(Model::Mgb).divider(), 0xAB);
     }
 }
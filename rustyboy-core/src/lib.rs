@@ -1,9 +1,15 @@
 pub mod cartridge;
+pub mod cheats;
 pub mod cpu;
 pub mod gameboy;
+pub mod hooks;
 pub mod io;
 mod mbc;
 pub mod mmu;
+pub mod model;
+pub mod movie;
 pub mod ppu;
+pub mod ram_search;
+pub mod savestate;
 pub mod sysclock;
 mod util;
@@ -1,5 +1,6 @@
 use crate::cartridge::Cartridge;
-use crate::mbc::Mbc;
+use crate::mbc::{Mbc, RAM_BANK_SIZE};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 /// Banking modes supported by MBC1
 #[derive(Debug)]
@@ -34,8 +35,10 @@ impl Mbc1 {
         }
     }
 
+    /// Banks past the end of the ROM wrap around, as only the used bank lines are connected
     fn read_rom_bank(&self, bank: u8, offset: usize) -> u8 {
-        self.cart.rom[(bank as usize * 0x4000) + offset]
+        let bank = bank as usize % self.rom_banks();
+        self.cart.rom[(bank * 0x4000) + offset]
     }
 
     fn ram_bank_offset(&self) -> u16 {
@@ -101,7 +104,7 @@ impl Mbc for Mbc1 {
 
     fn read_byte_from_ram(&self, addr: usize) -> u8 {
         if self.ram_enabled {
-            self.cart.ram[self.ram_bank_offset() as usize + addr]
+            self.cart.ram[(self.ram_bank_offset() as usize + addr) % self.cart.ram.len()]
         } else {
             log::warn!("MBC1: Attempted to read from RAM when it is disabled");
             0xFF
@@ -110,10 +113,101 @@ impl Mbc for Mbc1 {
 
     fn write_byte_to_ram(&mut self, addr: usize, value: u8) {
         if self.ram_enabled {
-            let offset = self.ram_bank_offset();
-            self.cart.ram[offset as usize + addr] = value;
+            let offset = (self.ram_bank_offset() as usize + addr) % self.cart.ram.len();
+            self.cart.ram[offset] = value;
         } else {
             log::warn!("MBC1: Attempted to write to RAM when it is disabled");
         }
     }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank_offset() as usize / RAM_BANK_SIZE
+    }
+}
+
+impl Savestate for Mbc1 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.cart.ram);
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.ram_bank);
+        state.write_u8(match self.banking_mode {
+            BankingMode::Simple => 0,
+            BankingMode::Advanced => 1,
+        });
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.cart.ram)?;
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = state.read_u8()?;
+        self.ram_bank = state.read_u8()?;
+        if !(1..=0x1F).contains(&self.rom_bank) || self.ram_bank > 0x03 {
+            return Err(StateError::Corrupt);
+        }
+        self.banking_mode = match state.read_u8()? {
+            0 => BankingMode::Simple,
+            1 => BankingMode::Advanced,
+            _ => return Err(StateError::Corrupt),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peek_poke_banks() {
+        let mut cart = Cartridge::new();
+        cart.rom = vec![0x00; 0x4000 * 8];
+        cart.ram = vec![0x00; 0x2000 * 4];
+        let mut mbc = Mbc1::new(cart);
+        mbc.poke_rom(5, 0x0123, 0x42);
+        mbc.poke_ram(2, 0x0010, 0x24);
+
+        // Peeking ignores the banking registers and the RAM enable
+        assert_eq!(mbc.peek_rom(5, 0x0123), 0x42);
+        assert_eq!(mbc.peek_ram(2, 0x0010), 0x24);
+        assert_eq!(mbc.rom_bank(), 1);
+        assert_eq!(mbc.rom_banks(), 8);
+        assert_eq!(mbc.ram_banks(), 4);
+
+        mbc.write_byte_to_rom(0x0000, 0x0A);
+        mbc.write_byte_to_rom(0x2000, 0x05);
+        mbc.write_byte_to_rom(0x4000, 0x02);
+        mbc.write_byte_to_rom(0x6000, 0x01);
+        assert_eq!(mbc.rom_bank(), 5);
+        assert_eq!(mbc.ram_bank(), 2);
+        assert_eq!(mbc.read_byte_from_rom(0x4123), 0x42);
+        assert_eq!(mbc.read_byte_from_ram(0x0010), 0x24);
+    }
+
+    #[test]
+    fn test_out_of_range_banks_wrap() {
+        let mut cart = Cartridge::new();
+        cart.rom = vec![0x00; 0x4000 * 4];
+        cart.rom[0x4000 * 2] = 0x42;
+        cart.ram = vec![0x00; 0x800];
+        let mut mbc = Mbc1::new(cart);
+        mbc.write_byte_to_rom(0x0000, 0x0A);
+        mbc.write_byte_to_rom(0x2000, 0x06);
+        assert_eq!(mbc.read_byte_from_rom(0x4000), 0x42);
+
+        mbc.write_byte_to_ram(0x1810, 0x24);
+        assert_eq!(mbc.read_byte_from_ram(0x0010), 0x24);
+    }
 }
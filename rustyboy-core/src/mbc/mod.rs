@@ -1,8 +1,17 @@
 use crate::cartridge::{Cartridge, CartridgeType};
+use crate::savestate::Savestate;
 
 mod mbc1;
 pub mod rom_only;
 
+/// Is there an MBC implementation for a cartridge type
+pub fn is_supported(cart_type: &CartridgeType) -> bool {
+    matches!(
+        cart_type,
+        CartridgeType::RomOnly | CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery
+    )
+}
+
 pub fn from_cartridge(cart: Cartridge) -> Box<dyn Mbc> {
     match cart.cart_type {
         CartridgeType::RomOnly => {
@@ -22,9 +31,59 @@ pub fn from_cartridge(cart: Cartridge) -> Box<dyn Mbc> {
     }
 }
 
-pub trait Mbc {
+/// Size of a ROM bank
+pub const ROM_BANK_SIZE: usize = 0x4000;
+/// Size of a cartridge RAM bank
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+pub trait Mbc: Savestate + Send {
     fn read_byte_from_rom(&self, addr: usize) -> u8;
     fn write_byte_to_rom(&mut self, addr: usize, value: u8);
     fn read_byte_from_ram(&self, addr: usize) -> u8;
     fn write_byte_to_ram(&mut self, addr: usize, value: u8);
+
+    fn cartridge(&self) -> &Cartridge;
+    fn cartridge_mut(&mut self) -> &mut Cartridge;
+    /// The ROM bank mapped into 0x4000-0x7FFF
+    fn rom_bank(&self) -> usize;
+    /// The RAM bank mapped into 0xA000-0xBFFF
+    fn ram_bank(&self) -> usize;
+
+    /// Number of 16 KiB ROM banks
+    fn rom_banks(&self) -> usize {
+        self.cartridge().rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    /// Number of 8 KiB RAM banks
+    fn ram_banks(&self) -> usize {
+        self.cartridge().ram.len().div_ceil(RAM_BANK_SIZE)
+    }
+
+    /// Read a byte from any ROM bank, without going through the banking registers
+    fn peek_rom(&self, bank: usize, offset: usize) -> u8 {
+        let rom = &self.cartridge().rom;
+        rom.get(bank * ROM_BANK_SIZE + offset % ROM_BANK_SIZE).copied().unwrap_or(0xFF)
+    }
+
+    /// Patch a byte of any ROM bank, writes to ROM normally go to the banking registers instead
+    fn poke_rom(&mut self, bank: usize, offset: usize, value: u8) {
+        let rom = &mut self.cartridge_mut().rom;
+        if let Some(byte) = rom.get_mut(bank * ROM_BANK_SIZE + offset % ROM_BANK_SIZE) {
+            *byte = value;
+        }
+    }
+
+    /// Read a byte from any RAM bank, even while RAM is disabled
+    fn peek_ram(&self, bank: usize, offset: usize) -> u8 {
+        let ram = &self.cartridge().ram;
+        ram.get(bank * RAM_BANK_SIZE + offset % RAM_BANK_SIZE).copied().unwrap_or(0xFF)
+    }
+
+    /// Write a byte to any RAM bank, even while RAM is disabled
+    fn poke_ram(&mut self, bank: usize, offset: usize, value: u8) {
+        let ram = &mut self.cartridge_mut().ram;
+        if let Some(byte) = ram.get_mut(bank * RAM_BANK_SIZE + offset % RAM_BANK_SIZE) {
+            *byte = value;
+        }
+    }
 }
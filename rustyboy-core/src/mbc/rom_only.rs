@@ -1,5 +1,6 @@
 use crate::cartridge::Cartridge;
 use crate::mbc::Mbc;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 pub struct RomOnly {
     pub cart: Cartridge,
@@ -25,11 +26,38 @@ impl Mbc for RomOnly {
     }
 
     fn read_byte_from_ram(&self, addr: usize) -> u8 {
-        self.cart.ram[addr]
+        self.cart.ram[addr % self.cart.ram.len()]
     }
 
     fn write_byte_to_ram(&mut self, addr: usize, value: u8) {
-        self.cart.ram[addr] = value;
+        let len = self.cart.ram.len();
+        self.cart.ram[addr % len] = value;
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    fn ram_bank(&self) -> usize {
+        0
+    }
+}
+
+impl Savestate for RomOnly {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.cart.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes(&mut self.cart.ram)
     }
 }
 
@@ -62,4 +90,17 @@ mod tests {
         assert_eq!(rom_only.read_byte_from_ram(0x0000), 0xFF);
         assert_eq!(rom_only.read_byte_from_ram(0x1FFF), 0x00);
     }
+
+    #[test]
+    fn test_peek_poke() {
+        let mut rom_only = RomOnly::new(Cartridge::new());
+        assert_eq!(rom_only.rom_banks(), 2);
+        assert_eq!(rom_only.ram_banks(), 1);
+        rom_only.poke_rom(1, 0x0010, 0x42);
+        assert_eq!(rom_only.read_byte_from_rom(0x4010), 0x42);
+        assert_eq!(rom_only.peek_rom(1, 0x0010), 0x42);
+        assert_eq!(rom_only.peek_rom(2, 0x0000), 0xFF);
+        rom_only.poke_ram(0, 0x0001, 0x24);
+        assert_eq!(rom_only.peek_ram(0, 0x0001), 0x24);
+    }
 }
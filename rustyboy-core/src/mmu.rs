@@ -1,17 +1,23 @@
-use crate::cartridge::Cartridge;
+use std::ops::RangeInclusive;
+
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cheats::Cheats;
 use crate::cpu::interrupts::{
     Interrupt, InterruptState, INTERRUPT_ENABLE_ADDR, INTERRUPT_FLAG_ADDR,
 };
-use crate::io::joypad::Joypad;
-use crate::io::timer::{Timer, DIV_ADDR, TAC_ADDR, TIMA_ADDR, TMA_ADDR};
+use crate::hooks::{Access, MemoryHooks};
+use crate::io::joypad::{Joypad, JOYPAD_ADDR};
+use crate::io::serial::{Serial, SB_ADDR, SC_ADDR};
+use crate::io::sound::{Sound, SOUND_END, SOUND_START};
+use crate::io::timer::{Timer, DIV_ADDR, TAC_ADDR};
+use crate::io::IoDevice;
 use crate::mbc;
 use crate::mbc::rom_only::RomOnly;
-use crate::mbc::Mbc;
-use crate::ppu::ppu::{
-    Ppu, BGP_ADDR, LCDC_ADDR, LYC_ADDR, LY_ADDR, OBP0_ADDR, OBP1_ADDR, SCX_ADDR, SCY_ADDR,
-    STAT_ADDR, WX_ADDR, WY_ADDR, OAM_DMA_ADDR,
-};
-use crate::ppu::stat::Mode;
+use crate::mbc::{Mbc, ROM_BANK_SIZE};
+use crate::model::Model;
+use crate::ppu::ppu::{Ppu, LCDC_ADDR, OAM_DMA_ADDR, VBK_ADDR, VRAM_BANK_SIZE, WX_ADDR};
+use crate::util::crc32::crc32;
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 const ROM_START: usize = 0x0000;
 const ROM_END: usize = 0x7FFF;
@@ -24,7 +30,9 @@ const CART_RAM_END: usize = 0xBFFF;
 
 const WRAM_START: usize = 0xC000;
 const WRAM_END: usize = 0xDFFF;
-const WRAM_SIZE: usize = WRAM_END - WRAM_START + 1;
+const WRAM_BANK_SIZE: usize = 0x1000;
+/// The CGB has 8 banks of WRAM, the DMG only uses the first two
+const WRAM_SIZE: usize = WRAM_BANK_SIZE * 8;
 
 const ECHO_RAM_START: usize = 0xE000;
 const ECHO_RAM_END: usize = 0xFDFF;
@@ -35,85 +43,257 @@ const OAM_END: usize = 0xFE9F;
 const UNUSED_START: usize = 0xFEA0;
 const UNUSED_END: usize = 0xFEFF;
 
-// TODO Proper memory mapped IO
 const IO_START: usize = 0xFF00;
 const IO_END: usize = 0xFF7F;
 const IO_SIZE: usize = IO_END - IO_START + 1;
 
+const KEY1_ADDR: usize = 0xFF4D;
+const SVBK_ADDR: usize = 0xFF70;
+
+/// Number of bytes copied by an OAM DMA transfer, one per M-cycle
+const DMA_LENGTH: usize = 0xA0;
+
 const HRAM_START: usize = 0xFF80;
 const HRAM_END: usize = 0xFFFE;
 const HRAM_SIZE: usize = HRAM_END - HRAM_START + 1;
 
+/// The owner of an IO register
+#[derive(Copy, Clone)]
+enum IoSlot {
+    Joypad,
+    Serial,
+    Timer,
+    Interrupts,
+    Sound,
+    Ppu,
+    /// OAM DMA needs access to the whole bus, so it's handled by the MMU itself
+    Dma,
+    /// The WRAM bank register, handled by the MMU itself
+    WramBank,
+    /// The CGB speed switch register, handled by the MMU itself
+    Speed,
+    /// A device added with `register_io_device`, indexed into `io_devices`
+    External(usize),
+}
+
 /// The MMU (Memory Management Unit) is responsible for managing the gameboy's memory
 pub struct Memory {
+    /// The hardware model being emulated
+    model: Model,
     /// The cartridge's data
     cart: Box<dyn Mbc>,
+    /// CRC-32 of the whole ROM, identifies the game for movies
+    rom_checksum: u32,
     pub cart_title: String,
+    /// Game Genie patches applied to ROM reads and GameShark writes applied every frame
+    pub cheats: Cheats,
+    /// Addresses whose accesses by the CPU are recorded for the frontend
+    pub hooks: MemoryHooks,
     pub joypad: Joypad,
     /// Interrupt registers
     pub interrupts: InterruptState,
     /// Timer
     pub timer: Timer,
+    /// Serial port
+    pub serial: Serial,
+    /// Sound registers
+    pub sound: Sound,
     /// The PPU
     pub ppu: Ppu,
     /// WRAM
     wram: [u8; WRAM_SIZE],
-    /// IO Registers
-    io: [u8; IO_SIZE],
+    /// The WRAM bank mapped into 0xD000-0xDFFF, only switchable on the CGB
+    wram_bank: usize,
+    /// Is the CGB running in double speed mode
+    double_speed: bool,
+    /// Will the next STOP switch speeds instead of stopping the CPU
+    speed_switch_armed: bool,
+    /// The last value written to the OAM DMA register
+    dma: u8,
+    /// M-cycles since the running OAM DMA transfer was started, None if no transfer is running
+    dma_progress: Option<usize>,
+    /// Clock cycles passed since power on
+    clock_cycles: u64,
+    /// The owner of each IO register, unmapped registers are None
+    io_map: [Option<IoSlot>; IO_SIZE],
+    /// Devices added with `register_io_device`
+    io_devices: Vec<Box<dyn IoDevice>>,
     /// High RAM
     hram: [u8; HRAM_SIZE],
-    joypad_status: u8,
-    joypad_select: u8,
 }
 
 impl Memory {
     /// Create a new empty Memory
     pub fn new() -> Self {
-        Memory {
-            cart: Box::new(RomOnly::new(Cartridge::new())),
+        let cart = Cartridge::new();
+        let mut mem = Memory {
+            model: Model::Dmg,
+            rom_checksum: crc32(&cart.rom),
+            cart: Box::new(RomOnly::new(cart)),
             cart_title: String::new(),
+            cheats: Cheats::new(),
+            hooks: MemoryHooks::new(),
             joypad: Joypad::new(),
             interrupts: InterruptState::new(),
             timer: Timer::new(),
+            serial: Serial::new(),
+            sound: Sound::new(),
             ppu: Ppu::new(),
             wram: [0xFF; WRAM_SIZE],
-            io: [0xFF; IO_SIZE],
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            dma: 0xFF,
+            dma_progress: None,
+            clock_cycles: 0,
+            io_map: [None; IO_SIZE],
+            io_devices: Vec::new(),
             hram: [0xFF; HRAM_SIZE],
-            joypad_status: 0xFF,
-            joypad_select: 0xFF,
-        }
+        };
+        mem.map_io_registers();
+        mem
     }
 
     /// Create a new Memory with a ROM file
-    pub fn new_with_rom(rom_name: &str) -> Self {
-        let cart = Cartridge::new_from_rom(rom_name);
+    /// The model is picked based on the cartridge header
+    pub fn new_with_rom(rom_name: &str) -> Result<Self, CartridgeError> {
+        let cart = Cartridge::new_from_rom(rom_name)?;
+        let model = Model::detect(&cart);
+        Ok(Self::new_with_cartridge(cart, model))
+    }
+
+    /// Create a new Memory with a loaded cartridge for a given model
+    pub fn new_with_cartridge(cart: Cartridge, model: Model) -> Self {
         let title = cart.title.clone();
-        Memory {
+        let mut mem = Memory {
+            model,
+            rom_checksum: crc32(&cart.rom),
             cart: mbc::from_cartridge(cart),
             cart_title: title,
-            joypad: Joypad::new(),
+            cheats: Cheats::new(),
+            hooks: MemoryHooks::new(),
+            joypad: Joypad::new_with_model(model),
             interrupts: InterruptState::new(),
-            timer: Timer::new(),
+            timer: Timer::new_with_model(model),
+            serial: Serial::new(),
+            sound: Sound::new(),
             ppu: Ppu::new(),
             wram: [0xFF; WRAM_SIZE],
-            io: [0xFF; IO_SIZE],
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            // The CGB boot ROM leaves the last OAM DMA source at 0x00
+            dma: if model.is_cgb() { 0x00 } else { 0xFF },
+            dma_progress: None,
+            clock_cycles: 0,
+            io_map: [None; IO_SIZE],
+            io_devices: Vec::new(),
             hram: [0xFF; HRAM_SIZE],
-            joypad_status: 0xFF,
-            joypad_select: 0xFF,
+        };
+        mem.map_io_registers();
+        mem
+    }
+
+    /// Map the registers of the built in devices for the current model
+    fn map_io_registers(&mut self) {
+        self.map_io(JOYPAD_ADDR..=JOYPAD_ADDR, IoSlot::Joypad);
+        self.map_io(SB_ADDR..=SC_ADDR, IoSlot::Serial);
+        self.map_io(DIV_ADDR..=TAC_ADDR, IoSlot::Timer);
+        self.map_io(INTERRUPT_FLAG_ADDR..=INTERRUPT_FLAG_ADDR, IoSlot::Interrupts);
+        self.map_io(SOUND_START..=SOUND_END, IoSlot::Sound);
+        self.map_io(LCDC_ADDR..=WX_ADDR, IoSlot::Ppu);
+        self.map_io(OAM_DMA_ADDR..=OAM_DMA_ADDR, IoSlot::Dma);
+        if self.model.is_cgb() {
+            self.map_io(VBK_ADDR..=VBK_ADDR, IoSlot::Ppu);
+            self.map_io(SVBK_ADDR..=SVBK_ADDR, IoSlot::WramBank);
+            self.map_io(KEY1_ADDR..=KEY1_ADDR, IoSlot::Speed);
         }
     }
 
+    fn map_io(&mut self, range: RangeInclusive<usize>, slot: IoSlot) {
+        for addr in range {
+            self.io_map[addr - IO_START] = Some(slot);
+        }
+    }
+
+    /// Map a device into a range of the IO registers, replacing whatever was mapped there before
+    pub fn register_io_device(&mut self, range: RangeInclusive<usize>, device: Box<dyn IoDevice>) {
+        assert!(
+            *range.start() >= IO_START && *range.end() <= IO_END,
+            "IO device range {:04X}-{:04X} is outside of the IO registers",
+            range.start(),
+            range.end()
+        );
+        self.io_devices.push(device);
+        self.map_io(range, IoSlot::External(self.io_devices.len() - 1));
+    }
+
+    /// The inserted cartridge, its RAM is what battery saves store
+    pub fn cartridge(&self) -> &Cartridge {
+        self.cart.cartridge()
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        self.cart.cartridge_mut()
+    }
+
+    /// The hardware model being emulated
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// CRC-32 of the cartridge ROM
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    /// Is the CGB running in double speed mode
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Will the next STOP switch speeds instead of stopping the CPU
+    pub fn speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    /// Switch between normal and double speed mode
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.timer.write(DIV_ADDR, 0x00);
+        log::debug!("Switched to {} speed", if self.double_speed { "double" } else { "normal" });
+    }
+
+    /// Enter low power mode for STOP, DIV is reset and the LCD is blanked
+    /// Nothing is stepped until the CPU wakes up again
+    pub fn stop(&mut self) {
+        self.timer.write(DIV_ADDR, 0x00);
+        self.ppu.blank();
+    }
+
     /// Step the IO devices
+    /// In double speed mode the PPU keeps running at normal speed
     pub fn step(&mut self, clock_cycles: u8) {
         self.timer.step(clock_cycles);
-        self.ppu.step(clock_cycles);
-        self.joypad_status = self.joypad.read_value(self.joypad_select);
-        
+        self.serial.step(clock_cycles);
+        self.ppu.step(if self.double_speed { clock_cycles / 2 } else { clock_cycles });
+
         if self.timer.interrupt_fired {
             self.timer.interrupt_fired = false;
             self.interrupts.requested_interrupts |= Interrupt::Timer as u8;
         }
 
+        if self.joypad.interrupt_fired {
+            self.joypad.interrupt_fired = false;
+            self.interrupts.requested_interrupts |= Interrupt::Joypad as u8;
+        }
+
+        if self.serial.interrupt_fired {
+            self.serial.interrupt_fired = false;
+            self.interrupts.requested_interrupts |= Interrupt::Serial as u8;
+        }
+
         if self.ppu.lcd_interrupt_fired {
             self.ppu.lcd_interrupt_fired = false;
             self.interrupts.requested_interrupts |= Interrupt::LcdStat as u8;
@@ -125,45 +305,94 @@ impl Memory {
         }
     }
 
+    /// Advance the rest of the system by one M-cycle
+    /// Used directly by the CPU for internal cycles that don't access the bus
+    pub fn tick(&mut self) {
+        self.step(4);
+        self.step_dma();
+        self.clock_cycles += 4;
+    }
+
+    /// Clock cycles passed since power on
+    pub fn clock_cycles(&self) -> u64 {
+        self.clock_cycles
+    }
+
+    /// Reads a byte as part of a CPU instruction, taking one M-cycle
+    pub fn read_cycle<T: Into<usize>>(&mut self, addr: T) -> u8 {
+        let addr = addr.into();
+        self.tick();
+        let v = self.get_byte(addr);
+        self.hooks.record(Access::Read, addr as u16, v);
+        v
+    }
+
+    /// Fetches the opcode of the next instruction, taking one M-cycle
+    pub fn fetch_cycle(&mut self, addr: u16) -> u8 {
+        self.tick();
+        let v = self.get_byte(addr);
+        self.hooks.record(Access::Execute, addr, v);
+        v
+    }
+
+    /// Writes a byte as part of a CPU instruction, taking one M-cycle
+    pub fn write_cycle<T: Into<usize>>(&mut self, addr: T, v: u8) {
+        let addr = addr.into();
+        self.tick();
+        self.set_byte(addr, v);
+        self.hooks.record(Access::Write, addr as u16, v);
+    }
+
+    /// Copy the next byte of a running OAM DMA transfer
+    fn step_dma(&mut self) {
+        let progress = match self.dma_progress {
+            Some(progress) => progress,
+            None => return,
+        };
+
+        // The first M-cycle after the DMA register is written is spent setting up the transfer
+        if progress > 0 {
+            let i = progress - 1;
+            self.ppu.oam[i] = self.get_byte(((self.dma as usize) << 8) + i);
+        }
+        self.dma_progress = if progress == DMA_LENGTH { None } else { Some(progress + 1) };
+    }
+
+    /// OAM can't be accessed by the CPU while a DMA transfer is copying to it
+    fn oam_locked(&self) -> bool {
+        matches!(self.dma_progress, Some(progress) if progress > 0)
+    }
+
+    /// Do the GameShark writes of the enabled cheats, called once per frame
+    pub fn apply_ram_cheats(&mut self) {
+        let cheats = std::mem::take(&mut self.cheats);
+        for (addr, bank, value) in cheats.ram_writes() {
+            self.poke(addr, bank, value);
+        }
+        self.cheats = cheats;
+    }
+
     /// Reads a byte from the memory address space
     pub fn get_byte<T: Into<usize>>(&self, addr: T) -> u8 {
         let addr = addr.into();
         match addr {
-            ROM_START..=ROM_END => self.cart.read_byte_from_rom(addr),
-            VRAM_START..=VRAM_END => self.ppu.vram[addr - VRAM_START],
+            ROM_START..=ROM_END => self.cheats.patch_rom(addr as u16, self.cart.read_byte_from_rom(addr)),
+            VRAM_START..=VRAM_END => self.ppu.vram[self.vram_offset(addr - VRAM_START)],
             CART_RAM_START..=CART_RAM_END => self.cart.read_byte_from_ram(addr - CART_RAM_START),
-            WRAM_START..=WRAM_END => self.wram[addr - WRAM_START],
+            WRAM_START..=WRAM_END => self.wram[self.wram_offset(addr - WRAM_START)],
             ECHO_RAM_START..=ECHO_RAM_END => {
                 log::warn!("Attempted prohibited read from echo RAM {}", addr);
-                self.wram[addr - ECHO_RAM_START]
+                self.wram[self.wram_offset(addr - ECHO_RAM_START)]
             }
+            OAM_START..=OAM_END if self.oam_locked() => 0xFF,
             OAM_START..=OAM_END => self.ppu.oam[addr - OAM_START],
             UNUSED_START..=UNUSED_END => {
                 log::warn!("Attempted prohibited read from unused memory {}", addr);
                 0xFF
             }
-            IO_START..=IO_END => match addr {
-                0xFF00 => self.joypad_status,
-                DIV_ADDR => self.timer.divider,
-                TIMA_ADDR => self.timer.counter,
-                TMA_ADDR => self.timer.modulo,
-                TAC_ADDR => self.timer.control,
-                INTERRUPT_FLAG_ADDR => self.interrupts.requested_interrupts,
-                LCDC_ADDR => self.ppu.lcdc.into(),
-                STAT_ADDR => self.ppu.stat.into(),
-                SCY_ADDR => self.ppu.scy,
-                SCX_ADDR => self.ppu.scx,
-                LY_ADDR => self.ppu.ly,
-                LYC_ADDR => self.ppu.lyc,
-                BGP_ADDR => self.ppu.bgp,
-                OBP0_ADDR => self.ppu.obp0,
-                OBP1_ADDR => self.ppu.obp1,
-                WY_ADDR => self.ppu.wy,
-                WX_ADDR => self.ppu.wx,
-                _ => self.io[addr - IO_START],
-            },
+            IO_START..=IO_END => self.read_io(addr),
             HRAM_START..=HRAM_END => self.hram[addr - HRAM_START],
-            INTERRUPT_ENABLE_ADDR => self.interrupts.enabled_interrupts,
+            INTERRUPT_ENABLE_ADDR => self.interrupts.read(addr),
             _ => {
                 log::error!("Attempted to read from invalid memory address {}", addr);
                 0xFF
@@ -178,77 +407,200 @@ impl Memory {
             "Writing {:02X} to {:04X}, replacing {:02X}",
             v,
             addr,
-            self.get_byte(addr)
+            self.peek(addr as u16, None)
         );
         match addr {
             ROM_START..=ROM_END => self.cart.write_byte_to_rom(addr, v),
             VRAM_START..=VRAM_END => {
                 self.ppu.vram_changed = true;
-                self.ppu.vram[addr - VRAM_START] = v
+                self.ppu.vram[self.vram_offset(addr - VRAM_START)] = v
             }
             CART_RAM_START..=CART_RAM_END => self.cart.write_byte_to_ram(addr - CART_RAM_START, v),
-            WRAM_START..=WRAM_END => self.wram[addr - WRAM_START] = v,
+            WRAM_START..=WRAM_END => self.wram[self.wram_offset(addr - WRAM_START)] = v,
             ECHO_RAM_START..=ECHO_RAM_END => {
                 log::warn!("Attempted prohibited write to echo RAM {}", addr);
-                self.wram[addr - ECHO_RAM_START] = v
+                self.wram[self.wram_offset(addr - ECHO_RAM_START)] = v
+            }
+            OAM_START..=OAM_END if self.oam_locked() => {
+                log::debug!("Ignoring write to OAM {:04X} during DMA", addr);
             }
             OAM_START..=OAM_END => self.ppu.oam[addr - OAM_START] = v,
             UNUSED_START..=UNUSED_END => {
                 log::warn!("Attempted prohibited write to unused memory {}", addr);
             }
 
-            IO_START..=IO_END => {
-                match addr {
-                    0xFF00 => {
-                        self.joypad_select = v;
-                    }
-                    DIV_ADDR => self.timer.divider = 0, // All writes to DIV reset it to 0
-                    TIMA_ADDR => self.timer.counter = v,
-                    TMA_ADDR => self.timer.modulo = v,
-                    TAC_ADDR => self.timer.control = v,
-                    INTERRUPT_FLAG_ADDR => self.interrupts.requested_interrupts = v,
-                    LCDC_ADDR => {
-                        if !self.ppu.lcdc.enabled && v & 0x80 != 0 {
-                            // If the LCD is turned on, switch to mode 2
-                            self.ppu.stat.mode = Mode::OamSearch;
-                        }
-                        if v & 0x80 == 0 && self.ppu.lcdc.enabled {
-                            // If the LCD is turned off, switch to mode 0
-                            self.ppu.stat.mode = Mode::HBlank;
-                            self.ppu.ly = 0;
-                        }
-                        self.ppu.lcdc.set(v);
-                    }
-                    STAT_ADDR => self.ppu.stat.set(v),
-                    SCY_ADDR => self.ppu.scy = v,
-                    SCX_ADDR => self.ppu.scx = v,
-                    LY_ADDR => self.ppu.ly = v,
-                    LYC_ADDR => {
-                        self.ppu.lyc = v;
-                        self.ppu.check_lyc(); // Check if LYC=LY
-                    }
-                    OAM_DMA_ADDR => {
-                        let start = (v as u16) << 8;
-                        for i in 0..0xA0 {
-                            self.ppu.oam[i] = self.get_byte(start as usize + i);
-                        }
-                    }
-                    BGP_ADDR => self.ppu.bgp = v,
-                    OBP0_ADDR => self.ppu.obp0 = v,
-                    OBP1_ADDR => self.ppu.obp1 = v,
-                    WY_ADDR => self.ppu.wy = v,
-                    WX_ADDR => self.ppu.wx = v,
-                    _ => self.io[addr - IO_START] = v,
-                }
-            }
+            IO_START..=IO_END => self.write_io(addr, v),
             HRAM_START..=HRAM_END => self.hram[addr - HRAM_START] = v,
-            INTERRUPT_ENABLE_ADDR => self.interrupts.enabled_interrupts = v,
+            INTERRUPT_ENABLE_ADDR => self.interrupts.write(addr, v),
             _ => {
                 log::error!("Attempted to write to invalid memory address {}", addr);
             }
         }
     }
 
+    /// Reads an IO register from the device it's mapped to
+    fn read_io(&self, addr: usize) -> u8 {
+        match self.io_map[addr - IO_START] {
+            Some(IoSlot::Joypad) => self.joypad.read(addr),
+            Some(IoSlot::Serial) => self.serial.read(addr),
+            Some(IoSlot::Timer) => self.timer.read(addr),
+            Some(IoSlot::Interrupts) => self.interrupts.read(addr),
+            Some(IoSlot::Sound) => self.sound.read(addr),
+            Some(IoSlot::Ppu) => self.ppu.read(addr),
+            Some(IoSlot::Dma) => self.dma,
+            Some(IoSlot::WramBank) => 0xF8 | self.wram_bank as u8,
+            Some(IoSlot::Speed) => {
+                0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+            }
+            Some(IoSlot::External(i)) => self.io_devices[i].read(addr),
+            // Nothing drives the bus for unmapped registers, so all bits read as 1
+            None => 0xFF,
+        }
+    }
+
+    /// Writes an IO register to the device it's mapped to
+    fn write_io(&mut self, addr: usize, v: u8) {
+        match self.io_map[addr - IO_START] {
+            Some(IoSlot::Joypad) => self.joypad.write(addr, v),
+            Some(IoSlot::Serial) => self.serial.write(addr, v),
+            Some(IoSlot::Timer) => self.timer.write(addr, v),
+            Some(IoSlot::Interrupts) => self.interrupts.write(addr, v),
+            Some(IoSlot::Sound) => self.sound.write(addr, v),
+            Some(IoSlot::Ppu) => self.ppu.write(addr, v),
+            Some(IoSlot::Dma) => {
+                // The transfer copies one byte per M-cycle, see step_dma
+                self.dma = v;
+                self.dma_progress = Some(0);
+            }
+            Some(IoSlot::WramBank) => {
+                // Bank 0 can't be mapped into 0xD000-0xDFFF, selecting it maps bank 1
+                self.wram_bank = ((v & 0x07) as usize).max(1);
+            }
+            Some(IoSlot::Speed) => self.speed_switch_armed = v & 0x01 != 0,
+            Some(IoSlot::External(i)) => self.io_devices[i].write(addr, v),
+            None => log::debug!("Ignoring write of {:02X} to unmapped IO register {:04X}", v, addr),
+        }
+    }
+
+    /// Sets an IO register without the side effects of a CPU write
+    /// DIV isn't reset, DMA doesn't start a transfer and LCDC doesn't change the PPU mode
+    fn poke_io(&mut self, addr: usize, v: u8) {
+        match self.io_map[addr - IO_START] {
+            Some(IoSlot::Joypad) => self.joypad.poke(addr, v),
+            Some(IoSlot::Serial) => self.serial.poke(addr, v),
+            Some(IoSlot::Timer) => self.timer.poke(addr, v),
+            Some(IoSlot::Interrupts) => self.interrupts.poke(addr, v),
+            Some(IoSlot::Sound) => self.sound.poke(addr, v),
+            Some(IoSlot::Ppu) => self.ppu.poke(addr, v),
+            Some(IoSlot::Dma) => self.dma = v,
+            Some(IoSlot::External(i)) => self.io_devices[i].poke(addr, v),
+            Some(IoSlot::WramBank) | Some(IoSlot::Speed) | None => self.write_io(addr, v),
+        }
+    }
+
+    /// Offset into WRAM for an offset into 0xC000-0xDFFF based on the current bank
+    fn wram_offset(&self, offset: usize) -> usize {
+        if offset < WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.wram_bank * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE
+        }
+    }
+
+    /// Offset into VRAM for an offset into 0x8000-0x9FFF based on the current bank
+    fn vram_offset(&self, offset: usize) -> usize {
+        self.ppu.vram_bank * VRAM_BANK_SIZE + offset
+    }
+
+    /// Number of banks that can be peeked at an address, 1 for memory that isn't banked
+    pub fn banks(&self, addr: u16) -> usize {
+        match addr as usize {
+            ROM_START..=ROM_END => self.cart.rom_banks(),
+            VRAM_START..=VRAM_END if self.model.is_cgb() => 2,
+            CART_RAM_START..=CART_RAM_END => self.cart.ram_banks().max(1),
+            // Banks are numbered like SVBK, so bank 0 at 0xD000 is the same memory as 0xC000
+            0xD000..=WRAM_END if self.model.is_cgb() => WRAM_SIZE / WRAM_BANK_SIZE,
+            0xD000..=WRAM_END => 2,
+            ECHO_RAM_START..=ECHO_RAM_END => self.banks(addr - 0x2000),
+            _ => 1,
+        }
+    }
+
+    /// The bank currently mapped at an address
+    /// 0x0000-0x3FFF and 0xC000-0xCFFF are reported as bank 0, echo RAM as the WRAM it mirrors
+    pub fn current_bank(&self, addr: u16) -> usize {
+        match addr as usize {
+            0x4000..=ROM_END => self.cart.rom_bank(),
+            VRAM_START..=VRAM_END => self.ppu.vram_bank,
+            CART_RAM_START..=CART_RAM_END => self.cart.ram_bank(),
+            0xD000..=WRAM_END => self.wram_bank,
+            ECHO_RAM_START..=ECHO_RAM_END => self.current_bank(addr - 0x2000),
+            _ => 0,
+        }
+    }
+
+    /// Read a byte from any bank without side effects, for debuggers and cheats
+    /// Unlike `get_byte` this ignores the OAM DMA lock and cartridge RAM enable, and doesn't log
+    /// If no bank is given the one currently mapped at the address is used
+    pub fn peek(&self, addr: u16, bank: Option<usize>) -> u8 {
+        let bank = bank.unwrap_or_else(|| self.current_bank(addr));
+        let addr = addr as usize;
+        match addr {
+            ROM_START..=ROM_END => self.cart.peek_rom(bank, addr % ROM_BANK_SIZE),
+            VRAM_START..=VRAM_END => self
+                .ppu
+                .vram
+                .get(bank * VRAM_BANK_SIZE + addr - VRAM_START)
+                .copied()
+                .unwrap_or(0xFF),
+            CART_RAM_START..=CART_RAM_END => self.cart.peek_ram(bank, addr - CART_RAM_START),
+            WRAM_START..=0xCFFF => self.wram[addr - WRAM_START],
+            0xD000..=WRAM_END => self
+                .wram
+                .get(bank * WRAM_BANK_SIZE + addr - 0xD000)
+                .copied()
+                .unwrap_or(0xFF),
+            ECHO_RAM_START..=ECHO_RAM_END => self.peek((addr - 0x2000) as u16, Some(bank)),
+            OAM_START..=OAM_END => self.ppu.oam[addr - OAM_START],
+            UNUSED_START..=UNUSED_END => 0xFF,
+            // IO reads don't change device state
+            IO_START..=IO_END => self.read_io(addr),
+            HRAM_START..=HRAM_END => self.hram[addr - HRAM_START],
+            _ => self.interrupts.read(addr),
+        }
+    }
+
+    /// Write a byte to any bank without side effects, for debuggers and cheats
+    /// ROM is patched instead of writing to the banking registers, and the OAM DMA lock and
+    /// cartridge RAM enable are ignored
+    /// IO registers are set through their device without the side effects of a CPU write
+    pub fn poke(&mut self, addr: u16, bank: Option<usize>, v: u8) {
+        let bank = bank.unwrap_or_else(|| self.current_bank(addr));
+        let addr = addr as usize;
+        match addr {
+            ROM_START..=ROM_END => self.cart.poke_rom(bank, addr % ROM_BANK_SIZE, v),
+            VRAM_START..=VRAM_END => {
+                if let Some(byte) = self.ppu.vram.get_mut(bank * VRAM_BANK_SIZE + addr - VRAM_START) {
+                    *byte = v;
+                    self.ppu.vram_changed = true;
+                }
+            }
+            CART_RAM_START..=CART_RAM_END => self.cart.poke_ram(bank, addr - CART_RAM_START, v),
+            WRAM_START..=0xCFFF => self.wram[addr - WRAM_START] = v,
+            0xD000..=WRAM_END => {
+                if let Some(byte) = self.wram.get_mut(bank * WRAM_BANK_SIZE + addr - 0xD000) {
+                    *byte = v;
+                }
+            }
+            ECHO_RAM_START..=ECHO_RAM_END => self.poke((addr - 0x2000) as u16, Some(bank), v),
+            OAM_START..=OAM_END => self.ppu.oam[addr - OAM_START] = v,
+            UNUSED_START..=UNUSED_END => {}
+            IO_START..=IO_END => self.poke_io(addr, v),
+            HRAM_START..=HRAM_END => self.hram[addr - HRAM_START] = v,
+            _ => self.interrupts.write(addr, v),
+        }
+    }
+
     /// Reads a word from the memory address space
     pub fn get_word<T: Into<usize>>(&self, addr: T) -> u16 {
         let addr = addr.into();
@@ -267,9 +619,60 @@ impl Memory {
     }
 }
 
+/// Cheats, hooks and registered IO devices are set up by the frontend and aren't part of the state
+impl Savestate for Memory {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.cart.save_state(state);
+        state.write_bytes(&self.wram);
+        state.write_u8(self.wram_bank as u8);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.speed_switch_armed);
+        state.write_u8(self.dma);
+        state.write_bool(self.dma_progress.is_some());
+        state.write_u8(self.dma_progress.unwrap_or(0) as u8);
+        state.write_u64(self.clock_cycles);
+        state.write_bytes(&self.hram);
+        self.joypad.save_state(state);
+        self.interrupts.save_state(state);
+        self.timer.save_state(state);
+        self.serial.save_state(state);
+        self.sound.save_state(state);
+        self.ppu.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cart.load_state(state)?;
+        state.read_bytes(&mut self.wram)?;
+        self.wram_bank = state.read_u8()? as usize;
+        if !(1..WRAM_SIZE / WRAM_BANK_SIZE).contains(&self.wram_bank) {
+            return Err(StateError::Corrupt);
+        }
+        self.double_speed = state.read_bool()?;
+        self.speed_switch_armed = state.read_bool()?;
+        self.dma = state.read_u8()?;
+        let dma_running = state.read_bool()?;
+        let dma_progress = state.read_u8()? as usize;
+        if dma_progress > DMA_LENGTH {
+            return Err(StateError::Corrupt);
+        }
+        self.dma_progress = dma_running.then_some(dma_progress);
+        self.clock_cycles = state.read_u64()?;
+        state.read_bytes(&mut self.hram)?;
+        self.joypad.load_state(state)?;
+        self.interrupts.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.sound.load_state(state)?;
+        self.ppu.load_state(state)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cartridge::CartridgeType;
+    use crate::cheats::Cheat;
+    use crate::io::joypad::Key;
 
     #[test]
     fn test_new() {
@@ -279,7 +682,7 @@ mod tests {
 
     #[test]
     fn test_new_with_rom() {
-        let mem = Memory::new_with_rom("resources/test-rom.gb");
+        let mem = Memory::new_with_rom("resources/test-rom.gb").unwrap();
         assert_eq!(mem.get_byte(0x0101 as usize), 0xC3);
     }
 
@@ -332,8 +735,14 @@ mod tests {
     #[test]
     fn test_get_byte_io() {
         let mut mem = Memory::new();
-        mem.io[0x01] = 0x01;
-        assert_eq!(mem.get_byte(0xFF01 as usize), 0x01);
+        mem.serial.data = 0x01;
+        assert_eq!(mem.get_byte(SB_ADDR), 0x01);
+    }
+
+    #[test]
+    fn test_get_byte_io_unmapped() {
+        let mem = Memory::new();
+        assert_eq!(mem.get_byte(0xFF03 as usize), 0xFF);
     }
 
     #[test]
@@ -396,9 +805,9 @@ mod tests {
     #[test]
     fn test_get_word_io() {
         let mut mem = Memory::new();
-        mem.io[0x01] = 0x01;
-        mem.io[0x02] = 0x02;
-        assert_eq!(mem.get_word(0xFF01 as usize), 0x0201);
+        mem.serial.data = 0x01;
+        mem.serial.control = 0x81;
+        assert_eq!(mem.get_word(SB_ADDR), 0xFF01);
     }
 
     #[test]
@@ -519,6 +928,317 @@ mod tests {
         assert_eq!(mem.get_byte(UNUSED_START), 0xFF);
     }
 
+    #[test]
+    fn test_wram_banking_cgb() {
+        let mut mem = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        mem.set_byte(0xD000 as usize, 0x01);
+        mem.set_byte(SVBK_ADDR, 0x02);
+        assert_eq!(mem.get_byte(SVBK_ADDR), 0xFA);
+        assert_eq!(mem.get_byte(0xD000 as usize), 0xFF);
+        mem.set_byte(0xD000 as usize, 0x02);
+        mem.set_byte(SVBK_ADDR, 0x00);
+        assert_eq!(mem.get_byte(SVBK_ADDR), 0xF9);
+        assert_eq!(mem.get_byte(0xD000 as usize), 0x01);
+        assert_eq!(mem.wram[0x2000], 0x02);
+    }
+
+    #[test]
+    fn test_wram_banking_dmg() {
+        let mut mem = Memory::new();
+        mem.set_byte(0xD000 as usize, 0x01);
+        mem.set_byte(SVBK_ADDR, 0x02);
+        assert_eq!(mem.get_byte(0xD000 as usize), 0x01);
+    }
+
+    #[test]
+    fn test_vram_banking_cgb() {
+        let mut mem = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        mem.set_byte(VBK_ADDR, 0x01);
+        assert_eq!(mem.get_byte(VBK_ADDR), 0xFF);
+        mem.set_byte(VRAM_START, 0x01);
+        assert_eq!(mem.ppu.vram[VRAM_BANK_SIZE], 0x01);
+        assert_eq!(mem.ppu.vram[0x0000], 0x00);
+    }
+
+    #[test]
+    fn test_sgb_player_id() {
+        let mut mem = Memory::new_with_cartridge(Cartridge::new(), Model::Sgb);
+        mem.set_byte(JOYPAD_ADDR, 0x30);
+        assert_eq!(mem.get_byte(JOYPAD_ADDR), 0xFF);
+        mem.joypad.sgb.as_mut().unwrap().current_player = 1;
+        assert_eq!(mem.get_byte(JOYPAD_ADDR), 0xFE);
+    }
+
+    #[test]
+    fn test_set_byte_io_unmapped() {
+        let mut mem = Memory::new();
+        mem.set_byte(0xFF03 as usize, 0x01);
+        assert_eq!(mem.get_byte(0xFF03 as usize), 0xFF);
+    }
+
+    /// Write 0x00 and 0xFF to an IO register and check which bits read back as 1
+    /// Modelled on Mooneye's acceptance/bits/unused_hwio tests
+    fn check_hwio(mem: &mut Memory, addr: usize, mask: u8, writable: u8) {
+        let original = mem.get_byte(addr);
+        mem.set_byte(addr, 0x00);
+        let value = mem.get_byte(addr);
+        assert_eq!(value & (mask | writable), mask, "{:04X} after writing 00", addr);
+        mem.set_byte(addr, 0xFF);
+        let value = mem.get_byte(addr);
+        assert_eq!(value & (mask | writable), mask | writable, "{:04X} after writing FF", addr);
+        mem.set_byte(addr, original);
+    }
+
+    #[test]
+    fn test_sound_registers_stored() {
+        // The whole sound range is mapped, so fully writable registers and wave RAM read back what was written
+        let mut mem = Memory::new();
+        for addr in [0xFF12_usize, 0xFF17, 0xFF21, 0xFF22, 0xFF24, 0xFF25] {
+            mem.set_byte(addr, 0x5A);
+            assert_eq!(mem.get_byte(addr), 0x5A, "{:04X}", addr);
+        }
+        for addr in 0xFF30_usize..=0xFF3F {
+            mem.set_byte(addr, addr as u8);
+        }
+        for addr in 0xFF30_usize..=0xFF3F {
+            assert_eq!(mem.get_byte(addr), addr as u8, "{:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn test_unused_hwio() {
+        let mut mem = Memory::new();
+        // (address, bits that always read as 1, writable bits)
+        let registers: [(usize, u8, u8); 27] = [
+            (0xFF00, 0xC0, 0x30), // P1
+            (0xFF02, 0x7E, 0x81), // SC
+            (0xFF07, 0xF8, 0x07), // TAC
+            (0xFF0F, 0xE0, 0x1F), // IF
+            (0xFF10, 0x80, 0x7F), // NR10
+            (0xFF11, 0x3F, 0x00), // NR11
+            (0xFF13, 0xFF, 0x00), // NR13
+            (0xFF14, 0xBF, 0x00), // NR14
+            (0xFF16, 0x3F, 0x00), // NR21
+            (0xFF18, 0xFF, 0x00), // NR23
+            (0xFF19, 0xBF, 0x00), // NR24
+            (0xFF1A, 0x7F, 0x80), // NR30
+            (0xFF1B, 0xFF, 0x00), // NR31
+            (0xFF1C, 0x9F, 0x60), // NR32
+            (0xFF1D, 0xFF, 0x00), // NR33
+            (0xFF1E, 0xBF, 0x00), // NR34
+            (0xFF20, 0xFF, 0x00), // NR41
+            (0xFF23, 0xBF, 0x00), // NR44
+            (0xFF26, 0x70, 0x80), // NR52
+            (0xFF41, 0x80, 0x78), // STAT
+            (0xFF4F, 0xFF, 0x00), // VBK on DMG
+            (0xFF70, 0xFF, 0x00), // SVBK on DMG
+            (0xFF4D, 0xFF, 0x00), // KEY1 on DMG
+            (0xFF03, 0xFF, 0x00),
+            (0xFF08, 0xFF, 0x00),
+            (0xFF27, 0xFF, 0x00),
+            (0xFF7F, 0xFF, 0x00),
+        ];
+        for (addr, mask, writable) in registers {
+            check_hwio(&mut mem, addr, mask, writable);
+        }
+    }
+
+    #[test]
+    fn test_unused_hwio_cgb() {
+        let mut mem = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        check_hwio(&mut mem, VBK_ADDR, 0xFE, 0x01);
+        // Bit 0 isn't checked since writing bank 0 maps bank 1
+        check_hwio(&mut mem, SVBK_ADDR, 0xF8, 0x06);
+        check_hwio(&mut mem, KEY1_ADDR, 0x7E, 0x01);
+    }
+
+    #[test]
+    fn test_power_on_io() {
+        let dmg = Memory::new_with_cartridge(Cartridge::new(), Model::Dmg);
+        let cgb = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        assert_eq!(dmg.get_byte(OAM_DMA_ADDR), 0xFF);
+        assert_eq!(cgb.get_byte(OAM_DMA_ADDR), 0x00);
+        // These are the same on every model
+        for mem in [&dmg, &cgb] {
+            assert_eq!(mem.get_byte(LCDC_ADDR), 0x91);
+            assert_eq!(mem.get_byte(INTERRUPT_FLAG_ADDR), 0xE1);
+            assert_eq!(mem.get_byte(0xFF47 as usize), 0xFC);
+        }
+    }
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut mem = Memory::new();
+        mem.set_byte(JOYPAD_ADDR, 0x10);
+        mem.joypad.push_key(Key::Start);
+        mem.tick();
+        assert_eq!(mem.interrupts.requested_interrupts & Interrupt::Joypad as u8, 0x10);
+    }
+
+    #[test]
+    fn test_switch_speed() {
+        let mut mem = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        mem.set_byte(KEY1_ADDR, 0x01);
+        assert!(mem.speed_switch_armed());
+        mem.switch_speed();
+        assert_eq!(mem.get_byte(KEY1_ADDR), 0xFE);
+        assert_eq!(mem.get_byte(DIV_ADDR), 0x00);
+    }
+
+    #[test]
+    fn test_peek_poke_banks() {
+        let mut cart = Cartridge::new();
+        cart.cart_type = CartridgeType::Mbc1;
+        cart.rom = vec![0x00; ROM_BANK_SIZE * 4];
+        cart.ram = vec![0x00; 0x2000 * 4];
+        let mut mem = Memory::new_with_cartridge(cart, Model::Cgb);
+
+        mem.poke(0x4010, Some(3), 0x11);
+        mem.poke(0xA010, Some(2), 0x22);
+        mem.poke(0xD010, Some(5), 0x33);
+        mem.poke(0x8010, Some(1), 0x44);
+        assert_eq!(mem.peek(0x4010, Some(3)), 0x11);
+        assert_eq!(mem.peek(0xA010, Some(2)), 0x22);
+        assert_eq!(mem.peek(0xF010, Some(5)), 0x33);
+        assert_eq!(mem.peek(0x8010, Some(1)), 0x44);
+        assert_eq!(mem.peek(0x4010, None), 0x00);
+        assert_eq!(mem.banks(0x4000), 4);
+        assert_eq!(mem.banks(0xD000), 8);
+        assert_eq!(mem.banks(0xC000), 1);
+
+        // Poking ROM patches it instead of switching banks
+        mem.poke(0x2000, None, 0x03);
+        assert_eq!(mem.current_bank(0x4000), 1);
+        assert_eq!(mem.peek(0x2000, None), 0x03);
+        mem.set_byte(0x2000 as usize, 0x03);
+        assert_eq!(mem.current_bank(0x4000), 3);
+        assert_eq!(mem.peek(0x4010, None), 0x11);
+    }
+
+    #[test]
+    fn test_peek_poke_echo_ram() {
+        let mut mem = Memory::new_with_cartridge(Cartridge::new(), Model::Cgb);
+        mem.set_byte(SVBK_ADDR, 0x03);
+        for (i, byte) in mem.wram.iter_mut().enumerate() {
+            *byte = (i ^ (i >> 8) ^ (i >> 12)) as u8;
+        }
+        for addr in ECHO_RAM_START..=ECHO_RAM_END {
+            assert_eq!(mem.peek(addr as u16, None), mem.get_byte(addr), "{:04X}", addr);
+        }
+        assert_eq!(mem.current_bank(0xF000), 3);
+        assert_eq!(mem.banks(0xF000), 8);
+
+        mem.poke(0xF010, None, 0xAB);
+        assert_eq!(mem.get_byte(0xD010 as usize), 0xAB);
+        assert_ne!(mem.get_byte(0xC010 as usize), 0xAB);
+    }
+
+    #[test]
+    fn test_peek_ignores_locks() {
+        let mut mem = Memory::new();
+        mem.poke(0xC001, None, 0x34);
+        mem.write_cycle(OAM_DMA_ADDR, 0xC0);
+        mem.tick();
+        mem.tick();
+        mem.tick();
+        assert_eq!(mem.get_byte(OAM_START + 1), 0xFF);
+        assert_eq!(mem.peek(OAM_START as u16 + 1, None), 0x34);
+        mem.poke(0xA000, None, 0x56);
+        assert_eq!(mem.peek(0xA000, None), 0x56);
+        assert_eq!(mem.banks(0xD000), 2);
+    }
+
+    #[test]
+    fn test_poke_io_without_side_effects() {
+        let mut mem = Memory::new();
+        for _ in 0..0x100 {
+            mem.tick();
+        }
+        let div = mem.peek(DIV_ADDR as u16, None);
+        assert_ne!(div, 0x00);
+        mem.poke(DIV_ADDR as u16, None, div);
+        assert_eq!(mem.peek(DIV_ADDR as u16, None), div);
+        mem.set_byte(DIV_ADDR, 0x42);
+        assert_eq!(mem.peek(DIV_ADDR as u16, None), 0x00);
+
+        mem.poke(OAM_DMA_ADDR as u16, None, 0xC0);
+        assert_eq!(mem.peek(OAM_DMA_ADDR as u16, None), 0xC0);
+        assert!(mem.dma_progress.is_none());
+
+        mem.ppu.lcdc.enabled = true;
+        mem.ppu.ly = 0x20;
+        mem.poke(LCDC_ADDR as u16, None, 0x00);
+        assert!(!mem.ppu.lcdc.enabled);
+        assert_eq!(mem.ppu.ly, 0x20);
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut mem = Memory::new();
+        mem.cheats.add(Cheat::new("3EA-14F+0163A3C2", "").unwrap());
+        assert_eq!(mem.get_byte(0x0A14 as usize), 0x3E);
+        assert_eq!(mem.peek(0x0A14, None), 0xFF);
+        assert_eq!(mem.get_byte(0xC2A3 as usize), 0xFF);
+        mem.apply_ram_cheats();
+        assert_eq!(mem.get_byte(0xC2A3 as usize), 0x63);
+
+        mem.cheats.enabled = false;
+        assert_eq!(mem.get_byte(0x0A14 as usize), 0xFF);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut mem = Memory::new();
+        mem.set_byte(0xC001 as usize, 0x01);
+        mem.set_byte(0xC09F as usize, 0x02);
+        mem.write_cycle(OAM_DMA_ADDR, 0xC0);
+        assert_eq!(mem.get_byte(OAM_DMA_ADDR), 0xC0);
+        mem.tick();
+        assert_eq!(mem.ppu.oam[0x00], 0x00);
+        mem.tick();
+        mem.tick();
+        assert_eq!(mem.ppu.oam[0x01], 0x01);
+        assert_eq!(mem.get_byte(OAM_START), 0xFF);
+        for _ in 0..DMA_LENGTH - 2 {
+            mem.tick();
+        }
+        assert_eq!(mem.ppu.oam[0x9F], 0x02);
+        assert_eq!(mem.get_byte(OAM_START + 1), 0x01);
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut mem = Memory::new();
+        mem.write_cycle(DIV_ADDR, 0x00);
+        mem.timer.write(TAC_ADDR, 0x05);
+        assert_eq!(mem.clock_cycles(), 4);
+        for _ in 0..4 {
+            mem.tick();
+        }
+        assert_eq!(mem.get_byte(0xFF05 as usize), 0x01);
+        assert_eq!(mem.clock_cycles(), 20);
+    }
+
+    #[test]
+    fn test_register_io_device() {
+        struct Latch(u8);
+        impl IoDevice for Latch {
+            fn read(&self, _addr: usize) -> u8 {
+                self.0
+            }
+
+            fn write(&mut self, _addr: usize, v: u8) {
+                self.0 = v;
+            }
+        }
+
+        let mut mem = Memory::new();
+        mem.register_io_device(0xFF03..=0xFF04, Box::new(Latch(0x00)));
+        mem.set_byte(0xFF03 as usize, 0x42);
+        assert_eq!(mem.get_byte(0xFF04 as usize), 0x42);
+        assert_eq!(mem.get_byte(0xFF08 as usize), 0xFF);
+    }
+
     #[test]
     fn test_set_word_hram() {
         let mut mem = Memory::new();
@@ -0,0 +1,122 @@
+use crate::cartridge::{Cartridge, CgbSupport};
+
+/// The models in the order of their IDs in movie and save state files
+pub(crate) const MODELS: [Model; 6] = [
+    Model::Dmg0,
+    Model::Dmg,
+    Model::Mgb,
+    Model::Sgb,
+    Model::Cgb,
+    Model::Agb,
+];
+
+/// The Gameboy hardware models that can be emulated
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// Original Gameboy with the early revision 0 boot ROM
+    Dmg0,
+    /// Original Gameboy
+    Dmg,
+    /// Gameboy Pocket
+    Mgb,
+    /// Super Gameboy
+    Sgb,
+    /// Gameboy Color
+    Cgb,
+    /// Gameboy Advance running in Gameboy Color mode
+    Agb,
+}
+
+impl Model {
+    /// Pick a model based on the CGB and SGB flags in the cartridge header
+    /// CGB rendering isn't implemented yet, so games that also run on the
+    /// original Gameboy are emulated as a DMG (or SGB if they support it)
+    pub fn detect(cart: &Cartridge) -> Self {
+        if cart.cgb_support == CgbSupport::Only {
+            Model::Cgb
+        } else if cart.sgb_support {
+            Model::Sgb
+        } else {
+            Model::Dmg
+        }
+    }
+
+    /// Does this model have the Gameboy Color hardware (VRAM/WRAM banking, double speed)
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    /// Does this model listen for SGB command packets
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::Sgb)
+    }
+}
+
+impl std::fmt::Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dmg0 => write!(f, "DMG0"),
+            Self::Dmg => write!(f, "DMG"),
+            Self::Mgb => write!(f, "MGB"),
+            Self::Sgb => write!(f, "SGB"),
+            Self::Cgb => write!(f, "CGB"),
+            Self::Agb => write!(f, "AGB"),
+        }
+    }
+}
+
+impl std::str::FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Self::Dmg0),
+            "dmg" => Ok(Self::Dmg),
+            "mgb" => Ok(Self::Mgb),
+            "sgb" => Ok(Self::Sgb),
+            "cgb" => Ok(Self::Cgb),
+            "agb" => Ok(Self::Agb),
+            _ => Err(format!("Unknown model {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_dmg() {
+        let cart = Cartridge::new();
+        assert_eq!(Model::detect(&cart), Model::Dmg);
+    }
+
+    #[test]
+    fn test_detect_cgb_compatible() {
+        let mut cart = Cartridge::new();
+        cart.cgb_support = CgbSupport::Compatible;
+        assert_eq!(Model::detect(&cart), Model::Dmg);
+    }
+
+    #[test]
+    fn test_detect_cgb_only() {
+        let mut cart = Cartridge::new();
+        cart.cgb_support = CgbSupport::Only;
+        cart.sgb_support = true;
+        assert_eq!(Model::detect(&cart), Model::Cgb);
+    }
+
+    #[test]
+    fn test_detect_sgb() {
+        let mut cart = Cartridge::new();
+        cart.sgb_support = true;
+        assert_eq!(Model::detect(&cart), Model::Sgb);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("dmg0".parse::<Model>(), Ok(Model::Dmg0));
+        assert_eq!("CGB".parse::<Model>(), Ok(Model::Cgb));
+        assert!("nes".parse::<Model>().is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::gameboy::Gameboy;
+use crate::io::joypad::Joypad;
+use crate::model::{Model, MODELS};
+
+pub const MOVIE_MAGIC: [u8; 4] = *b"RBMV";
+pub const MOVIE_VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = 16;
+
+/// Errors while loading or starting a movie
+#[derive(Debug)]
+pub enum MovieError {
+    Io(std::io::Error),
+    /// The file doesn't start with the movie magic
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidModel(u8),
+    InvalidStart(u8),
+    /// The file ends before all of its frames
+    Truncated,
+    /// The movie was recorded with a different ROM
+    RomMismatch { expected: u32, actual: u32 },
+    /// The movie was recorded with a different model
+    ModelMismatch { expected: Model, actual: Model },
+}
+
+impl std::fmt::Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::InvalidMagic => write!(f, "Not a movie file"),
+            Self::UnsupportedVersion(version) => write!(f, "Unsupported movie version {}", version),
+            Self::InvalidModel(model) => write!(f, "Invalid model {} in movie", model),
+            Self::InvalidStart(start) => write!(f, "Invalid start state {} in movie", start),
+            Self::Truncated => write!(f, "Movie file is truncated"),
+            Self::RomMismatch { expected, actual } => write!(
+                f,
+                "Movie was recorded with a different ROM (CRC-32 {:08X}, loaded ROM is {:08X})",
+                expected, actual
+            ),
+            Self::ModelMismatch { expected, actual } => write!(
+                f,
+                "Movie was recorded on a {}, but a {} is being emulated",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<std::io::Error> for MovieError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The state the emulator is in when a movie starts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MovieStart {
+    /// The Gameboy was just powered on
+    PowerOn,
+}
+
+/// A recording of the keys held during every frame, played back to reproduce a run exactly
+///
+/// A movie file is laid out as follows, all numbers are little endian:
+///
+/// | Offset | Size | Contents |
+/// | ------ | ---- | -------- |
+/// | 0      | 4    | Magic `RBMV` |
+/// | 4      | 1    | Format version, currently 1 |
+/// | 5      | 1    | Model: 0 DMG0, 1 DMG, 2 MGB, 3 SGB, 4 CGB, 5 AGB |
+/// | 6      | 1    | Start state: 0 power on |
+/// | 7      | 1    | Reserved, 0 |
+/// | 8      | 4    | CRC-32 of the ROM |
+/// | 12     | 4    | Number of frames |
+/// | 16     | 1 per frame | The keys held during each frame, in the format of `Joypad::pressed_keys` |
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub model: Model,
+    pub start: MovieStart,
+    /// CRC-32 of the ROM the movie was recorded with
+    pub rom_checksum: u32,
+    /// The keys held during each frame, in the format of `Joypad::pressed_keys`
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    /// Create an empty movie starting from power on for the game a Gameboy is running
+    pub fn new(gb: &Gameboy) -> Self {
+        Movie {
+            model: gb.mmu.model(),
+            start: MovieStart::PowerOn,
+            rom_checksum: gb.mmu.rom_checksum(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Record the keys held during the next frame, called before every `Gameboy::step`
+    pub fn record_frame(&mut self, joypad: &Joypad) {
+        self.frames.push(joypad.pressed_keys());
+    }
+
+    /// Check that a Gameboy is running the game and model the movie was recorded with
+    pub fn check(&self, gb: &Gameboy) -> Result<(), MovieError> {
+        if self.rom_checksum != gb.mmu.rom_checksum() {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_checksum,
+                actual: gb.mmu.rom_checksum(),
+            });
+        }
+        if self.model != gb.mmu.model() {
+            return Err(MovieError::ModelMismatch {
+                expected: self.model,
+                actual: gb.mmu.model(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.frames.len());
+        bytes.extend_from_slice(&MOVIE_MAGIC);
+        bytes.push(MOVIE_VERSION);
+        bytes.push(MODELS.iter().position(|m| *m == self.model).unwrap() as u8);
+        bytes.push(match self.start {
+            MovieStart::PowerOn => 0,
+        });
+        bytes.push(0);
+        bytes.extend_from_slice(&self.rom_checksum.to_le_bytes());
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.frames);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(if bytes.starts_with(&MOVIE_MAGIC) {
+                MovieError::Truncated
+            } else {
+                MovieError::InvalidMagic
+            });
+        }
+        if bytes[0..4] != MOVIE_MAGIC {
+            return Err(MovieError::InvalidMagic);
+        }
+        if bytes[4] != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(bytes[4]));
+        }
+        let model = *MODELS
+            .get(bytes[5] as usize)
+            .ok_or(MovieError::InvalidModel(bytes[5]))?;
+        let start = match bytes[6] {
+            0 => MovieStart::PowerOn,
+            start => return Err(MovieError::InvalidStart(start)),
+        };
+        let rom_checksum = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let frame_count = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        let frames = bytes[HEADER_SIZE..]
+            .get(..frame_count)
+            .ok_or(MovieError::Truncated)?
+            .to_vec();
+
+        Ok(Movie {
+            model,
+            start,
+            rom_checksum,
+            frames,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), MovieError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, MovieError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Feeds the keys of a movie to the joypad frame by frame
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, frame: 0 }
+    }
+
+    /// Press the keys of the next frame, called before every `Gameboy::step`
+    /// Returns false once every frame has been played, all keys are released then
+    pub fn play_frame(&mut self, joypad: &mut Joypad) -> bool {
+        match self.movie.frames.get(self.frame) {
+            Some(keys) => {
+                joypad.set_pressed_keys(*keys);
+                self.frame += 1;
+                true
+            }
+            None => {
+                joypad.set_pressed_keys(0);
+                false
+            }
+        }
+    }
+
+    /// The number of frames played so far
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::joypad::Key;
+
+    const TEST_ROM: &str = "resources/test-rom.gb";
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let movie = Movie {
+            model: Model::Cgb,
+            start: MovieStart::PowerOn,
+            rom_checksum: 0x12345678,
+            frames: vec![0x00, 0x01, 0x88],
+        };
+        let bytes = movie.to_bytes();
+        assert_eq!(
+            bytes[..HEADER_SIZE],
+            [b'R', b'B', b'M', b'V', 1, 4, 0, 0, 0x78, 0x56, 0x34, 0x12, 3, 0, 0, 0]
+        );
+        assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        let mut bytes = Movie::new(&Gameboy::new(TEST_ROM).unwrap()).to_bytes();
+        bytes[12] = 1;
+        assert!(matches!(Movie::from_bytes(&bytes), Err(MovieError::Truncated)));
+        bytes[5] = 6;
+        assert!(matches!(Movie::from_bytes(&bytes), Err(MovieError::InvalidModel(6))));
+        bytes[4] = 2;
+        assert!(matches!(Movie::from_bytes(&bytes), Err(MovieError::UnsupportedVersion(2))));
+        assert!(matches!(Movie::from_bytes(b"RIFF"), Err(MovieError::InvalidMagic)));
+    }
+
+    #[test]
+    fn test_check() {
+        let gb = Gameboy::new(TEST_ROM).unwrap();
+        let mut movie = Movie::new(&gb);
+        assert!(movie.check(&gb).is_ok());
+        movie.model = Model::Cgb;
+        assert!(matches!(movie.check(&gb), Err(MovieError::ModelMismatch { .. })));
+        movie.rom_checksum ^= 1;
+        assert!(matches!(movie.check(&gb), Err(MovieError::RomMismatch { .. })));
+    }
+
+    /// A WRAM program that keeps reading both key lines of the joypad register and logs the
+    /// values to 0xD000-0xDFFF, wrapping around when it reaches the end
+    const POLL_JOYPAD: [u8; 26] = [
+        0x21, 0x00, 0xD0, // LD HL, 0xD000
+        0x3E, 0x10, // loop: LD A, 0x10 ; select the buttons
+        0xE0, 0x00, // LDH (0x00), A
+        0xF0, 0x00, // LDH A, (0x00)
+        0x22, // LD (HL+), A
+        0x3E, 0x20, // LD A, 0x20 ; select the directions
+        0xE0, 0x00, // LDH (0x00), A
+        0xF0, 0x00, // LDH A, (0x00)
+        0x22, // LD (HL+), A
+        0x7C, // LD A, H
+        0xFE, 0xE0, // CP 0xE0
+        0x20, 0xED, // JR NZ, loop
+        0x26, 0xD0, // LD H, 0xD0
+        0x18, 0xE9, // JR loop
+    ];
+
+    fn polling_gameboy() -> Gameboy {
+        let mut gb = Gameboy::new(TEST_ROM).unwrap();
+        for (i, byte) in POLL_JOYPAD.into_iter().enumerate() {
+            gb.mmu.set_byte(0xC000 + i, byte);
+        }
+        gb.registers_mut().pc = 0xC000;
+        gb
+    }
+
+    /// The joypad values the program logged
+    fn joypad_log(gb: &Gameboy) -> Vec<u8> {
+        (0xD000..=0xDFFF).map(|addr| gb.mmu.peek(addr, None)).collect()
+    }
+
+    #[test]
+    fn test_playback_reproduces_frames() {
+        let mut gb = polling_gameboy();
+        let mut movie = Movie::new(&gb);
+        let mut logs = Vec::new();
+        for i in 0..30 {
+            match i {
+                5 => gb.mmu.joypad.push_key(Key::Start),
+                10 => gb.mmu.joypad.release_key(Key::Start),
+                15 => gb.mmu.joypad.push_key(Key::Right),
+                20 => gb.mmu.joypad.push_key(Key::A),
+                _ => {}
+            }
+            movie.record_frame(&gb.mmu.joypad);
+            gb.step().unwrap();
+            logs.push(joypad_log(&gb));
+        }
+        // The program saw the keys change
+        assert!(logs[0].contains(&0xDF) && !logs[0].contains(&0xD7));
+        assert!(logs[7].contains(&0xD7));
+        assert!(logs[17].contains(&0xEE) && !logs[17].contains(&0xD7));
+        assert!(logs[29].contains(&0xDE) && logs[29].contains(&0xEE));
+
+        let mut replay = polling_gameboy();
+        movie.check(&replay).unwrap();
+        let mut player = MoviePlayer::new(movie);
+        while player.play_frame(&mut replay.mmu.joypad) {
+            replay.step().unwrap();
+            assert!(joypad_log(&replay) == logs[player.frame() - 1], "frame {}", player.frame());
+        }
+        assert!(player.finished());
+        assert_eq!(player.frame(), 30);
+        assert!(replay.mmu.ppu.frame_buffer == gb.mmu.ppu.frame_buffer);
+        assert_eq!(replay.mmu.clock_cycles(), gb.mmu.clock_cycles());
+        assert_eq!(replay.mmu.joypad.pressed_keys(), 0x00);
+    }
+}
@@ -1,3 +1,6 @@
 mod lcdc;
+pub mod oam;
+pub mod palette;
 pub mod ppu;
 pub mod stat;
+pub mod tilemap;
@@ -0,0 +1,181 @@
+use crate::ppu::palette::Palette;
+use crate::ppu::ppu::{Color, Ppu};
+
+/// Number of objects in OAM
+pub const OAM_ENTRIES: usize = 40;
+
+const SCREEN_HEIGHT: u8 = 144;
+
+/// The attributes of a single OAM entry
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OamEntry {
+    pub index: usize,
+    /// Y position plus 16, as stored in OAM
+    pub y: u8,
+    /// X position plus 8, as stored in OAM
+    pub x: u8,
+    pub tile: u8,
+    /// Which of OBP0 and OBP1 the object uses
+    pub palette: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    /// The object is drawn behind background colours 1-3
+    pub bg_priority: bool,
+}
+
+impl OamEntry {
+    /// The position of the object's top left corner on screen, can be negative
+    pub fn screen_position(&self) -> (i16, i16) {
+        (self.x as i16 - 8, self.y as i16 - 16)
+    }
+}
+
+/// The attributes of the object at an index in OAM, `None` if the index is past the last object
+pub fn oam_entry(ppu: &Ppu, index: usize) -> Option<OamEntry> {
+    let entry = ppu.oam.get(index * 4..index * 4 + 4)?;
+    Some(OamEntry {
+        index,
+        y: entry[0],
+        x: entry[1],
+        tile: entry[2],
+        palette: (entry[3] >> 4) & 0x1,
+        x_flip: entry[3] & 0x20 != 0,
+        y_flip: entry[3] & 0x40 != 0,
+        bg_priority: entry[3] & 0x80 != 0,
+    })
+}
+
+/// The attributes of every object in OAM
+pub fn oam_entries(ppu: &Ppu) -> Vec<OamEntry> {
+    (0..OAM_ENTRIES).filter_map(|index| oam_entry(ppu, index)).collect()
+}
+
+/// Draw an object as RGBA the way the PPU would with the current LCDC and palettes
+/// The image is 8 pixels wide and 8 or 16 high depending on the object size, colour 0 is transparent.
+/// `None` if the index is past the last object
+pub fn render_object(ppu: &Ppu, index: usize, palette: &Palette) -> Option<Vec<u8>> {
+    let entry = oam_entry(ppu, index)?;
+    let height = ppu.object_height() as usize;
+    // The lowest bit of the tile number is ignored for 8x16 objects
+    let tile = if height == 16 { entry.tile & 0xFE } else { entry.tile };
+    let obp = if entry.palette == 1 { ppu.obp1 } else { ppu.obp0 };
+
+    let mut image = vec![0; 8 * height * 4];
+    for y in 0..height {
+        let row = if entry.y_flip { height - 1 - y } else { y };
+        let addr = tile as usize * 16 + row * 2;
+        let (lo, hi) = (ppu.vram[addr], ppu.vram[addr + 1]);
+        for x in 0..8 {
+            let bit = if entry.x_flip { x } else { 7 - x };
+            let color_val = (lo >> bit) & 0x1 | ((hi >> bit) & 0x1) << 1;
+            if color_val == 0 {
+                continue;
+            }
+            let color = match (obp >> (color_val * 2)) & 0x3 {
+                0 => Color::White,
+                1 => Color::LightGray,
+                2 => Color::DarkGray,
+                _ => Color::Black,
+            };
+            let i = (y * 8 + x) * 4;
+            image[i..i + 4].copy_from_slice(&palette.rgba(color));
+        }
+    }
+    Some(image)
+}
+
+/// The lines where objects were dropped by the 10 objects per line limit, with the OAM indices
+/// of the dropped objects, using the current OAM and LCDC
+pub fn dropped_objects(ppu: &Ppu) -> Vec<(u8, Vec<usize>)> {
+    (0..SCREEN_HEIGHT)
+        .filter_map(|ly| {
+            let dropped = ppu.select_objects(ly).dropped;
+            if dropped == 0 {
+                return None;
+            }
+            let indices = (0..OAM_ENTRIES).filter(|i| dropped & (1 << i) != 0).collect();
+            Some((ly, indices))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oam_entries() {
+        let mut ppu = Ppu::new();
+        ppu.oam[4..8].copy_from_slice(&[0x20, 0x10, 0x42, 0xF0]);
+        let entries = oam_entries(&ppu);
+        assert_eq!(entries.len(), OAM_ENTRIES);
+        assert_eq!(
+            entries[1],
+            OamEntry {
+                index: 1,
+                y: 0x20,
+                x: 0x10,
+                tile: 0x42,
+                palette: 1,
+                x_flip: true,
+                y_flip: true,
+                bg_priority: true,
+            }
+        );
+        assert_eq!(entries[1].screen_position(), (8, 16));
+        assert_eq!(oam_entry(&ppu, 1), Some(entries[1]));
+        assert_eq!(oam_entry(&ppu, OAM_ENTRIES), None);
+        assert_eq!(render_object(&ppu, OAM_ENTRIES, &Palette::GRAY), None);
+    }
+
+    #[test]
+    fn test_render_object_flipped() {
+        let mut ppu = Ppu::new();
+        ppu.obp0 = 0xE4;
+        // Tile 2 has a single black pixel in its top left corner
+        ppu.vram[0x20] = 0x80;
+        ppu.vram[0x21] = 0x80;
+        ppu.oam[0..4].copy_from_slice(&[16, 8, 0x02, 0x60]);
+        let image = render_object(&ppu, 0, &Palette::GRAY).unwrap();
+        assert_eq!(image.len(), 8 * 8 * 4);
+        assert_eq!(image[(7 * 8 + 7) * 4..][..4], [0, 0, 0, 255]);
+        assert_eq!(image[0..4], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_render_object_tall() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc.objects_size = true;
+        ppu.obp0 = 0xE4;
+        // The second tile of the pair has its first row set to colour 1
+        ppu.vram[0x30] = 0xFF;
+        ppu.oam[0..4].copy_from_slice(&[16, 8, 0x03, 0x00]);
+        let image = render_object(&ppu, 0, &Palette::GRAY).unwrap();
+        assert_eq!(image.len(), 8 * 16 * 4);
+        assert_eq!(image[8 * 8 * 4..][..4], [192, 192, 192, 255]);
+    }
+
+    #[test]
+    fn test_dropped_objects() {
+        let mut ppu = Ppu::new();
+        // 12 objects on lines 0-7 at different X positions
+        for i in 0..12 {
+            ppu.oam[i * 4] = 16;
+            ppu.oam[i * 4 + 1] = 8 + i as u8 * 8;
+        }
+        let dropped = dropped_objects(&ppu);
+        assert_eq!(dropped.len(), 8);
+        assert_eq!(dropped[0], (0, vec![10, 11]));
+        assert_eq!(dropped[7].0, 7);
+        assert_eq!(ppu.select_objects(0).drawn(), &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_select_objects_partially_above_screen() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc.objects_size = true;
+        ppu.oam[0] = 4;
+        assert_eq!(ppu.select_objects(3).drawn(), &[0]);
+        assert!(ppu.select_objects(4).drawn().is_empty());
+    }
+}
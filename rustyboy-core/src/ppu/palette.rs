@@ -0,0 +1,114 @@
+use crate::ppu::ppu::Color;
+
+/// The RGBA colours the four DMG shades are displayed as
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// The colours of white, light gray, dark gray and black
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Palette {
+    /// Evenly spaced grays
+    pub const GRAY: Palette = Palette::new([[255, 255, 255], [192, 192, 192], [96, 96, 96], [0, 0, 0]]);
+    /// The green tinted screen of the original Gameboy
+    pub const DMG: Palette = Palette::new([[155, 188, 15], [139, 172, 15], [48, 98, 48], [15, 56, 15]]);
+    /// The grayish screen of the Gameboy Pocket
+    pub const POCKET: Palette = Palette::new([[196, 207, 161], [139, 149, 109], [77, 83, 60], [31, 31, 31]]);
+    /// The blue-green backlight of the Gameboy Light
+    pub const LIGHT: Palette = Palette::new([[0, 181, 129], [0, 154, 113], [0, 105, 74], [0, 79, 59]]);
+
+    /// Create a custom palette from the RGB colours of white, light gray, dark gray and black
+    pub const fn new(rgb: [[u8; 3]; 4]) -> Self {
+        let mut colors = [[0, 0, 0, 255]; 4];
+        let mut i = 0;
+        while i < 4 {
+            colors[i] = [rgb[i][0], rgb[i][1], rgb[i][2], 255];
+            i += 1;
+        }
+        Palette { colors }
+    }
+
+    pub fn rgba(&self, color: Color) -> [u8; 4] {
+        match color {
+            Color::White => self.colors[0],
+            Color::LightGray => self.colors[1],
+            Color::DarkGray => self.colors[2],
+            Color::Black => self.colors[3],
+        }
+    }
+
+    /// Convert a frame to RGBA, `out` needs 4 bytes for every pixel
+    pub fn frame_to_rgba(&self, frame_buffer: &[Color], out: &mut [u8]) {
+        for (pixel, color) in out.chunks_exact_mut(4).zip(frame_buffer) {
+            pixel.copy_from_slice(&self.rgba(*color));
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAY
+    }
+}
+
+impl std::str::FromStr for Palette {
+    type Err = String;
+
+    /// Parse a preset name, or four comma separated hex colours from white to black
+    /// for example `e0f8d0,88c070,346856,081820`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gray" | "grey" => return Ok(Palette::GRAY),
+            "dmg" => return Ok(Palette::DMG),
+            "pocket" => return Ok(Palette::POCKET),
+            "light" => return Ok(Palette::LIGHT),
+            _ => {}
+        }
+
+        let colors: Vec<&str> = s.split(',').map(|c| c.trim().trim_start_matches('#')).collect();
+        if colors.len() != 4 {
+            return Err(format!(
+                "Unknown palette {}, expected gray, dmg, pocket, light or four hex colours",
+                s
+            ));
+        }
+        let mut rgb = [[0; 3]; 4];
+        for (i, color) in colors.iter().enumerate() {
+            let value = match u32::from_str_radix(color, 16) {
+                Ok(value) if color.len() == 6 => value,
+                _ => return Err(format!("Invalid colour {} in palette", color)),
+            };
+            rgb[i] = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+        Ok(Palette::new(rgb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgba() {
+        assert_eq!(Palette::GRAY.rgba(Color::LightGray), [192, 192, 192, 255]);
+        assert_eq!(Palette::DMG.rgba(Color::Black), [15, 56, 15, 255]);
+    }
+
+    #[test]
+    fn test_frame_to_rgba() {
+        let mut out = [0; 8];
+        Palette::GRAY.frame_to_rgba(&[Color::White, Color::DarkGray], &mut out);
+        assert_eq!(out, [255, 255, 255, 255, 96, 96, 96, 255]);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("Pocket".parse(), Ok(Palette::POCKET));
+        assert_eq!(
+            "#ffffff,aa5500,005500,000000".parse(),
+            Ok(Palette::new([[255, 255, 255], [170, 85, 0], [0, 85, 0], [0, 0, 0]]))
+        );
+        assert!("ffffff,aa5500".parse::<Palette>().is_err());
+        assert!("ffffff,aa5500,005500,00000g".parse::<Palette>().is_err());
+    }
+}
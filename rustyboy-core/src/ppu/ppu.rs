@@ -1,5 +1,7 @@
+use crate::io::IoDevice;
 use crate::ppu::lcdc::Lcdc;
 use crate::ppu::stat::{Mode, Stat};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 pub const LCDC_ADDR: usize = 0xFF40;
 pub const STAT_ADDR: usize = 0xFF41;
@@ -13,10 +15,16 @@ pub const OBP0_ADDR: usize = 0xFF48;
 pub const OBP1_ADDR: usize = 0xFF49;
 pub const WY_ADDR: usize = 0xFF4A;
 pub const WX_ADDR: usize = 0xFF4B;
+pub const VBK_ADDR: usize = 0xFF4F;
+
+/// Size of a single VRAM bank, the CGB has two of them
+pub const VRAM_BANK_SIZE: usize = 0x2000;
 
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
 const MAX_SCANLINE: u8 = 153;
+/// The PPU only draws up to 10 objects on each line
+pub const MAX_OBJECTS_PER_LINE: usize = 10;
 const HBLANK_CYCLES: u32 = 204;
 const VBLANK_CYCLES: u32 = 456;
 const OAM_SEARCH_CYCLES: u32 = 80;
@@ -30,6 +38,21 @@ pub enum Color {
     Black,
 }
 
+/// The objects on a single line
+pub struct LineObjects {
+    /// OAM indices of the objects drawn, in OAM order
+    drawn: [usize; MAX_OBJECTS_PER_LINE],
+    count: usize,
+    /// Bit n is set if object n covers the line but was dropped by the 10 objects limit
+    pub dropped: u64,
+}
+
+impl LineObjects {
+    pub fn drawn(&self) -> &[usize] {
+        &self.drawn[..self.count]
+    }
+}
+
 pub struct Ppu {
     /// The LCD Control register
     pub lcdc: Lcdc,
@@ -60,7 +83,9 @@ pub struct Ppu {
     /// Has a VBlank interrupt been fired
     pub vblank_interrupt_fired: bool,
     /// VRAM
-    pub vram: [u8; 0x2000],
+    pub vram: [u8; VRAM_BANK_SIZE * 2],
+    /// The VRAM bank mapped into 0x8000-0x9FFF, only switchable on the CGB
+    pub vram_bank: usize,
     pub vram_changed: bool,
     /// OAM
     pub oam: [u8; 0xA0],
@@ -90,7 +115,8 @@ impl Ppu {
             frame_buffer: [Color::White; WIDTH * HEIGHT],
             lcd_interrupt_fired: false,
             vblank_interrupt_fired: false,
-            vram: [0; 0x2000],
+            vram: [0; VRAM_BANK_SIZE * 2],
+            vram_bank: 0,
             vram_changed: false,
             oam: [0; 0xA0],
             clock: 0,
@@ -164,6 +190,11 @@ impl Ppu {
         }
     }
 
+    /// Clear the screen, used while the CPU is stopped
+    pub fn blank(&mut self) {
+        self.frame_buffer = [Color::White; WIDTH * HEIGHT];
+    }
+
     pub fn check_lyc(&mut self) {
         if self.ly == self.lyc {
             self.stat.lyc_ly_flag = true;
@@ -278,72 +309,214 @@ impl Ppu {
         self.window_line_counter += 1;
     }
 
-    fn draw_objects(&mut self) {
-        let mut object_count = 0;
-        let mut object_x_coords: [u8; 10] = [0; 10];
+    /// Height of objects in pixels, 8 or 16 depending on LCDC
+    pub fn object_height(&self) -> u8 {
+        if self.lcdc.objects_size { 16 } else { 8 }
+    }
 
+    /// Pick the objects drawn on a line, the first 10 in OAM order that cover it
+    /// Objects with the same X as one picked earlier are skipped, so the lower OAM index wins
+    pub fn select_objects(&self, ly: u8) -> LineObjects {
+        let mut objects = LineObjects {
+            drawn: [0; MAX_OBJECTS_PER_LINE],
+            count: 0,
+            dropped: 0,
+        };
+        // OAM Y is the object's top edge plus 16, so objects can be partially above the screen
+        let line = ly as u16 + 16;
         for i in 0..40 {
-            let mut should_skip = false;
-            if object_count >= 10 {
-                break;
+            let object_y = self.oam[i * 4] as u16;
+            if line < object_y || line >= object_y + self.object_height() as u16 {
+                continue;
             }
-            
-            let object_y = self.oam[i * 4].wrapping_sub(16);
-            let object_x = self.oam[i * 4 + 1].wrapping_sub(8);
-            for j in 0..object_count {
-                if object_x_coords[j] == object_x {
-                    should_skip = true;
-                }
+            if objects.count >= MAX_OBJECTS_PER_LINE {
+                objects.dropped |= 1 << i;
+                continue;
             }
-            if should_skip {
+
+            let object_x = self.oam[i * 4 + 1];
+            if objects.drawn[..objects.count].iter().any(|j| self.oam[j * 4 + 1] == object_x) {
                 continue;
             }
+            objects.drawn[objects.count] = i;
+            objects.count += 1;
+        }
+        objects
+    }
 
+    fn draw_objects(&mut self) {
+        let objects = self.select_objects(self.ly);
+        let object_height = self.object_height();
+        for &i in objects.drawn() {
+            let object_row = (self.ly as u16 + 16 - self.oam[i * 4] as u16) as u8;
+            let object_x = self.oam[i * 4 + 1].wrapping_sub(8);
             let object_tile = if self.lcdc.objects_size {
                 self.oam[i * 4 + 2] & 0xFE
             } else {
                 self.oam[i * 4 + 2]
             };
             let object_flags = self.oam[i * 4 + 3];
-            let object_height = if self.lcdc.objects_size { 16 } else { 8 };
-            if object_y <= self.ly && self.ly < object_y + object_height {
-                object_count += 1;
-                object_x_coords[object_count - 1] = object_x;
-                let bg_priority = object_flags & 0x80 != 0;
-                let y_flip = object_flags & 0x40 != 0;
-                let x_flip = object_flags & 0x20 != 0;
-                let palette = if object_flags & 0x10 != 0 { self.obp1 } else { self.obp0 };
-                let tile_line = if y_flip {
-                    (object_height - 1 - (self.ly - object_y)) * 2
+            let bg_priority = object_flags & 0x80 != 0;
+            let y_flip = object_flags & 0x40 != 0;
+            let x_flip = object_flags & 0x20 != 0;
+            let palette = if object_flags & 0x10 != 0 { self.obp1 } else { self.obp0 };
+            let tile_line = if y_flip {
+                (object_height - 1 - object_row) * 2
+            } else {
+                object_row * 2
+            };
+            let tile_addr = 0x8000 + (object_tile as u16 * 16) + tile_line as u16;
+            let lo = self.vram[tile_addr as usize - 0x8000];
+            let hi = self.vram[tile_addr as usize + 1 - 0x8000];
+            for pixel in 0..8 {
+                let color_val = if x_flip {
+                    (lo >> pixel & 0x1) | (hi >> pixel & 0x1) << 1
                 } else {
-                    (self.ly - object_y) * 2
+                    (lo >> (7 - pixel) & 0x1) | (hi >> (7 - pixel) & 0x1) << 1
                 };
-                let tile_addr = 0x8000 + (object_tile as u16 * 16) + tile_line as u16;
-                let lo = self.vram[tile_addr as usize - 0x8000];
-                let hi = self.vram[tile_addr as usize + 1 - 0x8000];
-                for pixel in 0..8 {
-                    let color_val = if x_flip {
-                        (lo >> pixel & 0x1) | (hi >> pixel & 0x1) << 1
-                    } else {
-                        (lo >> (7 - pixel) & 0x1) | (hi >> (7 - pixel) & 0x1) << 1
-                    };
-                    if color_val == 0 {
-                        continue;
-                    }
-                    let color = (palette >> (color_val * 2)) & 0x3;
-                    let x_pos = object_x.wrapping_add(pixel);
-                    if bg_priority && self.bg_pixels[self.ly as usize * WIDTH + x_pos as usize] != 0 {
-                        continue;
-                    }
-                    self.frame_buffer[self.ly as usize * WIDTH + x_pos as usize] = match color {
-                        0 => Color::White,
-                        1 => Color::LightGray,
-                        2 => Color::DarkGray,
-                        3 => Color::Black,
-                        _ => Color::Black,
-                    };
+                if color_val == 0 {
+                    continue;
+                }
+                let color = (palette >> (color_val * 2)) & 0x3;
+                let x_pos = object_x.wrapping_add(pixel);
+                // Objects can be partially off the left or right edge of the screen
+                if x_pos as usize >= WIDTH {
+                    continue;
                 }
+                if bg_priority && self.bg_pixels[self.ly as usize * WIDTH + x_pos as usize] != 0 {
+                    continue;
+                }
+                self.frame_buffer[self.ly as usize * WIDTH + x_pos as usize] = match color {
+                    0 => Color::White,
+                    1 => Color::LightGray,
+                    2 => Color::DarkGray,
+                    3 => Color::Black,
+                    _ => Color::Black,
+                };
             }
         }
     }
 }
+
+impl IoDevice for Ppu {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            LCDC_ADDR => self.lcdc.into(),
+            STAT_ADDR => self.stat.into(),
+            SCY_ADDR => self.scy,
+            SCX_ADDR => self.scx,
+            LY_ADDR => self.ly,
+            LYC_ADDR => self.lyc,
+            BGP_ADDR => self.bgp,
+            OBP0_ADDR => self.obp0,
+            OBP1_ADDR => self.obp1,
+            WY_ADDR => self.wy,
+            WX_ADDR => self.wx,
+            VBK_ADDR => 0xFE | self.vram_bank as u8,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: usize, v: u8) {
+        match addr {
+            LCDC_ADDR => {
+                if !self.lcdc.enabled && v & 0x80 != 0 {
+                    // If the LCD is turned on, switch to mode 2
+                    self.stat.mode = Mode::OamSearch;
+                }
+                if v & 0x80 == 0 && self.lcdc.enabled {
+                    // If the LCD is turned off, switch to mode 0
+                    self.stat.mode = Mode::HBlank;
+                    self.ly = 0;
+                }
+                self.lcdc.set(v);
+            }
+            STAT_ADDR => self.stat.set(v),
+            SCY_ADDR => self.scy = v,
+            SCX_ADDR => self.scx = v,
+            LY_ADDR => log::debug!("Ignoring write of {:02X} to read only LY register", v),
+            LYC_ADDR => {
+                self.lyc = v;
+                self.check_lyc(); // Check if LYC=LY
+            }
+            BGP_ADDR => self.bgp = v,
+            OBP0_ADDR => self.obp0 = v,
+            OBP1_ADDR => self.obp1 = v,
+            WY_ADDR => self.wy = v,
+            WX_ADDR => self.wx = v,
+            VBK_ADDR => self.vram_bank = (v & 0x01) as usize,
+            _ => {}
+        }
+    }
+
+    fn poke(&mut self, addr: usize, v: u8) {
+        match addr {
+            // Turning the LCD on or off with a poke doesn't change the mode or LY
+            LCDC_ADDR => self.lcdc.set(v),
+            LY_ADDR => self.ly = v,
+            // LYC=LY is only checked again on the next line
+            LYC_ADDR => self.lyc = v,
+            _ => self.write(addr, v),
+        }
+    }
+}
+
+impl Savestate for Ppu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.lcdc.into());
+        state.write_u8(self.stat.into());
+        state.write_bytes(&[self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0, self.obp1, self.wy, self.wx]);
+        for color in self.frame_buffer.iter() {
+            state.write_u8(*color as u8);
+        }
+        state.write_bool(self.lcd_interrupt_fired);
+        state.write_bool(self.vblank_interrupt_fired);
+        state.write_bytes(&self.vram);
+        state.write_u8(self.vram_bank as u8);
+        state.write_bytes(&self.oam);
+        state.write_u32(self.clock);
+        state.write_u8(self.window_line_counter);
+        state.write_bytes(&self.bg_pixels);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.lcdc.set(state.read_u8()?);
+        let stat = state.read_u8()?;
+        self.stat.set(stat);
+        self.stat.lyc_ly_flag = stat & 0b0000_0100 != 0;
+        self.stat.mode = match stat & 0x03 {
+            0x00 => Mode::HBlank,
+            0x01 => Mode::VBlank,
+            0x02 => Mode::OamSearch,
+            _ => Mode::PixelTransfer,
+        };
+        let mut registers = [0; 9];
+        state.read_bytes(&mut registers)?;
+        [self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0, self.obp1, self.wy, self.wx] = registers;
+        if self.ly > MAX_SCANLINE {
+            return Err(StateError::Corrupt);
+        }
+        for color in self.frame_buffer.iter_mut() {
+            *color = match state.read_u8()? {
+                0 => Color::White,
+                1 => Color::LightGray,
+                2 => Color::DarkGray,
+                3 => Color::Black,
+                _ => return Err(StateError::Corrupt),
+            };
+        }
+        self.lcd_interrupt_fired = state.read_bool()?;
+        self.vblank_interrupt_fired = state.read_bool()?;
+        state.read_bytes(&mut self.vram)?;
+        self.vram_bank = state.read_u8()? as usize;
+        if self.vram_bank > 1 {
+            return Err(StateError::Corrupt);
+        }
+        // Any cached view of VRAM has to be redrawn
+        self.vram_changed = true;
+        state.read_bytes(&mut self.oam)?;
+        self.clock = state.read_u32()?;
+        self.window_line_counter = state.read_u8()?;
+        state.read_bytes(&mut self.bg_pixels)
+    }
+}
@@ -36,41 +36,57 @@ impl Stat {
     }
 
     /// Set the value of the STAT register
+    /// The mode and LYC=LY flag are read only, so only the interrupt selection bits are written
     pub fn set(&mut self, value: u8) {
         self.lyc_ly_interrupt = value & 0b0100_0000 != 0;
-        self.mode_0_hblank_interrupt = value & 0b0010_0000 != 0;
+        self.mode_2_oam_interrupt = value & 0b0010_0000 != 0;
         self.mode_1_vblank_interrupt = value & 0b0001_0000 != 0;
-        self.mode_2_oam_interrupt = value & 0b0000_1000 != 0;
-        self.lyc_ly_flag = value & 0b0000_0100 != 0;
-        self.mode = match value & 0b0000_0011 {
-            0b00 => Mode::HBlank,
-            0b01 => Mode::VBlank,
-            0b10 => Mode::OamSearch,
-            0b11 => Mode::PixelTransfer,
-            _ => unreachable!(),
-        }
+        self.mode_0_hblank_interrupt = value & 0b0000_1000 != 0;
     }
 }
 
 impl Into<u8> for Stat {
     fn into(self) -> u8 {
+        // Bit 7 is unused and always reads as 1
         let mut value = 0x80;
-        if self.lyc_ly_flag {
+        if self.lyc_ly_interrupt {
             value |= 0b0100_0000;
         }
-        if self.mode_0_hblank_interrupt {
+        if self.mode_2_oam_interrupt {
             value |= 0b0010_0000;
         }
         if self.mode_1_vblank_interrupt {
             value |= 0b0001_0000;
         }
-        if self.mode_2_oam_interrupt {
+        if self.mode_0_hblank_interrupt {
             value |= 0b0000_1000;
         }
-        if self.lyc_ly_interrupt {
+        if self.lyc_ly_flag {
             value |= 0b0000_0100;
         }
         value |= self.mode as u8;
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into() {
+        let mut stat = Stat::new();
+        stat.lyc_ly_flag = true;
+        stat.lyc_ly_interrupt = true;
+        assert_eq!(Into::<u8>::into(stat), 0xC5);
+    }
+
+    #[test]
+    fn test_set_read_only_bits() {
+        let mut stat = Stat::new();
+        stat.set(0xFF);
+        assert_eq!(Into::<u8>::into(stat), 0xF9);
+        stat.set(0x00);
+        assert_eq!(Into::<u8>::into(stat), 0x81);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::ppu::palette::Palette;
+use crate::ppu::ppu::{Color, Ppu};
+
+/// Width and height of a tile map in pixels
+pub const TILEMAP_SIZE: usize = 256;
+
+const SCREEN_WIDTH: usize = 160;
+const SCREEN_HEIGHT: usize = 144;
+
+/// Colour of the outline of the area shown on screen
+const VIEWPORT_COLOR: [u8; 4] = [255, 0, 0, 255];
+/// Colour of the outline of the area covered by the window
+const WINDOW_COLOR: [u8; 4] = [0, 96, 255, 255];
+
+/// The two tile maps in VRAM
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileMap {
+    /// 0x9800-0x9BFF
+    Low,
+    /// 0x9C00-0x9FFF
+    High,
+}
+
+impl TileMap {
+    fn address(self) -> usize {
+        match self {
+            TileMap::Low => 0x9800,
+            TileMap::High => 0x9C00,
+        }
+    }
+}
+
+/// How tile numbers in a tile map are turned into tile data addresses
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileData {
+    /// Unsigned tile numbers from 0x8000
+    Unsigned,
+    /// Signed tile numbers from 0x9000, covering 0x8800-0x97FF
+    Signed,
+}
+
+impl TileData {
+    fn tile_address(self, tile_no: u8) -> usize {
+        match self {
+            TileData::Unsigned => 0x8000 + tile_no as usize * 16,
+            TileData::Signed => (0x9000 + tile_no as i8 as isize * 16) as usize,
+        }
+    }
+}
+
+/// A debug view of a whole 256x256 tile map, as the background or window would draw it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileMapView {
+    pub map: TileMap,
+    pub data: TileData,
+    /// Outline the part of the map shown on screen (SCX/SCY) and the part of it the window covers (WX/WY)
+    pub show_viewport: bool,
+}
+
+impl TileMapView {
+    /// The map and addressing LCDC currently selects for the background
+    pub fn background(ppu: &Ppu) -> Self {
+        TileMapView {
+            map: if ppu.lcdc.background_tile_map { TileMap::High } else { TileMap::Low },
+            data: Self::lcdc_tile_data(ppu),
+            show_viewport: true,
+        }
+    }
+
+    /// The map and addressing LCDC currently selects for the window
+    pub fn window(ppu: &Ppu) -> Self {
+        TileMapView {
+            map: if ppu.lcdc.window_tile_map { TileMap::High } else { TileMap::Low },
+            data: Self::lcdc_tile_data(ppu),
+            show_viewport: false,
+        }
+    }
+
+    fn lcdc_tile_data(ppu: &Ppu) -> TileData {
+        if ppu.lcdc.background_tile_data {
+            TileData::Unsigned
+        } else {
+            TileData::Signed
+        }
+    }
+
+    /// Draw the tile map as a 256x256 RGBA image, using BGP for the shades
+    pub fn render(&self, ppu: &Ppu, palette: &Palette) -> Vec<u8> {
+        let mut image = vec![0; TILEMAP_SIZE * TILEMAP_SIZE * 4];
+        for y in 0..TILEMAP_SIZE {
+            for x in 0..TILEMAP_SIZE {
+                let tile_no = ppu.vram[self.map.address() + (y / 8) * 32 + x / 8 - 0x8000];
+                let tile_addr = self.data.tile_address(tile_no) + (y % 8) * 2 - 0x8000;
+                let lo = ppu.vram[tile_addr];
+                let hi = ppu.vram[tile_addr + 1];
+                let color_val = (lo >> (7 - x % 8)) & 0x1 | ((hi >> (7 - x % 8)) & 0x1) << 1;
+                let color = match (ppu.bgp >> (color_val * 2)) & 0x3 {
+                    0 => Color::White,
+                    1 => Color::LightGray,
+                    2 => Color::DarkGray,
+                    _ => Color::Black,
+                };
+                set_pixel(&mut image, x, y, palette.rgba(color));
+            }
+        }
+
+        if self.show_viewport {
+            let (scx, scy) = (ppu.scx as usize, ppu.scy as usize);
+            if ppu.lcdc.window_enabled && ppu.wx <= 166 && (ppu.wy as usize) < SCREEN_HEIGHT {
+                // The window covers the screen from its origin to the bottom right corner
+                let window_x = (ppu.wx as usize).saturating_sub(7);
+                let window_y = ppu.wy as usize;
+                draw_rect(
+                    &mut image,
+                    scx + window_x,
+                    scy + window_y,
+                    SCREEN_WIDTH - window_x,
+                    SCREEN_HEIGHT - window_y,
+                    WINDOW_COLOR,
+                );
+            }
+            draw_rect(&mut image, scx, scy, SCREEN_WIDTH, SCREEN_HEIGHT, VIEWPORT_COLOR);
+        }
+        image
+    }
+}
+
+/// Set a pixel of the image, wrapping around the edges like the background does
+fn set_pixel(image: &mut [u8], x: usize, y: usize, rgba: [u8; 4]) {
+    let i = ((y % TILEMAP_SIZE) * TILEMAP_SIZE + x % TILEMAP_SIZE) * 4;
+    image[i..i + 4].copy_from_slice(&rgba);
+}
+
+/// Outline a rectangle, wrapping around the edges of the image
+fn draw_rect(image: &mut [u8], x: usize, y: usize, width: usize, height: usize, rgba: [u8; 4]) {
+    for dx in 0..width {
+        set_pixel(image, x + dx, y, rgba);
+        set_pixel(image, x + dx, y + height - 1, rgba);
+    }
+    for dy in 0..height {
+        set_pixel(image, x, y + dy, rgba);
+        set_pixel(image, x + width - 1, y + dy, rgba);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let i = (y * TILEMAP_SIZE + x) * 4;
+        image[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_tile_address() {
+        assert_eq!(TileData::Unsigned.tile_address(0x80), 0x8800);
+        assert_eq!(TileData::Signed.tile_address(0x00), 0x9000);
+        assert_eq!(TileData::Signed.tile_address(0x80), 0x8800);
+        assert_eq!(TileData::Signed.tile_address(0xFF), 0x8FF0);
+    }
+
+    #[test]
+    fn test_render_tiles() {
+        let mut ppu = Ppu::new();
+        ppu.bgp = 0xE4;
+        // Tile 1 is solid black, placed at the second tile of the high map
+        ppu.vram[0x0010..0x0020].fill(0xFF);
+        ppu.vram[0x1C01] = 0x01;
+        let view = TileMapView {
+            map: TileMap::High,
+            data: TileData::Unsigned,
+            show_viewport: false,
+        };
+        let image = view.render(&ppu, &Palette::GRAY);
+        assert_eq!(image.len(), TILEMAP_SIZE * TILEMAP_SIZE * 4);
+        assert_eq!(pixel(&image, 7, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(&image, 8, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&image, 15, 7), [0, 0, 0, 255]);
+        assert_eq!(pixel(&image, 16, 0), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_render_viewport_wraps() {
+        let mut ppu = Ppu::new();
+        ppu.scx = 200;
+        ppu.scy = 10;
+        let image = TileMapView::background(&ppu).render(&ppu, &Palette::GRAY);
+        assert_eq!(pixel(&image, 200, 10), VIEWPORT_COLOR);
+        // The right edge wraps around to x = (200 + 159) % 256
+        assert_eq!(pixel(&image, 103, 50), VIEWPORT_COLOR);
+        assert_eq!(pixel(&image, 50, 153), VIEWPORT_COLOR);
+        assert_ne!(pixel(&image, 50, 50), VIEWPORT_COLOR);
+    }
+
+    #[test]
+    fn test_render_window_origin() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc.window_enabled = true;
+        ppu.wx = 7 + 20;
+        ppu.wy = 30;
+        let image = TileMapView::background(&ppu).render(&ppu, &Palette::GRAY);
+        assert_eq!(pixel(&image, 20, 30), WINDOW_COLOR);
+        assert_eq!(pixel(&image, 20, 100), WINDOW_COLOR);
+        assert_eq!(pixel(&image, 10, 30), [255, 255, 255, 255]);
+
+        let view = TileMapView::window(&ppu);
+        assert!(!view.show_viewport);
+    }
+}
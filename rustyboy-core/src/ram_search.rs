@@ -0,0 +1,159 @@
+use crate::mmu::Memory;
+
+/// The RAM searched, as the start address, length and first bank
+/// Bank counts depend on the cartridge and model, so they're looked up when a search starts
+const REGIONS: [(u16, u16, usize); 4] = [
+    (0xA000, 0x2000, 0),
+    (0xC000, 0x1000, 0),
+    (0xD000, 0x1000, 1),
+    (0xFF80, 0x007F, 0),
+];
+
+/// The size of the values searched for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    /// Two bytes, little endian
+    Word,
+}
+
+impl Width {
+    fn bytes(self) -> u16 {
+        match self {
+            Width::Byte => 1,
+            Width::Word => 2,
+        }
+    }
+}
+
+/// How a value has to compare to its previous value to stay a candidate
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    /// The value is a specific number, whatever it was before
+    Value(u16),
+}
+
+impl Comparison {
+    fn matches(self, previous: u16, current: u16) -> bool {
+        match self {
+            Comparison::Equal => current == previous,
+            Comparison::Changed => current != previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+            Comparison::Value(value) => current == value,
+        }
+    }
+}
+
+/// An address that still matches every filter, with its value when it was last checked
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub addr: u16,
+    pub bank: usize,
+    pub previous: u16,
+}
+
+impl Candidate {
+    pub fn value(&self, mem: &Memory, width: Width) -> u16 {
+        let lo = mem.peek(self.addr, Some(self.bank)) as u16;
+        match width {
+            Width::Byte => lo,
+            Width::Word => lo | (mem.peek(self.addr + 1, Some(self.bank)) as u16) << 8,
+        }
+    }
+}
+
+/// A classic RAM search, narrowing down the addresses of a value in cartridge RAM, WRAM and HRAM
+/// by filtering a snapshot across frames, for example by whether the value decreased after losing a life
+pub struct RamSearch {
+    pub width: Width,
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    /// Start a search with every address in RAM as a candidate
+    pub fn new(mem: &Memory, width: Width) -> Self {
+        let mut candidates = Vec::new();
+        for (start, len, first_bank) in REGIONS {
+            for bank in first_bank..mem.banks(start) {
+                // Words have to fit in the region
+                for addr in start..=start + len - width.bytes() {
+                    let mut candidate = Candidate { addr, bank, previous: 0 };
+                    candidate.previous = candidate.value(mem, width);
+                    candidates.push(candidate);
+                }
+            }
+        }
+        RamSearch { width, candidates }
+    }
+
+    /// Keep the candidates whose current value compares to their previous value, and remember
+    /// the current values for the next filter
+    pub fn filter(&mut self, mem: &Memory, comparison: Comparison) {
+        let width = self.width;
+        self.candidates.retain_mut(|candidate| {
+            let current = candidate.value(mem, width);
+            let matches = comparison.matches(candidate.previous, current);
+            candidate.previous = current;
+            matches
+        });
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_bytes() {
+        let mut mem = Memory::new();
+        mem.poke(0xC100, None, 5);
+        mem.poke(0xFF90, None, 5);
+        let mut search = RamSearch::new(&mem, Width::Byte);
+        let ram = 0x2000 + 0x1000 + 0x1000 + 0x7F;
+        assert_eq!(search.candidates().len(), ram);
+
+        search.filter(&mem, Comparison::Value(5));
+        assert_eq!(search.candidates().len(), 2);
+
+        mem.poke(0xC100, None, 4);
+        search.filter(&mem, Comparison::Decreased);
+        assert_eq!(
+            search.candidates(),
+            &[Candidate {
+                addr: 0xC100,
+                bank: 0,
+                previous: 4,
+            }]
+        );
+        search.filter(&mem, Comparison::Equal);
+        assert_eq!(search.candidates().len(), 1);
+        search.filter(&mem, Comparison::Changed);
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn test_filter_words() {
+        let mut mem = Memory::new();
+        mem.poke(0xD010, Some(1), 0xFF);
+        mem.poke(0xD011, Some(1), 0x00);
+        let mut search = RamSearch::new(&mem, Width::Word);
+        search.filter(&mem, Comparison::Value(0x00FF));
+        assert_eq!(search.candidates().len(), 1);
+
+        // Carrying into the high byte is an increase
+        mem.poke(0xD010, Some(1), 0x00);
+        mem.poke(0xD011, Some(1), 0x01);
+        search.filter(&mem, Comparison::Increased);
+        assert_eq!(search.candidates()[0].addr, 0xD010);
+        assert_eq!(search.candidates()[0].previous, 0x0100);
+    }
+}
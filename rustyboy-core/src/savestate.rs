@@ -0,0 +1,247 @@
+use crate::model::{Model, MODELS};
+
+pub const STATE_MAGIC: [u8; 4] = *b"RBST";
+pub const STATE_VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = 12;
+
+/// Errors while loading a save state
+#[derive(Debug)]
+pub enum StateError {
+    /// The data doesn't start with the save state magic
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidModel(u8),
+    /// The data ends before the whole state was read
+    Truncated,
+    /// A value in the state is out of range
+    Corrupt,
+    /// The state was saved with a different ROM
+    RomMismatch { expected: u32, actual: u32 },
+    /// The state was saved on a different model
+    ModelMismatch { expected: Model, actual: Model },
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "Not a save state"),
+            Self::UnsupportedVersion(version) => write!(f, "Unsupported save state version {}", version),
+            Self::InvalidModel(model) => write!(f, "Invalid model {} in save state", model),
+            Self::Truncated => write!(f, "Save state is truncated"),
+            Self::Corrupt => write!(f, "Save state is corrupt"),
+            Self::RomMismatch { expected, actual } => write!(
+                f,
+                "Save state was made with a different ROM (CRC-32 {:08X}, loaded ROM is {:08X})",
+                expected, actual
+            ),
+            Self::ModelMismatch { expected, actual } => write!(
+                f,
+                "Save state was made on a {}, but a {} is being emulated",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A component of the emulator that can be saved to and restored from a save state
+pub(crate) trait Savestate {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Builds a save state
+///
+/// A save state is laid out as follows, all numbers are little endian:
+///
+/// | Offset | Size | Contents |
+/// | ------ | ---- | -------- |
+/// | 0      | 4    | Magic `RBST` |
+/// | 4      | 1    | Format version, currently 1 |
+/// | 5      | 1    | Model, numbered like in movie files |
+/// | 6      | 2    | Reserved, 0 |
+/// | 8      | 4    | CRC-32 of the ROM |
+/// | 12     |      | The state of each component, in the order the Gameboy saves them |
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(model: Model, rom_checksum: u32) -> Self {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend_from_slice(&STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+        bytes.push(MODELS.iter().position(|m| *m == model).unwrap() as u8);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&rom_checksum.to_le_bytes());
+        StateWriter { bytes }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads back a save state made by `StateWriter`
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header against the running game and model
+    pub fn new(bytes: &'a [u8], model: Model, rom_checksum: u32) -> Result<Self, StateError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(if bytes.starts_with(&STATE_MAGIC) {
+                StateError::Truncated
+            } else {
+                StateError::InvalidMagic
+            });
+        }
+        if bytes[0..4] != STATE_MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+        if bytes[4] != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(bytes[4]));
+        }
+        let state_model = *MODELS
+            .get(bytes[5] as usize)
+            .ok_or(StateError::InvalidModel(bytes[5]))?;
+        let state_checksum = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if state_checksum != rom_checksum {
+            return Err(StateError::RomMismatch {
+                expected: state_checksum,
+                actual: rom_checksum,
+            });
+        }
+        if state_model != model {
+            return Err(StateError::ModelMismatch {
+                expected: state_model,
+                actual: model,
+            });
+        }
+        Ok(StateReader {
+            bytes: &bytes[HEADER_SIZE..],
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Fill a buffer with the next bytes of the state
+    pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    /// Check that the whole state was read
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = StateWriter::new(Model::Cgb, 0x12345678);
+        writer.write_u8(0x42);
+        writer.write_bool(true);
+        writer.write_u16(0x1234);
+        writer.write_u32(0xDEADBEEF);
+        writer.write_u64(0x0123456789ABCDEF);
+        writer.write_bytes(&[1, 2, 3]);
+        let bytes = writer.finish();
+        assert_eq!(bytes[..HEADER_SIZE], [b'R', b'B', b'S', b'T', 1, 4, 0, 0, 0x78, 0x56, 0x34, 0x12]);
+
+        let mut reader = StateReader::new(&bytes, Model::Cgb, 0x12345678).unwrap();
+        assert_eq!(reader.read_u8().unwrap(), 0x42);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u16().unwrap(), 0x1234);
+        assert_eq!(reader.read_u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(reader.read_u64().unwrap(), 0x0123456789ABCDEF);
+        let mut buffer = [0; 3];
+        reader.read_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+        assert!(reader.finish().is_ok());
+    }
+
+    #[test]
+    fn test_reader_errors() {
+        let bytes = StateWriter::new(Model::Dmg, 0x12345678).finish();
+        assert!(matches!(
+            StateReader::new(&bytes, Model::Dmg, 0x87654321),
+            Err(StateError::RomMismatch { .. })
+        ));
+        assert!(matches!(
+            StateReader::new(&bytes, Model::Cgb, 0x12345678),
+            Err(StateError::ModelMismatch { .. })
+        ));
+        assert!(matches!(StateReader::new(b"RBST", Model::Dmg, 0), Err(StateError::Truncated)));
+        assert!(matches!(StateReader::new(b"RBMV", Model::Dmg, 0), Err(StateError::InvalidMagic)));
+
+        let mut reader = StateReader::new(&bytes, Model::Dmg, 0x12345678).unwrap();
+        assert!(matches!(reader.read_u16(), Err(StateError::Truncated)));
+    }
+}
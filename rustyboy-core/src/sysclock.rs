@@ -1,3 +1,5 @@
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
 /// The system clock
 
 /// The amount of clock cycles per frame
@@ -32,6 +34,19 @@ impl Clock {
     }
 }
 
+impl Savestate for Clock {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.clock_cycles_passed);
+        state.write_u32(self.machine_cycles_passed);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.clock_cycles_passed = state.read_u32()?;
+        self.machine_cycles_passed = state.read_u32()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,25 @@
+/// The reflected CRC-32 polynomial used by zlib, PNG and most file formats
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+/// Calculate the CRC-32 checksum of some data
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0x00000000);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
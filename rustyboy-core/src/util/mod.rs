@@ -1 +1,2 @@
 pub mod binaryutils;
+pub mod crc32;
@@ -0,0 +1,16 @@
+// Generates rustyboy.h in OUT_DIR from src/lib.rs with cbindgen, configured by cbindgen.toml
+// A test checks that include/rustyboy.h matches it, so the checked in header can't go stale
+
+use std::{env, path::Path};
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR isn't set");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR isn't set");
+    let config = cbindgen::Config::from_file(Path::new(&crate_dir).join("cbindgen.toml"))
+        .expect("Failed to read cbindgen.toml");
+    cbindgen::generate_with_config(&crate_dir, config)
+        .expect("Failed to generate the C header")
+        .write_to_file(Path::new(&out_dir).join("rustyboy.h"));
+}
@@ -0,0 +1,168 @@
+// Generated by build.rs with cbindgen from src/lib.rs, do not edit
+
+#ifndef RUSTYBOY_H
+#define RUSTYBOY_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Width of the screen in pixels
+#define RB_SCREEN_WIDTH 160
+
+// Height of the screen in pixels
+#define RB_SCREEN_HEIGHT 144
+
+// Size of a frame as RGBA, 4 bytes per pixel
+#define RB_FRAMEBUFFER_SIZE ((RB_SCREEN_WIDTH * RB_SCREEN_HEIGHT) * 4)
+
+// Size of a palette for `rb_gameboy_set_palette`, 3 bytes for each of the 4 shades
+#define RB_PALETTE_SIZE 12
+
+// Pick the model based on the cartridge header
+#define RB_MODEL_AUTO 0
+
+#define RB_MODEL_DMG0 1
+
+#define RB_MODEL_DMG 2
+
+#define RB_MODEL_MGB 3
+
+#define RB_MODEL_SGB 4
+
+#define RB_MODEL_CGB 5
+
+#define RB_MODEL_AGB 6
+
+// Button bits for `rb_gameboy_set_buttons`
+#define RB_BUTTON_A 1
+
+#define RB_BUTTON_B 2
+
+#define RB_BUTTON_SELECT 4
+
+#define RB_BUTTON_START 8
+
+#define RB_BUTTON_RIGHT 16
+
+#define RB_BUTTON_LEFT 32
+
+#define RB_BUTTON_UP 64
+
+#define RB_BUTTON_DOWN 128
+
+// Result of every call that can fail
+typedef enum {
+  RB_ERROR_OK = 0,
+  // A required pointer was null
+  RB_ERROR_NULL_POINTER = 1,
+  // The model isn't one of the `RB_MODEL_*` values
+  RB_ERROR_INVALID_MODEL = 2,
+  // The ROM is smaller than 32 KiB
+  RB_ERROR_ROM_TOO_SMALL = 3,
+  // The cartridge header has an invalid type, ROM size or RAM size
+  RB_ERROR_INVALID_HEADER = 4,
+  // The cartridge uses a memory bank controller that isn't emulated yet
+  RB_ERROR_UNSUPPORTED_CARTRIDGE = 5,
+  // The CPU locked up on an illegal opcode, only loading a state gets it out again
+  RB_ERROR_ILLEGAL_OPCODE = 6,
+  // An output buffer is too small
+  RB_ERROR_BUFFER_TOO_SMALL = 7,
+  // A memory range goes past 0xFFFF
+  RB_ERROR_INVALID_ADDRESS = 8,
+  // The data isn't a valid save state
+  RB_ERROR_INVALID_STATE = 9,
+  // The save state was made with a different ROM or model
+  RB_ERROR_STATE_MISMATCH = 10,
+  // The emulator panicked, the handle should be freed
+  RB_ERROR_PANIC = 11,
+} RbError;
+
+// An emulated Gameboy with a cartridge inserted
+typedef struct RbGameboy RbGameboy;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A description of an error code, as a static C string
+// Takes a plain integer because C can pass any value, unknown codes get a generic message
+const char *rb_error_message(int32_t error);
+
+// Create a Gameboy running a ROM, the ROM is copied
+// The handle is written to `out` and has to be freed with `rb_gameboy_free`
+//
+// # Safety
+// `rom` has to point to `len` readable bytes and `out` has to be writable
+RbError rb_gameboy_new(const uint8_t *rom, size_t len, uint8_t model, RbGameboy **out);
+
+// Free a Gameboy, null is ignored
+//
+// # Safety
+// `gb` has to come from `rb_gameboy_new` and can't be used afterwards
+void rb_gameboy_free(RbGameboy *gb);
+
+// Run until the next frame has been drawn
+//
+// # Safety
+// `gb` has to be a live handle
+RbError rb_gameboy_run_frame(RbGameboy *gb);
+
+// Copy the last frame as RGBA into `out`, which needs `RB_FRAMEBUFFER_SIZE` bytes
+//
+// # Safety
+// `gb` has to be a live handle and `out` has to point to `len` writable bytes
+RbError rb_gameboy_framebuffer_rgba(RbGameboy *gb, uint8_t *out, size_t len);
+
+// Set the colours frames are drawn with, `rgb` holds `RB_PALETTE_SIZE` bytes of RGB
+// for white, light gray, dark gray and black
+// Frames are drawn in evenly spaced grays until a palette is set
+//
+// # Safety
+// `gb` has to be a live handle and `rgb` has to point to `len` readable bytes
+RbError rb_gameboy_set_palette(RbGameboy *gb, const uint8_t *rgb, size_t len);
+
+// Set the buttons held down, a combination of the `RB_BUTTON_*` bits
+//
+// # Safety
+// `gb` has to be a live handle
+RbError rb_gameboy_set_buttons(RbGameboy *gb, uint8_t buttons);
+
+// Read `len` bytes of memory starting at `addr`, as currently mapped
+// Reads have no side effects on the emulated machine
+//
+// # Safety
+// `gb` has to be a live handle and `out` has to point to `len` writable bytes
+RbError rb_gameboy_read_memory(RbGameboy *gb, uint16_t addr, uint8_t *out, size_t len);
+
+// Write `len` bytes of memory starting at `addr`, as currently mapped
+// ROM is patched instead of switching banks and IO registers are set without side effects
+//
+// # Safety
+// `gb` has to be a live handle and `data` has to point to `len` readable bytes
+RbError rb_gameboy_write_memory(RbGameboy *gb, uint16_t addr, const uint8_t *data, size_t len);
+
+// The size of a save state, which stays the same for as long as the handle lives
+// Returns 0 for a null handle
+//
+// # Safety
+// `gb` has to be a live handle or null
+size_t rb_gameboy_save_state_size(RbGameboy *gb);
+
+// Save the state into `out`, which needs `rb_gameboy_save_state_size` bytes
+//
+// # Safety
+// `gb` has to be a live handle and `out` has to point to `len` writable bytes
+RbError rb_gameboy_save_state(RbGameboy *gb, uint8_t *out, size_t len);
+
+// Load a state saved with the same ROM and model, nothing changes if it fails
+//
+// # Safety
+// `gb` has to be a live handle and `data` has to point to `len` readable bytes
+RbError rb_gameboy_load_state(RbGameboy *gb, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUSTYBOY_H */
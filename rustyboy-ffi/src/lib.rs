@@ -0,0 +1,442 @@
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+use rustyboy_core::{
+    cartridge::{Cartridge, CartridgeError},
+    gameboy::Gameboy,
+    model::Model,
+    ppu::palette::Palette,
+    savestate::StateError,
+};
+
+/// Width of the screen in pixels
+pub const RB_SCREEN_WIDTH: usize = 160;
+/// Height of the screen in pixels
+pub const RB_SCREEN_HEIGHT: usize = 144;
+/// Size of a frame as RGBA, 4 bytes per pixel
+pub const RB_FRAMEBUFFER_SIZE: usize = RB_SCREEN_WIDTH * RB_SCREEN_HEIGHT * 4;
+/// Size of a palette for `rb_gameboy_set_palette`, 3 bytes for each of the 4 shades
+pub const RB_PALETTE_SIZE: usize = 12;
+
+/// Pick the model based on the cartridge header
+pub const RB_MODEL_AUTO: u8 = 0;
+pub const RB_MODEL_DMG0: u8 = 1;
+pub const RB_MODEL_DMG: u8 = 2;
+pub const RB_MODEL_MGB: u8 = 3;
+pub const RB_MODEL_SGB: u8 = 4;
+pub const RB_MODEL_CGB: u8 = 5;
+pub const RB_MODEL_AGB: u8 = 6;
+
+/// Button bits for `rb_gameboy_set_buttons`
+pub const RB_BUTTON_A: u8 = 0x01;
+pub const RB_BUTTON_B: u8 = 0x02;
+pub const RB_BUTTON_SELECT: u8 = 0x04;
+pub const RB_BUTTON_START: u8 = 0x08;
+pub const RB_BUTTON_RIGHT: u8 = 0x10;
+pub const RB_BUTTON_LEFT: u8 = 0x20;
+pub const RB_BUTTON_UP: u8 = 0x40;
+pub const RB_BUTTON_DOWN: u8 = 0x80;
+
+/// Result of every call that can fail
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RbError {
+    Ok = 0,
+    /// A required pointer was null
+    NullPointer = 1,
+    /// The model isn't one of the `RB_MODEL_*` values
+    InvalidModel = 2,
+    /// The ROM is smaller than 32 KiB
+    RomTooSmall = 3,
+    /// The cartridge header has an invalid type, ROM size or RAM size
+    InvalidHeader = 4,
+    /// The cartridge uses a memory bank controller that isn't emulated yet
+    UnsupportedCartridge = 5,
+    /// The CPU locked up on an illegal opcode, only loading a state gets it out again
+    IllegalOpcode = 6,
+    /// An output buffer is too small
+    BufferTooSmall = 7,
+    /// A memory range goes past 0xFFFF
+    InvalidAddress = 8,
+    /// The data isn't a valid save state
+    InvalidState = 9,
+    /// The save state was made with a different ROM or model
+    StateMismatch = 10,
+    /// The emulator panicked, the handle should be freed
+    Panic = 11,
+}
+
+impl From<CartridgeError> for RbError {
+    fn from(e: CartridgeError) -> Self {
+        match e {
+            CartridgeError::TooSmall(_) => Self::RomTooSmall,
+            CartridgeError::Unsupported(_) => Self::UnsupportedCartridge,
+            _ => Self::InvalidHeader,
+        }
+    }
+}
+
+impl From<StateError> for RbError {
+    fn from(e: StateError) -> Self {
+        match e {
+            StateError::RomMismatch { .. } | StateError::ModelMismatch { .. } => Self::StateMismatch,
+            _ => Self::InvalidState,
+        }
+    }
+}
+
+/// An emulated Gameboy with a cartridge inserted
+pub struct RbGameboy {
+    gb: Gameboy,
+    /// The colours frames are converted to RGBA with
+    palette: Palette,
+}
+
+/// Run a call, turning a panic into an error instead of unwinding into C
+fn guard(f: impl FnOnce() -> Result<(), RbError>) -> RbError {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => RbError::Ok,
+        Ok(Err(e)) => e,
+        Err(_) => RbError::Panic,
+    }
+}
+
+/// Borrow a handle passed from C
+unsafe fn handle<'a>(gb: *mut RbGameboy) -> Result<&'a mut RbGameboy, RbError> {
+    gb.as_mut().ok_or(RbError::NullPointer)
+}
+
+/// Borrow the Gameboy behind a handle
+unsafe fn gameboy<'a>(gb: *mut RbGameboy) -> Result<&'a mut Gameboy, RbError> {
+    handle(gb).map(|gb| &mut gb.gb)
+}
+
+/// Borrow a buffer passed from C, an empty buffer may be null
+unsafe fn buffer<'a>(data: *const u8, len: usize) -> Result<&'a [u8], RbError> {
+    if len == 0 {
+        Ok(&[])
+    } else if data.is_null() {
+        Err(RbError::NullPointer)
+    } else {
+        Ok(std::slice::from_raw_parts(data, len))
+    }
+}
+
+/// Borrow a writable buffer passed from C, an empty buffer may be null
+unsafe fn buffer_mut<'a>(data: *mut u8, len: usize) -> Result<&'a mut [u8], RbError> {
+    if len == 0 {
+        Ok(&mut [])
+    } else if data.is_null() {
+        Err(RbError::NullPointer)
+    } else {
+        Ok(std::slice::from_raw_parts_mut(data, len))
+    }
+}
+
+/// Check that a memory range fits in the address space
+fn check_range(addr: u16, len: usize) -> Result<(), RbError> {
+    if addr as usize + len > 0x10000 {
+        return Err(RbError::InvalidAddress);
+    }
+    Ok(())
+}
+
+/// A description of an error code, as a static C string
+/// Takes a plain integer because C can pass any value, unknown codes get a generic message
+#[no_mangle]
+pub extern "C" fn rb_error_message(error: i32) -> *const c_char {
+    let message = match error {
+        0 => c"No error",
+        1 => c"A required pointer was null",
+        2 => c"Invalid model",
+        3 => c"ROM is too small",
+        4 => c"Invalid cartridge header",
+        5 => c"Unsupported cartridge type",
+        6 => c"CPU locked up on an illegal opcode",
+        7 => c"Buffer is too small",
+        8 => c"Memory range goes past 0xFFFF",
+        9 => c"Invalid save state",
+        10 => c"Save state was made with a different ROM or model",
+        11 => c"The emulator panicked",
+        _ => c"Unknown error",
+    };
+    message.as_ptr()
+}
+
+/// Create a Gameboy running a ROM, the ROM is copied
+/// The handle is written to `out` and has to be freed with `rb_gameboy_free`
+///
+/// # Safety
+/// `rom` has to point to `len` readable bytes and `out` has to be writable
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_new(rom: *const u8, len: usize, model: u8, out: *mut *mut RbGameboy) -> RbError {
+    guard(|| {
+        if out.is_null() {
+            return Err(RbError::NullPointer);
+        }
+        let model = match model {
+            RB_MODEL_AUTO => None,
+            RB_MODEL_DMG0 => Some(Model::Dmg0),
+            RB_MODEL_DMG => Some(Model::Dmg),
+            RB_MODEL_MGB => Some(Model::Mgb),
+            RB_MODEL_SGB => Some(Model::Sgb),
+            RB_MODEL_CGB => Some(Model::Cgb),
+            RB_MODEL_AGB => Some(Model::Agb),
+            _ => return Err(RbError::InvalidModel),
+        };
+        let cart = Cartridge::new_from_bytes(buffer(rom, len)?.to_vec())?;
+        let gb = Gameboy::new_with_cartridge(cart, model);
+        *out = Box::into_raw(Box::new(RbGameboy {
+            gb,
+            palette: Palette::default(),
+        }));
+        Ok(())
+    })
+}
+
+/// Free a Gameboy, null is ignored
+///
+/// # Safety
+/// `gb` has to come from `rb_gameboy_new` and can't be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_free(gb: *mut RbGameboy) {
+    if !gb.is_null() {
+        drop(Box::from_raw(gb));
+    }
+}
+
+/// Run until the next frame has been drawn
+///
+/// # Safety
+/// `gb` has to be a live handle
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_run_frame(gb: *mut RbGameboy) -> RbError {
+    guard(|| gameboy(gb)?.step().map_err(|_| RbError::IllegalOpcode))
+}
+
+/// Copy the last frame as RGBA into `out`, which needs `RB_FRAMEBUFFER_SIZE` bytes
+///
+/// # Safety
+/// `gb` has to be a live handle and `out` has to point to `len` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_framebuffer_rgba(gb: *mut RbGameboy, out: *mut u8, len: usize) -> RbError {
+    guard(|| {
+        let gb = handle(gb)?;
+        if len < RB_FRAMEBUFFER_SIZE {
+            return Err(RbError::BufferTooSmall);
+        }
+        let out = buffer_mut(out, RB_FRAMEBUFFER_SIZE)?;
+        gb.palette.frame_to_rgba(&gb.gb.mmu.ppu.frame_buffer, out);
+        Ok(())
+    })
+}
+
+/// Set the colours frames are drawn with, `rgb` holds `RB_PALETTE_SIZE` bytes of RGB
+/// for white, light gray, dark gray and black
+/// Frames are drawn in evenly spaced grays until a palette is set
+///
+/// # Safety
+/// `gb` has to be a live handle and `rgb` has to point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_set_palette(gb: *mut RbGameboy, rgb: *const u8, len: usize) -> RbError {
+    guard(|| {
+        let gb = handle(gb)?;
+        if len < RB_PALETTE_SIZE {
+            return Err(RbError::BufferTooSmall);
+        }
+        let mut colors = [[0; 3]; 4];
+        for (color, rgb) in colors.iter_mut().zip(buffer(rgb, RB_PALETTE_SIZE)?.chunks_exact(3)) {
+            color.copy_from_slice(rgb);
+        }
+        gb.palette = Palette::new(colors);
+        Ok(())
+    })
+}
+
+/// Set the buttons held down, a combination of the `RB_BUTTON_*` bits
+///
+/// # Safety
+/// `gb` has to be a live handle
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_set_buttons(gb: *mut RbGameboy, buttons: u8) -> RbError {
+    guard(|| {
+        gameboy(gb)?.mmu.joypad.set_pressed_keys(buttons);
+        Ok(())
+    })
+}
+
+/// Read `len` bytes of memory starting at `addr`, as currently mapped
+/// Reads have no side effects on the emulated machine
+///
+/// # Safety
+/// `gb` has to be a live handle and `out` has to point to `len` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_read_memory(gb: *mut RbGameboy, addr: u16, out: *mut u8, len: usize) -> RbError {
+    guard(|| {
+        let gb = gameboy(gb)?;
+        check_range(addr, len)?;
+        for (i, byte) in buffer_mut(out, len)?.iter_mut().enumerate() {
+            *byte = gb.mmu.peek(addr + i as u16, None);
+        }
+        Ok(())
+    })
+}
+
+/// Write `len` bytes of memory starting at `addr`, as currently mapped
+/// ROM is patched instead of switching banks and IO registers are set without side effects
+///
+/// # Safety
+/// `gb` has to be a live handle and `data` has to point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_write_memory(gb: *mut RbGameboy, addr: u16, data: *const u8, len: usize) -> RbError {
+    guard(|| {
+        let gb = gameboy(gb)?;
+        check_range(addr, len)?;
+        for (i, byte) in buffer(data, len)?.iter().enumerate() {
+            gb.mmu.poke(addr + i as u16, None, *byte);
+        }
+        Ok(())
+    })
+}
+
+/// The size of a save state, which stays the same for as long as the handle lives
+/// Returns 0 for a null handle
+///
+/// # Safety
+/// `gb` has to be a live handle or null
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_save_state_size(gb: *mut RbGameboy) -> usize {
+    match gb.as_ref() {
+        Some(gb) => panic::catch_unwind(AssertUnwindSafe(|| gb.gb.save_state().len())).unwrap_or(0),
+        None => 0,
+    }
+}
+
+/// Save the state into `out`, which needs `rb_gameboy_save_state_size` bytes
+///
+/// # Safety
+/// `gb` has to be a live handle and `out` has to point to `len` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_save_state(gb: *mut RbGameboy, out: *mut u8, len: usize) -> RbError {
+    guard(|| {
+        let state = gameboy(gb)?.save_state();
+        if len < state.len() {
+            return Err(RbError::BufferTooSmall);
+        }
+        buffer_mut(out, state.len())?.copy_from_slice(&state);
+        Ok(())
+    })
+}
+
+/// Load a state saved with the same ROM and model, nothing changes if it fails
+///
+/// # Safety
+/// `gb` has to be a live handle and `data` has to point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn rb_gameboy_load_state(gb: *mut RbGameboy, data: *const u8, len: usize) -> RbError {
+    guard(|| Ok(gameboy(gb)?.load_state(buffer(data, len)?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    const TEST_ROM: &str = "../rustyboy-core/resources/test-rom.gb";
+
+    unsafe fn new_gameboy(rom: &[u8], model: u8) -> Result<*mut RbGameboy, RbError> {
+        let mut gb = std::ptr::null_mut();
+        match rb_gameboy_new(rom.as_ptr(), rom.len(), model, &mut gb) {
+            RbError::Ok => Ok(gb),
+            e => Err(e),
+        }
+    }
+
+    #[test]
+    fn test_gameboy() {
+        let rom = std::fs::read(TEST_ROM).unwrap();
+        unsafe {
+            let gb = new_gameboy(&rom, RB_MODEL_AUTO).unwrap();
+            for _ in 0..10 {
+                assert_eq!(rb_gameboy_run_frame(gb), RbError::Ok);
+            }
+
+            let mut frame = vec![0; RB_FRAMEBUFFER_SIZE];
+            assert_eq!(rb_gameboy_framebuffer_rgba(gb, frame.as_mut_ptr(), frame.len()), RbError::Ok);
+            assert_eq!(frame[3], 0xFF);
+            assert_eq!(rb_gameboy_framebuffer_rgba(gb, frame.as_mut_ptr(), 4), RbError::BufferTooSmall);
+
+            let palette = [0x9B, 0xBC, 0x0F, 0x8B, 0xAC, 0x0F, 0x30, 0x62, 0x30, 0x0F, 0x38, 0x0F];
+            assert_eq!(rb_gameboy_set_palette(gb, palette.as_ptr(), palette.len()), RbError::Ok);
+            assert_eq!(rb_gameboy_set_palette(gb, palette.as_ptr(), 3), RbError::BufferTooSmall);
+            assert_eq!(rb_gameboy_framebuffer_rgba(gb, frame.as_mut_ptr(), frame.len()), RbError::Ok);
+            let expected = Palette::DMG.rgba((*gb).gb.mmu.ppu.frame_buffer[0]);
+            assert_eq!(frame[..4], expected);
+
+            assert_eq!(rb_gameboy_set_buttons(gb, RB_BUTTON_START | RB_BUTTON_UP), RbError::Ok);
+            assert_eq!((*gb).gb.mmu.joypad.pressed_keys(), 0x48);
+
+            let mut state = vec![0; rb_gameboy_save_state_size(gb)];
+            assert_eq!(rb_gameboy_save_state(gb, state.as_mut_ptr(), state.len()), RbError::Ok);
+            assert_eq!(rb_gameboy_save_state(gb, state.as_mut_ptr(), 1), RbError::BufferTooSmall);
+
+            let mut before = [0; 2];
+            assert_eq!(rb_gameboy_read_memory(gb, 0xC000, before.as_mut_ptr(), before.len()), RbError::Ok);
+            let data = [!before[0], !before[1]];
+            assert_eq!(rb_gameboy_write_memory(gb, 0xC000, data.as_ptr(), data.len()), RbError::Ok);
+            let mut out = [0; 2];
+            assert_eq!(rb_gameboy_read_memory(gb, 0xC000, out.as_mut_ptr(), out.len()), RbError::Ok);
+            assert_eq!(out, data);
+            assert_eq!(rb_gameboy_read_memory(gb, 0xFFFF, out.as_mut_ptr(), out.len()), RbError::InvalidAddress);
+
+            assert_eq!(rb_gameboy_load_state(gb, state.as_ptr(), state.len()), RbError::Ok);
+            assert_eq!(rb_gameboy_read_memory(gb, 0xC000, out.as_mut_ptr(), out.len()), RbError::Ok);
+            assert_eq!(out, before);
+            assert_eq!(rb_gameboy_load_state(gb, state.as_ptr(), 4), RbError::InvalidState);
+
+            let cgb = new_gameboy(&rom, RB_MODEL_CGB).unwrap();
+            assert_eq!(rb_gameboy_load_state(cgb, state.as_ptr(), state.len()), RbError::StateMismatch);
+            rb_gameboy_free(cgb);
+            rb_gameboy_free(gb);
+        }
+    }
+
+    /// The header generated by build.rs has to be copied over the checked in one after the bindings change
+    #[test]
+    fn test_header_up_to_date() {
+        let generated = concat!(env!("OUT_DIR"), "/rustyboy.h");
+        assert!(
+            std::fs::read_to_string(generated).unwrap() == include_str!("../include/rustyboy.h"),
+            "include/rustyboy.h is out of date, copy {} over it",
+            generated
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let rom = std::fs::read(TEST_ROM).unwrap();
+        unsafe {
+            assert_eq!(new_gameboy(&rom[..0x100], RB_MODEL_AUTO), Err(RbError::RomTooSmall));
+            assert_eq!(new_gameboy(&rom, 7), Err(RbError::InvalidModel));
+            let mut unsupported = rom.clone();
+            unsupported[0x0147] = 0x19;
+            assert_eq!(new_gameboy(&unsupported, RB_MODEL_AUTO), Err(RbError::UnsupportedCartridge));
+            assert_eq!(rb_gameboy_new(rom.as_ptr(), rom.len(), 0, std::ptr::null_mut()), RbError::NullPointer);
+
+            assert_eq!(rb_gameboy_run_frame(std::ptr::null_mut()), RbError::NullPointer);
+            assert_eq!(rb_gameboy_save_state_size(std::ptr::null_mut()), 0);
+            rb_gameboy_free(std::ptr::null_mut());
+
+            let gb = new_gameboy(&rom, RB_MODEL_AUTO).unwrap();
+            (*gb).gb.mmu.poke(0x0100, None, 0xDD);
+            assert_eq!(rb_gameboy_run_frame(gb), RbError::IllegalOpcode);
+            rb_gameboy_free(gb);
+
+            let message = CStr::from_ptr(rb_error_message(RbError::IllegalOpcode as i32));
+            assert_eq!(message.to_str().unwrap(), "CPU locked up on an illegal opcode");
+            for code in [-1, 12, i32::MAX] {
+                assert_eq!(CStr::from_ptr(rb_error_message(code)).to_str().unwrap(), "Unknown error");
+            }
+        }
+    }
+}
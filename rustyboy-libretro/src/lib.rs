@@ -0,0 +1,560 @@
+mod libretro;
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+
+use rustyboy_core::{
+    cartridge::Cartridge,
+    cheats::{Cheat, Cheats},
+    gameboy::Gameboy,
+    ppu::palette::Palette,
+};
+
+use crate::libretro::*;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// The Gameboy's refresh rate, 4194304 Hz / 70224 clock cycles per frame
+const FRAME_RATE: f64 = 4194304.0 / 70224.0;
+/// The core has no audio output yet, silence is sent at this rate so frontends can sync to it
+const SAMPLE_RATE: f64 = 48000.0;
+
+/// The libretro joypad buttons in the bit order of `Joypad::pressed_keys`
+const JOYPAD_IDS: [c_uint; 8] = [
+    RETRO_DEVICE_ID_JOYPAD_A,
+    RETRO_DEVICE_ID_JOYPAD_B,
+    RETRO_DEVICE_ID_JOYPAD_SELECT,
+    RETRO_DEVICE_ID_JOYPAD_START,
+    RETRO_DEVICE_ID_JOYPAD_RIGHT,
+    RETRO_DEVICE_ID_JOYPAD_LEFT,
+    RETRO_DEVICE_ID_JOYPAD_UP,
+    RETRO_DEVICE_ID_JOYPAD_DOWN,
+];
+
+/// The core option picking the colours of the four shades
+const PALETTE_KEY: &CStr = c"rustyboy_palette";
+
+/// Read the palette option, None if the frontend doesn't have it
+fn palette_option(environment: RetroEnvironment) -> Option<Palette> {
+    let mut variable = RetroVariable {
+        key: PALETTE_KEY.as_ptr(),
+        value: std::ptr::null(),
+    };
+    let found = unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut _ as *mut c_void) };
+    if !found || variable.value.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(variable.value) }.to_str().ok()?.parse().ok()
+}
+
+/// The callbacks registered by the frontend
+#[derive(Copy, Clone)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    log: Option<RetroLogPrintf>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+/// A loaded game
+struct Core {
+    /// Boxed so the emulator isn't copied around on the stack
+    gb: Box<Gameboy>,
+    /// The ROM, kept to power cycle on reset
+    rom: Vec<u8>,
+    /// The last frame as XRGB8888
+    frame: Vec<u32>,
+    palette: Palette,
+    /// Fraction of an audio frame carried over to the next video frame
+    audio_frames_due: f64,
+    audio: Vec<i16>,
+    /// The cheats set by the frontend, keyed by their index in its cheat list
+    cheats: BTreeMap<c_uint, Cheat>,
+}
+
+impl Core {
+    fn new(rom: Vec<u8>) -> Option<Self> {
+        let cart = Cartridge::new_from_bytes(rom.clone()).ok()?;
+        let gb = Box::new(Gameboy::new_with_cartridge(cart, None));
+        Some(Core {
+            gb,
+            rom,
+            frame: vec![0; WIDTH * HEIGHT],
+            palette: Palette::default(),
+            audio_frames_due: 0.0,
+            audio: Vec::new(),
+            cheats: BTreeMap::new(),
+        })
+    }
+
+    /// Power cycle, keeping the battery backed RAM and cheats
+    fn reset(&mut self) {
+        // The ROM already loaded once, so it can't fail now
+        let cart = Cartridge::new_from_bytes(self.rom.clone()).expect("ROM failed to load again");
+        let mut gb = Box::new(Gameboy::new_with_cartridge(cart, None));
+        // The frontend keeps a pointer to the save RAM, so the buffer itself has to be kept
+        gb.mmu.cartridge_mut().ram = std::mem::take(&mut self.gb.mmu.cartridge_mut().ram);
+        gb.mmu.cheats = std::mem::take(&mut self.gb.mmu.cheats);
+        self.gb = gb;
+    }
+
+    fn run(&mut self, callbacks: Callbacks) {
+        if let Some(environment) = callbacks.environment {
+            let mut updated = false;
+            let updated_ptr = &mut updated as *mut bool as *mut c_void;
+            if unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, updated_ptr) } && updated {
+                self.palette = palette_option(environment).unwrap_or_default();
+            }
+        }
+        if let Some(input_poll) = callbacks.input_poll {
+            unsafe { input_poll() };
+        }
+        if let Some(input_state) = callbacks.input_state {
+            let mut keys = 0;
+            for (bit, id) in JOYPAD_IDS.into_iter().enumerate() {
+                if unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, id) } != 0 {
+                    keys |= 1 << bit;
+                }
+            }
+            self.gb.mmu.joypad.set_pressed_keys(keys);
+        }
+
+        // Once the CPU locks up the last frame stays on screen
+        let _ = self.gb.step();
+
+        for (pixel, color) in self.frame.iter_mut().zip(self.gb.mmu.ppu.frame_buffer.iter()) {
+            let [r, g, b, _] = self.palette.rgba(*color);
+            *pixel = u32::from_be_bytes([0, r, g, b]);
+        }
+        if let Some(video_refresh) = callbacks.video_refresh {
+            let pitch = WIDTH * std::mem::size_of::<u32>();
+            unsafe { video_refresh(self.frame.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, pitch) };
+        }
+
+        self.audio_frames_due += SAMPLE_RATE / FRAME_RATE;
+        let frames = self.audio_frames_due as usize;
+        self.audio_frames_due -= frames as f64;
+        self.audio.resize(frames * 2, 0);
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            unsafe { audio_sample_batch(self.audio.as_ptr(), frames) };
+        }
+    }
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    log: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+/// Log a warning through the frontend, or to stderr if it has no log interface
+fn log_warning(message: &str) {
+    match (callbacks().log, CString::new(message)) {
+        (Some(log), Ok(message)) => unsafe { log(RETRO_LOG_WARN, c"%s\n".as_ptr(), message.as_ptr()) },
+        _ => eprintln!("{}", message),
+    }
+}
+
+/// Run a closure with the loaded game, does nothing if no game is loaded
+/// A panic unloads the game and asks the frontend to shut the core down instead of unwinding into C
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+    let mut core = CORE.lock().unwrap();
+    let Some(loaded) = core.as_mut() else {
+        return default;
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(loaded))) {
+        Ok(value) => value,
+        Err(_) => {
+            *core = None;
+            drop(core);
+            shutdown();
+            default
+        }
+    }
+}
+
+/// Ask the frontend to stop running the core
+fn shutdown() {
+    if let Some(environment) = callbacks().environment {
+        unsafe { environment(RETRO_ENVIRONMENT_SHUTDOWN, std::ptr::null_mut()) };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: RetroEnvironment) {
+    let mut log = RetroLogCallback { log: None };
+    let has_log = unsafe { cb(RETRO_ENVIRONMENT_GET_LOG_INTERFACE, &mut log as *mut _ as *mut c_void) };
+    let mut callbacks = CALLBACKS.lock().unwrap();
+    callbacks.environment = Some(cb);
+    callbacks.log = if has_log { log.log } else { None };
+    drop(callbacks);
+    // The first choice is the default
+    let variables = [
+        RetroVariable {
+            key: PALETTE_KEY.as_ptr(),
+            value: c"Palette; gray|dmg|pocket|light".as_ptr(),
+        },
+        RetroVariable {
+            key: std::ptr::null(),
+            value: std::ptr::null(),
+        },
+    ];
+    unsafe { cb(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void) };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: RetroVideoRefresh) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(cb);
+}
+
+/// Audio is sent in batches, so single samples are never used
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: RetroAudioSampleBatch) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: RetroInputPoll) {
+    CALLBACKS.lock().unwrap().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: RetroInputState) {
+    CALLBACKS.lock().unwrap().input_state = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// `info` has to point to a `retro_system_info` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"RustyBoy".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"gb|gbc".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` has to point to a `retro_system_av_info` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FRAME_RATE,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+/// Only the joypad is supported
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), Core::reset);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    with_core((), |core| core.run(callbacks));
+}
+
+/// The state of a game always has the same size
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.gb.save_state().len())
+}
+
+/// # Safety
+/// `data` has to point to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    with_core(false, |core| {
+        let state = core.gb.save_state();
+        if state.len() > size {
+            return false;
+        }
+        std::slice::from_raw_parts_mut(data as *mut u8, state.len()).copy_from_slice(&state);
+        true
+    })
+}
+
+/// # Safety
+/// `data` has to point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core| core.gb.load_state(state).is_ok())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core((), |core| {
+        core.cheats.clear();
+        core.gb.mmu.cheats = Cheats::new();
+    });
+}
+
+/// Set the Game Genie or GameShark cheat at an index of the frontend's cheat list, replacing
+/// the one set there before. Several codes can be separated by `+`
+///
+/// # Safety
+/// `code` has to be a valid C string
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy();
+    with_core((), |core| {
+        match Cheat::new(&code, "") {
+            Ok(mut cheat) => {
+                cheat.enabled = enabled;
+                core.cheats.insert(index, cheat);
+            }
+            Err(e) => {
+                log_warning(&format!("Ignoring cheat {} \"{}\": {}", index, code, e));
+                core.cheats.remove(&index);
+            }
+        }
+        core.gb.mmu.cheats.cheats = core.cheats.values().cloned().collect();
+    });
+}
+
+/// # Safety
+/// `game` has to point to a `retro_game_info` with `size` bytes of ROM at `data`
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+
+    let environment = match callbacks().environment {
+        Some(environment) => environment,
+        None => return false,
+    };
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+
+    let core = panic::catch_unwind(|| {
+        let mut core = Core::new(rom)?;
+        core.palette = palette_option(environment).unwrap_or_default();
+        Some(core)
+    });
+    // A ROM that makes the emulator panic while loading is rejected like any other broken ROM
+    let core = core.unwrap_or(None);
+    let loaded = core.is_some();
+    *CORE.lock().unwrap() = core;
+    loaded
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// Only battery backed cartridge RAM is exposed, as the save RAM
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(std::ptr::null_mut(), |core| {
+        let cart = core.gb.mmu.cartridge_mut();
+        if id == RETRO_MEMORY_SAVE_RAM && cart.cart_type.has_battery() {
+            cart.ram.as_mut_ptr() as *mut c_void
+        } else {
+            std::ptr::null_mut()
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(0, |core| {
+        let cart = core.gb.mmu.cartridge();
+        if id == RETRO_MEMORY_SAVE_RAM && cart.cart_type.has_battery() {
+            cart.ram.len()
+        } else {
+            0
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static VIDEO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+    static AUDIO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888,
+            RETRO_ENVIRONMENT_SET_VARIABLES => true,
+            RETRO_ENVIRONMENT_GET_VARIABLE => {
+                let variable = &mut *(data as *mut RetroVariable);
+                assert_eq!(CStr::from_ptr(variable.key), PALETTE_KEY);
+                variable.value = c"pocket".as_ptr();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert!(!data.is_null());
+        assert_eq!((width, height, pitch), (160, 144, 640));
+        VIDEO_FRAMES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+        assert!(!data.is_null());
+        AUDIO_FRAMES.fetch_add(frames, Ordering::Relaxed);
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+        (port == 0 && device == RETRO_DEVICE_JOYPAD && id == RETRO_DEVICE_ID_JOYPAD_START) as i16
+    }
+
+    /// Drive the core the way a libretro frontend would
+    #[test]
+    fn test_host() {
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        assert_eq!(retro_api_version(), RETRO_API_VERSION);
+
+        let rom = std::fs::read("../rustyboy-core/resources/test-rom.gb").unwrap();
+        let game = RetroGameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(unsafe { retro_load_game(&game) });
+
+        let mut av_info = std::mem::MaybeUninit::<RetroSystemAvInfo>::uninit();
+        let av_info = unsafe {
+            retro_get_system_av_info(av_info.as_mut_ptr());
+            av_info.assume_init()
+        };
+        assert_eq!(av_info.geometry.base_width, 160);
+        assert_eq!(with_core(Palette::default(), |core| core.palette), Palette::POCKET);
+
+        for _ in 0..60 {
+            retro_run();
+        }
+        assert_eq!(VIDEO_FRAMES.load(Ordering::Relaxed), 60);
+        // One second of audio, give or take a frame
+        let audio_frames = AUDIO_FRAMES.load(Ordering::Relaxed) as f64;
+        assert!((audio_frames - SAMPLE_RATE * 60.0 / FRAME_RATE).abs() < 1.0);
+        assert_eq!(with_core(0, |core| core.gb.mmu.joypad.pressed_keys()), 0x08);
+
+        unsafe { retro_cheat_set(0, true, c"3EA-14F".as_ptr()) };
+        assert_eq!(with_core(0, |core| core.gb.mmu.get_byte(0x0A14 as usize)), 0x3E);
+        retro_reset();
+        assert_eq!(with_core(0, |core| core.gb.mmu.cheats.cheats.len()), 1);
+        unsafe { retro_cheat_set(0, false, c"3EA-14F".as_ptr()) };
+        assert_eq!(with_core(0, |core| core.gb.mmu.get_byte(0x0A14 as usize)), 0x00);
+        unsafe { retro_cheat_set(1, true, c"3EA-14F".as_ptr()) };
+        unsafe { retro_cheat_set(2, true, c"not a cheat".as_ptr()) };
+        assert_eq!(with_core(0, |core| core.gb.mmu.cheats.cheats.len()), 2);
+        retro_cheat_reset();
+        assert_eq!(with_core(1, |core| core.gb.mmu.cheats.cheats.len()), 0);
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        retro_run();
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert!(!unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, 1) });
+        assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 1) });
+
+        // The test ROM has no battery, so there's no save RAM
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SAVE_RAM), 0);
+        assert!(retro_get_memory_data(RETRO_MEMORY_SAVE_RAM).is_null());
+
+        // A panic unloads the game instead of unwinding into the frontend
+        assert!(with_core(true, |_| panic!("Emulator panicked")));
+        assert!(with_core(true, |_| false));
+        retro_run();
+        assert!(unsafe { retro_load_game(&game) });
+
+        retro_unload_game();
+        retro_deinit();
+        assert!(with_core(true, |_| false));
+
+        let broken = RetroGameInfo {
+            path: std::ptr::null(),
+            data: [0u8; 4].as_ptr() as *const c_void,
+            size: 4,
+            meta: std::ptr::null(),
+        };
+        assert!(!unsafe { retro_load_game(&broken) });
+    }
+}
@@ -0,0 +1,319 @@
+use pyo3::exceptions::{PyIndexError, PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes, PyMemoryView, PySlice};
+
+use rustyboy_core::{
+    cartridge::{Cartridge, CartridgeError},
+    gameboy::{EmulationError, Gameboy},
+    io::joypad::Key,
+    model::Model,
+    ppu::palette::Palette,
+};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// Size of the address space seen through `Gameboy.memory`
+const MEMORY_SIZE: usize = 0x10000;
+
+fn cartridge_error(e: CartridgeError) -> PyErr {
+    match e {
+        CartridgeError::Io(e) => e.into(),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+fn emulation_error(e: EmulationError) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+fn parse_model(model: Option<&str>) -> PyResult<Option<Model>> {
+    model.map(|model| model.parse().map_err(PyValueError::new_err)).transpose()
+}
+
+fn parse_button(name: &str) -> PyResult<Key> {
+    match name.to_ascii_lowercase().as_str() {
+        "a" => Ok(Key::A),
+        "b" => Ok(Key::B),
+        "select" => Ok(Key::Select),
+        "start" => Ok(Key::Start),
+        "right" => Ok(Key::Right),
+        "left" => Ok(Key::Left),
+        "up" => Ok(Key::Up),
+        "down" => Ok(Key::Down),
+        _ => Err(PyValueError::new_err(format!("Unknown button {}", name))),
+    }
+}
+
+/// A Gameboy running a ROM
+/// The core isn't Sync, so an instance can only be used from the thread that created it
+#[pyclass(name = "Gameboy", module = "rustyboy", unsendable)]
+struct PyGameboy {
+    /// Boxed so the emulator isn't copied around on the stack when the object is created
+    gb: Box<Gameboy>,
+    /// The colours `screen` is drawn with
+    palette: Palette,
+}
+
+#[pymethods]
+impl PyGameboy {
+    /// Load a ROM file, the model is picked from the cartridge header unless one is given
+    #[new]
+    #[pyo3(signature = (rom, model=None))]
+    fn new(rom: &str, model: Option<&str>) -> PyResult<Self> {
+        let model = parse_model(model)?;
+        let cart = Cartridge::new_from_rom(rom).map_err(cartridge_error)?;
+        Ok(PyGameboy {
+            gb: Box::new(Gameboy::new_with_cartridge(cart, model)),
+            palette: Palette::default(),
+        })
+    }
+
+    /// Load a ROM from its contents
+    #[staticmethod]
+    #[pyo3(signature = (rom, model=None))]
+    fn from_bytes(rom: &[u8], model: Option<&str>) -> PyResult<Self> {
+        let model = parse_model(model)?;
+        let cart = Cartridge::new_from_bytes(rom.to_vec()).map_err(cartridge_error)?;
+        Ok(PyGameboy {
+            gb: Box::new(Gameboy::new_with_cartridge(cart, model)),
+            palette: Palette::default(),
+        })
+    }
+
+    /// The title from the cartridge header
+    #[getter]
+    fn title(&self) -> &str {
+        &self.gb.mmu.cart_title
+    }
+
+    /// The model being emulated
+    #[getter]
+    fn model(&self) -> String {
+        self.gb.mmu.model().to_string()
+    }
+
+    /// Run a number of frames, raises RuntimeError if the CPU locks up
+    #[pyo3(signature = (frames=1))]
+    fn tick(&mut self, py: Python<'_>, frames: u32) -> PyResult<()> {
+        let gb = &mut self.gb;
+        py.detach(|| (0..frames).try_for_each(|_| gb.step())).map_err(emulation_error)
+    }
+
+    /// Run for at least a number of clock cycles, finishing the last instruction
+    /// Returns the number of clock cycles that were run
+    fn run_cycles(&mut self, py: Python<'_>, cycles: u32) -> PyResult<u32> {
+        let gb = &mut self.gb;
+        py.detach(|| gb.run_cycles(cycles)).map_err(emulation_error)
+    }
+
+    /// Hold a button down: a, b, select, start, right, left, up or down
+    fn button_press(&mut self, button: &str) -> PyResult<()> {
+        self.gb.mmu.joypad.push_key(parse_button(button)?);
+        Ok(())
+    }
+
+    /// Let go of a button
+    fn button_release(&mut self, button: &str) -> PyResult<()> {
+        self.gb.mmu.joypad.release_key(parse_button(button)?);
+        Ok(())
+    }
+
+    /// The buttons held down as a bitmask, from bit 0 to 7: a, b, select, start, right, left, up, down
+    #[getter]
+    fn get_buttons(&self) -> u8 {
+        self.gb.mmu.joypad.pressed_keys()
+    }
+
+    #[setter]
+    fn set_buttons(&mut self, buttons: u8) {
+        self.gb.mmu.joypad.set_pressed_keys(buttons);
+    }
+
+    /// A copy of the last frame as RGBA, a buffer of shape (144, 160, 4)
+    /// `numpy.asarray(gb.screen)` turns it into an array without copying it again
+    #[getter]
+    fn screen<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let mut rgba = vec![0; WIDTH * HEIGHT * 4];
+        self.palette.frame_to_rgba(&self.gb.mmu.ppu.frame_buffer, &mut rgba);
+        let bytes = PyByteArray::new(py, &rgba);
+        PyMemoryView::from(&bytes)?.call_method1("cast", ("B", (HEIGHT, WIDTH, 4)))
+    }
+
+    /// Set the colours `screen` is drawn with: gray, dmg, pocket, light, or four comma separated
+    /// hex colours from white to black like "e0f8d0,88c070,346856,081820"
+    fn set_palette(&mut self, palette: &str) -> PyResult<()> {
+        self.palette = palette.parse().map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+
+    /// The address space as currently mapped, indexed by address or slice
+    #[getter]
+    fn memory(slf: Py<Self>) -> Memory {
+        Memory { gb: slf }
+    }
+
+    /// Save the state of the whole machine
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.gb.save_state())
+    }
+
+    /// Load a state saved with the same ROM and model, raises ValueError if it can't be loaded
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.gb.load_state(state).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+}
+
+/// Reads and writes memory without side effects, `gb.memory[0xC000]` or `gb.memory[0xC000:0xC010]`
+/// Writes to ROM patch it instead of switching banks
+#[pyclass(module = "rustyboy", unsendable)]
+struct Memory {
+    gb: Py<PyGameboy>,
+}
+
+/// The addresses an index or slice covers
+fn addresses(key: &Bound<'_, PyAny>) -> PyResult<Vec<u16>> {
+    if let Ok(slice) = key.cast::<PySlice>() {
+        let indices = slice.indices(MEMORY_SIZE as isize)?;
+        return Ok((0..indices.slicelength)
+            .map(|i| (indices.start + i as isize * indices.step) as u16)
+            .collect());
+    }
+    let addr: isize = key.extract()?;
+    if !(0..MEMORY_SIZE as isize).contains(&addr) {
+        return Err(PyIndexError::new_err(format!("Address {:#X} is out of range", addr)));
+    }
+    Ok(vec![addr as u16])
+}
+
+#[pymethods]
+impl Memory {
+    fn __len__(&self) -> usize {
+        MEMORY_SIZE
+    }
+
+    /// An int for an address, bytes for a slice
+    fn __getitem__<'py>(&self, py: Python<'py>, key: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let gb = self.gb.borrow(py);
+        let bytes: Vec<u8> = addresses(key)?.into_iter().map(|addr| gb.gb.mmu.peek(addr, None)).collect();
+        if key.is_instance_of::<PySlice>() {
+            Ok(PyBytes::new(py, &bytes).into_any())
+        } else {
+            Ok(bytes[0].into_pyobject(py)?.into_any())
+        }
+    }
+
+    /// An int for an address, bytes of the same length for a slice
+    fn __setitem__(&self, py: Python<'_>, key: &Bound<'_, PyAny>, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let addresses = addresses(key)?;
+        let values: Vec<u8> = if key.is_instance_of::<PySlice>() {
+            value.extract()?
+        } else {
+            vec![value.extract()?]
+        };
+        if values.len() != addresses.len() {
+            return Err(PyTypeError::new_err(format!(
+                "Can't write {} bytes to {} addresses",
+                values.len(),
+                addresses.len()
+            )));
+        }
+        let mut gb = self.gb.borrow_mut(py);
+        for (addr, value) in addresses.into_iter().zip(values) {
+            gb.gb.mmu.poke(addr, None, value);
+        }
+        Ok(())
+    }
+}
+
+/// Python bindings for the emulator core
+#[pymodule]
+#[pyo3(name = "rustyboy")]
+fn rustyboy_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyGameboy>()?;
+    m.add_class::<Memory>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    const TEST_ROM: &str = "../rustyboy-core/resources/test-rom.gb";
+
+    fn run(script: &std::ffi::CStr) {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "rustyboy").unwrap();
+            rustyboy_module(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("rustyboy", module).unwrap();
+            globals.set_item("TEST_ROM", TEST_ROM).unwrap();
+            if let Err(e) = py.run(script, Some(&globals), None) {
+                panic!("{}", e);
+            }
+        });
+    }
+
+    #[test]
+    fn test_gameboy() {
+        run(cr#"
+gb = rustyboy.Gameboy(TEST_ROM)
+assert gb.model == "DMG"
+gb.tick(10)
+assert gb.run_cycles(100) >= 100
+
+screen = gb.screen
+assert screen.shape == (144, 160, 4)
+assert screen[0, 0, 3] == 255
+gb.set_palette("dmg")
+assert gb.screen[0, 0, 3] == 255
+assert gb.screen[0, 0, 0] in (155, 139, 48, 15)
+
+gb.button_press("start")
+gb.button_press("UP")
+assert gb.buttons == 0x48
+gb.button_release("start")
+gb.buttons = 0x01
+assert gb.buttons == 0x01
+
+state = gb.save_state()
+before = gb.memory[0xC000:0xC002]
+gb.memory[0xC000] = before[0] ^ 0xFF
+gb.memory[0xC001:0xC002] = bytes([before[1] ^ 0xFF])
+assert gb.memory[0xC000] == before[0] ^ 0xFF
+assert len(gb.memory) == 0x10000
+gb.load_state(state)
+assert gb.memory[0xC000:0xC002] == before
+"#);
+    }
+
+    #[test]
+    fn test_errors() {
+        run(cr#"
+def raises(error, f):
+    try:
+        f()
+    except error:
+        return
+    raise AssertionError(f"{error} not raised")
+
+rom = open(TEST_ROM, "rb").read()
+raises(ValueError, lambda: rustyboy.Gameboy.from_bytes(rom[:0x100]))
+raises(ValueError, lambda: rustyboy.Gameboy.from_bytes(rom, "NES"))
+raises(OSError, lambda: rustyboy.Gameboy("missing.gb"))
+
+gb = rustyboy.Gameboy.from_bytes(rom, "cgb")
+assert gb.model == "CGB"
+raises(ValueError, lambda: gb.button_press("turbo"))
+raises(ValueError, lambda: gb.set_palette("sepia"))
+raises(IndexError, lambda: gb.memory[0x10000])
+raises(ValueError, lambda: gb.load_state(b"RBST"))
+raises(ValueError, lambda: gb.load_state(rustyboy.Gameboy(TEST_ROM).save_state()))
+
+gb.memory[0x0100] = 0xDD
+raises(RuntimeError, lambda: gb.tick())
+"#);
+    }
+}
@@ -0,0 +1,433 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustyboy_core::ppu::{palette::Palette, ppu::Color};
+
+use crate::pacing::FRAME_RATE;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// Clock cycles per frame and clock rate, the frame rate of recorded videos as a fraction
+const FRAME_RATE_SCALE: u32 = 70224;
+const FRAME_RATE_RATE: u32 = 4194304;
+
+/// AVI index flag marking a frame that doesn't depend on other frames
+const AVIIF_KEYFRAME: u32 = 0x10;
+/// AVI header flag telling players the file has an idx1 index
+const AVIF_HASINDEX: u32 = 0x10;
+/// Videos are split before they reach 1 GiB, AVI 1.0 files can't pass 4 GiB and many players
+/// already fail on files over 1-2 GiB
+const MAX_VIDEO_SIZE: u64 = 1 << 30;
+
+/// Convert a frame to 24 bit RGB, scaling it up by an integer factor
+fn frame_to_rgb(frame_buffer: &[Color; WIDTH * HEIGHT], scale: usize, palette: &Palette) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * scale * scale * 3);
+    for row in frame_buffer.chunks_exact(WIDTH) {
+        for _ in 0..scale {
+            for color in row {
+                for _ in 0..scale {
+                    rgb.extend_from_slice(&palette.rgba(*color)[..3]);
+                }
+            }
+        }
+    }
+    rgb
+}
+
+/// A file name that doesn't clash with earlier captures of the same game
+pub fn capture_path(dir: &Path, title: &str, extension: &str) -> PathBuf {
+    let title: String = title
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let title = if title.is_empty() { "rustyboy".to_owned() } else { title };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    dir.join(format!("{}-{}.{}", title, timestamp, extension))
+}
+
+/// Save a frame as a PNG, scaled up by an integer factor
+pub fn save_screenshot(
+    path: &Path,
+    frame_buffer: &[Color; WIDTH * HEIGHT],
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let scale = scale.max(1);
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, (WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer
+        .write_image_data(&frame_to_rgb(frame_buffer, scale, palette))
+        .map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// Writes frames to an uncompressed AVI file
+/// Frames are stored as 24 bit bottom up DIBs, which every player can decode without extra codecs
+/// The emulator doesn't produce audio yet, so the file only has a video stream
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    scale: usize,
+    /// Offset of the 'movi' list type, index offsets are relative to it
+    movi_offset: u64,
+    /// Size of the 'movi' list so far, including the list type
+    movi_size: u32,
+    /// Offset and size of every frame chunk, relative to the 'movi' list type
+    index: Vec<(u32, u32)>,
+    /// Size of a frame chunk's data
+    frame_size: u32,
+    /// The largest the finished file is allowed to be
+    max_size: u64,
+}
+
+impl AviWriter<BufWriter<File>> {
+    pub fn create(path: &Path, scale: usize) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), scale)
+    }
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    /// Start a video, the header is completed by `finish`
+    pub fn new(mut out: W, scale: usize) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let width = (WIDTH * scale) as u32;
+        let height = (HEIGHT * scale) as u32;
+        let frame_size = Self::row_size(width) * height;
+
+        out.write_all(b"RIFF")?;
+        write_u32(&mut out, 0)?; // File size, patched by finish
+        out.write_all(b"AVI ")?;
+
+        out.write_all(b"LIST")?;
+        write_u32(&mut out, 4 + (8 + 56) + (12 + (8 + 56) + (8 + 40)))?;
+        out.write_all(b"hdrl")?;
+
+        out.write_all(b"avih")?;
+        write_u32(&mut out, 56)?;
+        write_u32(&mut out, (1_000_000.0 / FRAME_RATE).round() as u32)?;
+        write_u32(&mut out, (frame_size as f64 * FRAME_RATE).ceil() as u32)?;
+        write_u32(&mut out, 0)?; // Padding granularity
+        write_u32(&mut out, AVIF_HASINDEX)?;
+        write_u32(&mut out, 0)?; // Total frames, patched by finish
+        write_u32(&mut out, 0)?; // Initial frames
+        write_u32(&mut out, 1)?; // Streams
+        write_u32(&mut out, frame_size)?;
+        write_u32(&mut out, width)?;
+        write_u32(&mut out, height)?;
+        out.write_all(&[0; 16])?;
+
+        out.write_all(b"LIST")?;
+        write_u32(&mut out, 4 + (8 + 56) + (8 + 40))?;
+        out.write_all(b"strl")?;
+
+        out.write_all(b"strh")?;
+        write_u32(&mut out, 56)?;
+        out.write_all(b"vids")?;
+        out.write_all(b"DIB ")?;
+        write_u32(&mut out, 0)?; // Flags
+        write_u32(&mut out, 0)?; // Priority and language
+        write_u32(&mut out, 0)?; // Initial frames
+        write_u32(&mut out, FRAME_RATE_SCALE)?;
+        write_u32(&mut out, FRAME_RATE_RATE)?;
+        write_u32(&mut out, 0)?; // Start
+        write_u32(&mut out, 0)?; // Length, patched by finish
+        write_u32(&mut out, frame_size)?;
+        write_u32(&mut out, u32::MAX)?; // Default quality
+        write_u32(&mut out, 0)?; // Sample size, frames vary in size
+        for v in [0, 0, width as u16, height as u16] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+
+        out.write_all(b"strf")?;
+        write_u32(&mut out, 40)?;
+        write_u32(&mut out, 40)?; // BITMAPINFOHEADER size
+        write_u32(&mut out, width)?;
+        write_u32(&mut out, height)?; // Positive height, the rows are stored bottom up
+        out.write_all(&1u16.to_le_bytes())?; // Planes
+        out.write_all(&24u16.to_le_bytes())?; // Bits per pixel
+        write_u32(&mut out, 0)?; // BI_RGB, uncompressed
+        write_u32(&mut out, frame_size)?;
+        out.write_all(&[0; 16])?;
+
+        out.write_all(b"LIST")?;
+        write_u32(&mut out, 0)?; // List size, patched by finish
+        let movi_offset = out.stream_position()?;
+        out.write_all(b"movi")?;
+
+        Ok(AviWriter {
+            out,
+            scale,
+            movi_offset,
+            movi_size: 4,
+            index: Vec::new(),
+            frame_size,
+            max_size: MAX_VIDEO_SIZE,
+        })
+    }
+
+    /// DIB rows are padded to a multiple of 4 bytes
+    fn row_size(width: u32) -> u32 {
+        (width * 3 + 3) & !3
+    }
+
+    /// The number of frames written so far
+    pub fn frames(&self) -> usize {
+        self.index.len()
+    }
+
+    /// The size of the file once it's finished with the frames written so far
+    fn finished_size(&self) -> u64 {
+        self.movi_offset + self.movi_size as u64 + 8 + self.index.len() as u64 * 16
+    }
+
+    /// Would another frame take the file past the size limit
+    pub fn is_full(&self) -> bool {
+        self.finished_size() + 8 + self.frame_size as u64 + 16 > self.max_size
+    }
+
+    /// Add a frame, failing without writing anything once the file is full
+    pub fn write_frame(&mut self, frame_buffer: &[Color; WIDTH * HEIGHT], palette: &Palette) -> io::Result<()> {
+        if self.is_full() {
+            return Err(io::Error::other("the video reached its size limit"));
+        }
+        let width = WIDTH * self.scale;
+        let row_size = Self::row_size(width as u32) as usize;
+        let rgb = frame_to_rgb(frame_buffer, self.scale, palette);
+        let mut data = Vec::with_capacity(row_size * HEIGHT * self.scale);
+        for row in rgb.chunks_exact(width * 3).rev() {
+            for pixel in row.chunks_exact(3) {
+                data.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+            data.resize(data.len() + row_size - width * 3, 0);
+        }
+
+        let movi_size = u32::try_from(data.len())
+            .ok()
+            .and_then(|size| self.movi_size.checked_add(8)?.checked_add(size))
+            .ok_or_else(|| io::Error::other("the video is too large for an AVI file"))?;
+        self.out.write_all(b"00db")?;
+        write_u32(&mut self.out, data.len() as u32)?;
+        self.out.write_all(&data)?;
+        self.index.push((self.movi_size, data.len() as u32));
+        self.movi_size = movi_size;
+        Ok(())
+    }
+
+    /// Write the index and fill in the sizes in the header
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(b"idx1")?;
+        write_u32(&mut self.out, self.index.len() as u32 * 16)?;
+        for (offset, size) in &self.index {
+            self.out.write_all(b"00db")?;
+            write_u32(&mut self.out, AVIIF_KEYFRAME)?;
+            write_u32(&mut self.out, *offset)?;
+            write_u32(&mut self.out, *size)?;
+        }
+        let end = self.finished_size();
+
+        let frames = self.index.len() as u32;
+        self.patch_u32(4, (end - 8) as u32)?;
+        // Total frames in the main header, and the length of the video stream
+        self.patch_u32(12 + 8 + 4 + 8 + 16, frames)?;
+        self.patch_u32(12 + 8 + 4 + (8 + 56) + 12 + 8 + 32, frames)?;
+        self.patch_u32(self.movi_offset - 4, self.movi_size)?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn patch_u32(&mut self, offset: u64, v: u32) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(offset))?;
+        write_u32(&mut self.out, v)
+    }
+}
+
+fn write_u32<W: Write>(out: &mut W, v: u32) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+/// The screenshot and video capture state of the frontend
+pub struct Capture {
+    /// Directory captures are saved to
+    dir: PathBuf,
+    /// Integer factor captures are scaled up by
+    scale: usize,
+    palette: Palette,
+    video: Option<Video>,
+}
+
+/// A video being recorded
+struct Video {
+    path: PathBuf,
+    /// The title of the game, used to name the next file when the video is split
+    title: String,
+    writer: AviWriter<BufWriter<File>>,
+}
+
+impl Capture {
+    pub fn new(dir: PathBuf, scale: usize, palette: Palette) -> Self {
+        Capture {
+            dir,
+            scale,
+            palette,
+            video: None,
+        }
+    }
+
+    pub fn screenshot(&self, title: &str, frame_buffer: &[Color; WIDTH * HEIGHT]) {
+        let path = capture_path(&self.dir, title, "png");
+        match save_screenshot(&path, frame_buffer, self.scale, &self.palette) {
+            Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+            Err(e) => log::error!("Failed to save screenshot to {}: {}", path.display(), e),
+        }
+    }
+
+    /// Start recording a video, or stop the one being recorded
+    pub fn toggle_video(&mut self, title: &str) {
+        if self.video.is_some() {
+            self.stop_video();
+        } else {
+            self.start_video(title);
+        }
+    }
+
+    fn start_video(&mut self, title: &str) {
+        let path = capture_path(&self.dir, title, "avi");
+        match AviWriter::create(&path, self.scale) {
+            Ok(writer) => {
+                log::info!("Recording video to {}", path.display());
+                self.video = Some(Video {
+                    path,
+                    title: title.to_owned(),
+                    writer,
+                });
+            }
+            Err(e) => log::error!("Failed to create video {}: {}", path.display(), e),
+        }
+    }
+
+    /// Add a frame to the video being recorded, called after every emulated frame
+    /// A video that reaches the size limit is finished and the recording carries on in a new file
+    pub fn write_frame(&mut self, frame_buffer: &[Color; WIDTH * HEIGHT]) {
+        if let Some(video) = &self.video {
+            if video.writer.is_full() {
+                log::warn!(
+                    "Video {} reached the {} MiB size limit, continuing in a new file",
+                    video.path.display(),
+                    MAX_VIDEO_SIZE >> 20
+                );
+                let title = video.title.clone();
+                self.stop_video();
+                self.start_video(&title);
+            }
+        }
+        if let Some(video) = &mut self.video {
+            if let Err(e) = video.writer.write_frame(frame_buffer, &self.palette) {
+                log::error!("Failed to write to video {}: {}, stopping the recording", video.path.display(), e);
+                self.stop_video();
+            }
+        }
+    }
+
+    pub fn stop_video(&mut self) {
+        if let Some(video) = self.video.take() {
+            let frames = video.writer.frames();
+            match video.writer.finish() {
+                Ok(_) => log::info!("Saved {} frame video to {}", frames, video.path.display()),
+                Err(e) => log::error!("Failed to finish video {}: {}", video.path.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_avi_writer() {
+        let palette = Palette::default();
+        let mut writer = AviWriter::new(Cursor::new(Vec::new()), 1).unwrap();
+        writer.write_frame(&[Color::White; WIDTH * HEIGHT], &palette).unwrap();
+        writer.write_frame(&[Color::Black; WIDTH * HEIGHT], &palette).unwrap();
+        assert_eq!(writer.frames(), 2);
+        let data = writer.finish().unwrap().into_inner();
+
+        let frame_size = WIDTH * 3 * HEIGHT;
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+
+        // dwTotalFrames in the main header and dwLength in the stream header
+        assert_eq!(read_u32(&data, 48), 2);
+        assert_eq!(read_u32(&data, 140), 2);
+
+        let movi = 12 + 8 + read_u32(&data, 16) as usize;
+        assert_eq!(&data[movi..movi + 4], b"LIST");
+        assert_eq!(&data[movi + 8..movi + 12], b"movi");
+        let movi_size = read_u32(&data, movi + 4) as usize;
+        assert_eq!(movi_size, 4 + 2 * (8 + frame_size));
+
+        let first = movi + 12;
+        assert_eq!(&data[first..first + 4], b"00db");
+        assert_eq!(read_u32(&data, first + 4) as usize, frame_size);
+        let white = palette.rgba(Color::White);
+        assert_eq!(data[first + 8..first + 11], [white[2], white[1], white[0]]);
+
+        let idx1 = movi + 8 + movi_size;
+        assert_eq!(&data[idx1..idx1 + 4], b"idx1");
+        assert_eq!(read_u32(&data, idx1 + 4), 32);
+        assert_eq!(idx1 + 8 + 32, data.len());
+        for (n, entry) in data[idx1 + 8..].chunks_exact(16).enumerate() {
+            assert_eq!(&entry[..4], b"00db");
+            assert_eq!(read_u32(entry, 4), AVIIF_KEYFRAME);
+            assert_eq!(read_u32(entry, 8) as usize, 4 + n * (8 + frame_size));
+            assert_eq!(read_u32(entry, 12) as usize, frame_size);
+        }
+    }
+
+    #[test]
+    fn test_avi_writer_size_limit() {
+        let palette = Palette::default();
+        let frame = [Color::White; WIDTH * HEIGHT];
+        let mut writer = AviWriter::new(Cursor::new(Vec::new()), 1).unwrap();
+        let frame_size = (WIDTH * 3 * HEIGHT) as u64;
+        // Room for the headers, two frames and their index entries
+        writer.max_size = writer.finished_size() + 2 * (8 + frame_size + 16);
+        assert!(!writer.is_full());
+        writer.write_frame(&frame, &palette).unwrap();
+        writer.write_frame(&frame, &palette).unwrap();
+        assert!(writer.is_full());
+        assert!(writer.write_frame(&frame, &palette).is_err());
+        assert_eq!(writer.frames(), 2);
+
+        let max_size = writer.max_size;
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(data.len() as u64, max_size);
+        assert_eq!(read_u32(&data, 48), 2);
+    }
+
+    #[test]
+    fn test_avi_writer_overflow() {
+        let palette = Palette::default();
+        let mut writer = AviWriter::new(Cursor::new(Vec::new()), 1).unwrap();
+        writer.max_size = u64::MAX;
+        writer.movi_size = u32::MAX - 8;
+        assert!(writer.write_frame(&[Color::White; WIDTH * HEIGHT], &palette).is_err());
+        assert_eq!(writer.frames(), 0);
+    }
+}
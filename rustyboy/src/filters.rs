@@ -0,0 +1,328 @@
+use clap::ValueEnum;
+
+use rustyboy_core::ppu::{palette::Palette, ppu::Color};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// Brightness of the darkened scanlines, in eighths
+const SCANLINE_BRIGHTNESS: u32 = 5;
+/// Brightness of the gaps between LCD pixels, in eighths
+const LCD_GRID_BRIGHTNESS: u32 = 6;
+
+/// Upscalers run on the CPU before the frame is handed to `pixels`, which then scales
+/// the result to the window with nearest neighbour integer scaling
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scaler {
+    /// Plain nearest neighbour scaling
+    Nearest,
+    /// Scale2x (EPX), smooths diagonal edges without blurring
+    Scale2x,
+    /// Scale3x, the 3x version of Scale2x
+    Scale3x,
+    /// Darkens every other line like a CRT
+    Scanlines,
+    /// Darkens the gaps between pixels like the DMG's LCD
+    LcdGrid,
+}
+
+impl Scaler {
+    const ALL: [Scaler; 5] = [
+        Scaler::Nearest,
+        Scaler::Scale2x,
+        Scaler::Scale3x,
+        Scaler::Scanlines,
+        Scaler::LcdGrid,
+    ];
+
+    /// The factor the frame is scaled up by
+    pub fn factor(self) -> usize {
+        match self {
+            Scaler::Nearest => 1,
+            Scaler::Scale2x | Scaler::Scanlines => 2,
+            Scaler::Scale3x | Scaler::LcdGrid => 3,
+        }
+    }
+
+    /// The scaler after this one, used to cycle through them at runtime
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|s| *s == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Scale a frame of RGBA pixels, `dst` has to be `factor` times as wide and high
+    fn apply(self, src: &[u32], dst: &mut [u32]) {
+        match self {
+            Scaler::Nearest => dst.copy_from_slice(src),
+            Scaler::Scale2x => scale2x(src, dst),
+            Scaler::Scale3x => scale3x(src, dst),
+            Scaler::Scanlines => {
+                nearest(src, dst, 2);
+                for (y, row) in dst.chunks_exact_mut(WIDTH * 2).enumerate() {
+                    if y % 2 == 1 {
+                        row.iter_mut().for_each(|p| *p = darken(*p, SCANLINE_BRIGHTNESS));
+                    }
+                }
+            }
+            Scaler::LcdGrid => {
+                nearest(src, dst, 3);
+                for (y, row) in dst.chunks_exact_mut(WIDTH * 3).enumerate() {
+                    for (x, p) in row.iter_mut().enumerate() {
+                        if x % 3 == 2 || y % 3 == 2 {
+                            *p = darken(*p, LCD_GRID_BRIGHTNESS);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Scale a pixel's colour channels by a number of eighths, leaving alpha alone
+fn darken(pixel: u32, eighths: u32) -> u32 {
+    let [r, g, b, a] = pixel.to_ne_bytes();
+    let scale = |c: u8| ((c as u32 * eighths) / 8) as u8;
+    u32::from_ne_bytes([scale(r), scale(g), scale(b), a])
+}
+
+/// Average two pixels channel by channel
+fn blend(a: u32, b: u32) -> u32 {
+    let a = a.to_ne_bytes();
+    let b = b.to_ne_bytes();
+    u32::from_ne_bytes([0, 1, 2, 3].map(|i| ((a[i] as u16 + b[i] as u16) / 2) as u8))
+}
+
+fn nearest(src: &[u32], dst: &mut [u32], factor: usize) {
+    let width = WIDTH * factor;
+    for (y, row) in dst.chunks_exact_mut(width).enumerate() {
+        let src_row = &src[(y / factor) * WIDTH..][..WIDTH];
+        for (x, p) in row.iter_mut().enumerate() {
+            *p = src_row[x / factor];
+        }
+    }
+}
+
+/// The pixel at a position, positions outside the frame are clamped to the edge
+fn pixel_at(src: &[u32], x: isize, y: isize) -> u32 {
+    let x = x.clamp(0, WIDTH as isize - 1) as usize;
+    let y = y.clamp(0, HEIGHT as isize - 1) as usize;
+    src[y * WIDTH + x]
+}
+
+fn scale2x(src: &[u32], dst: &mut [u32]) {
+    let width = WIDTH * 2;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let (xi, yi) = (x as isize, y as isize);
+            let p = pixel_at(src, xi, yi);
+            let a = pixel_at(src, xi, yi - 1);
+            let b = pixel_at(src, xi + 1, yi);
+            let c = pixel_at(src, xi - 1, yi);
+            let d = pixel_at(src, xi, yi + 1);
+
+            let i = y * 2 * width + x * 2;
+            dst[i] = if c == a && c != d && a != b { a } else { p };
+            dst[i + 1] = if a == b && a != c && b != d { b } else { p };
+            dst[i + width] = if d == c && d != b && c != a { c } else { p };
+            dst[i + width + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+}
+
+fn scale3x(src: &[u32], dst: &mut [u32]) {
+    let width = WIDTH * 3;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let (xi, yi) = (x as isize, y as isize);
+            // The neighbourhood, laid out as
+            // a b c
+            // d e f
+            // g h i
+            let a = pixel_at(src, xi - 1, yi - 1);
+            let b = pixel_at(src, xi, yi - 1);
+            let c = pixel_at(src, xi + 1, yi - 1);
+            let d = pixel_at(src, xi - 1, yi);
+            let e = pixel_at(src, xi, yi);
+            let f = pixel_at(src, xi + 1, yi);
+            let g = pixel_at(src, xi - 1, yi + 1);
+            let h = pixel_at(src, xi, yi + 1);
+            let i = pixel_at(src, xi + 1, yi + 1);
+
+            let out = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            let top_left = y * 3 * width + x * 3;
+            for (n, pixel) in out.into_iter().enumerate() {
+                dst[top_left + (n / 3) * width + n % 3] = pixel;
+            }
+        }
+    }
+}
+
+/// Turns the frame buffer into the scaled and blended frame shown in the window
+pub struct ScreenFilters {
+    pub scaler: Scaler,
+    /// Mix every frame with the one before it, like the slow DMG LCD does
+    pub frame_blending: bool,
+    /// The frame emulated before the current one
+    previous: Box<[Color; WIDTH * HEIGHT]>,
+    /// The unscaled RGBA frame
+    frame: Vec<u32>,
+}
+
+impl ScreenFilters {
+    pub fn new(scaler: Scaler, frame_blending: bool) -> Self {
+        ScreenFilters {
+            scaler,
+            frame_blending,
+            previous: Box::new([Color::White; WIDTH * HEIGHT]),
+            frame: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    /// The size of the scaled frame
+    pub fn size(&self) -> (u32, u32) {
+        let factor = self.scaler.factor() as u32;
+        (WIDTH as u32 * factor, HEIGHT as u32 * factor)
+    }
+
+    /// Keep the current frame to blend the next one with, called before every emulated frame.
+    /// This happens even while blending is off so turning it on doesn't blend with a stale frame
+    pub fn remember_frame(&mut self, frame_buffer: &[Color; WIDTH * HEIGHT]) {
+        self.previous.copy_from_slice(frame_buffer);
+    }
+
+    /// Draw a frame into an RGBA buffer of the size returned by `size`
+    pub fn draw(&mut self, frame_buffer: &[Color; WIDTH * HEIGHT], palette: &Palette, out: &mut [u8]) {
+        for (i, pixel) in self.frame.iter_mut().enumerate() {
+            let current = u32::from_ne_bytes(palette.rgba(frame_buffer[i]));
+            *pixel = if self.frame_blending {
+                blend(current, u32::from_ne_bytes(palette.rgba(self.previous[i])))
+            } else {
+                current
+            };
+        }
+
+        let mut scaled = vec![0; out.len() / 4];
+        self.scaler.apply(&self.frame, &mut scaled);
+        for (rgba, pixel) in out.chunks_exact_mut(4).zip(scaled) {
+            rgba.copy_from_slice(&pixel.to_ne_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: u32 = u32::from_ne_bytes([0xFF, 0xFF, 0xFF, 0xFF]);
+    const BLACK: u32 = u32::from_ne_bytes([0x00, 0x00, 0x00, 0xFF]);
+    const GRAY: u32 = u32::from_ne_bytes([0x80, 0x80, 0x80, 0xFF]);
+
+    /// A white frame with black pixels at (1, 0) and (0, 1), a diagonal edge the upscalers
+    /// should smooth into the white pixel at (1, 1)
+    fn diagonal_frame() -> Vec<u32> {
+        let mut src = vec![WHITE; WIDTH * HEIGHT];
+        src[1] = BLACK;
+        src[WIDTH] = BLACK;
+        src
+    }
+
+    fn apply(scaler: Scaler, src: &[u32]) -> Vec<u32> {
+        let factor = scaler.factor();
+        let mut dst = vec![0; WIDTH * HEIGHT * factor * factor];
+        scaler.apply(src, &mut dst);
+        dst
+    }
+
+    #[test]
+    fn test_blend() {
+        let a = u32::from_ne_bytes([0x10, 0x20, 0x30, 0xFF]);
+        let b = u32::from_ne_bytes([0x30, 0x40, 0x51, 0xFF]);
+        assert_eq!(blend(a, b).to_ne_bytes(), [0x20, 0x30, 0x40, 0xFF]);
+        assert_eq!(blend(WHITE, BLACK).to_ne_bytes(), [0x7F, 0x7F, 0x7F, 0xFF]);
+    }
+
+    #[test]
+    fn test_scale2x() {
+        let dst = apply(Scaler::Scale2x, &diagonal_frame());
+        let width = WIDTH * 2;
+        // The black pixels stay 2x2 blocks
+        assert_eq!(dst[2..4], [BLACK, BLACK]);
+        assert_eq!(dst[width + 2..width + 4], [BLACK, BLACK]);
+        // Only the corner of the white pixel facing the edge is filled in
+        assert_eq!(dst[2 * width + 2..2 * width + 4], [BLACK, WHITE]);
+        assert_eq!(dst[3 * width + 2..3 * width + 4], [WHITE, WHITE]);
+        assert!(dst[4 * width..].iter().all(|p| *p == WHITE));
+    }
+
+    #[test]
+    fn test_scale3x() {
+        let dst = apply(Scaler::Scale3x, &diagonal_frame());
+        let width = WIDTH * 3;
+        assert_eq!(dst[3..6], [BLACK; 3]);
+        assert_eq!(dst[3 * width + 3..3 * width + 6], [BLACK, WHITE, WHITE]);
+        assert_eq!(dst[4 * width + 3..4 * width + 6], [WHITE; 3]);
+        assert_eq!(dst[5 * width + 3..5 * width + 6], [WHITE; 3]);
+        assert!(dst[6 * width..].iter().all(|p| *p == WHITE));
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let dst = apply(Scaler::Scanlines, &vec![GRAY; WIDTH * HEIGHT]);
+        let width = WIDTH * 2;
+        assert!(dst[..width].iter().all(|p| *p == GRAY));
+        assert!(dst[width..2 * width]
+            .iter()
+            .all(|p| p.to_ne_bytes() == [0x50, 0x50, 0x50, 0xFF]));
+        assert!(dst[2 * width..3 * width].iter().all(|p| *p == GRAY));
+    }
+
+    #[test]
+    fn test_lcd_grid() {
+        let dst = apply(Scaler::LcdGrid, &vec![GRAY; WIDTH * HEIGHT]);
+        let width = WIDTH * 3;
+        let gap = u32::from_ne_bytes([0x60, 0x60, 0x60, 0xFF]);
+        assert_eq!(dst[..4], [GRAY, GRAY, gap, GRAY]);
+        assert_eq!(dst[width..width + 4], [GRAY, GRAY, gap, GRAY]);
+        assert_eq!(dst[2 * width..2 * width + 4], [gap; 4]);
+        assert_eq!(dst[3 * width..3 * width + 4], [GRAY, GRAY, gap, GRAY]);
+    }
+
+    #[test]
+    fn test_frame_blending() {
+        let palette = Palette::default();
+        let white = Box::new([Color::White; WIDTH * HEIGHT]);
+        let black = Box::new([Color::Black; WIDTH * HEIGHT]);
+        let mut out = vec![0; WIDTH * HEIGHT * 4];
+        let mut filters = ScreenFilters::new(Scaler::Nearest, true);
+
+        filters.remember_frame(&white);
+        filters.draw(&black, &palette, &mut out);
+        let expected = blend(
+            u32::from_ne_bytes(palette.rgba(Color::White)),
+            u32::from_ne_bytes(palette.rgba(Color::Black)),
+        );
+        assert_eq!(out[..4], expected.to_ne_bytes());
+
+        // Frames are remembered while blending is off so turning it on doesn't blend with an old one
+        filters.frame_blending = false;
+        filters.remember_frame(&black);
+        filters.frame_blending = true;
+        filters.draw(&black, &palette, &mut out);
+        assert_eq!(out[..4], palette.rgba(Color::Black));
+    }
+}
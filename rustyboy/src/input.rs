@@ -0,0 +1,461 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use gilrs::{Axis, Button, GamepadId};
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use rustyboy_core::io::joypad::{Joypad, Key};
+
+/// The config file loaded when no input config is passed on the command line
+pub const DEFAULT_CONFIG_PATH: &str = "input.toml";
+
+const KEYS: [Key; 8] = [
+    Key::A,
+    Key::B,
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Down,
+    Key::Start,
+    Key::Select,
+];
+
+/// Something a keyboard key or gamepad button can be bound to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+    /// Presses and releases A repeatedly while held
+    AutofireA,
+    /// Presses and releases B repeatedly while held
+    AutofireB,
+    FastForward,
+    Turbo,
+    SlowMotion,
+    /// Save the screen as a PNG
+    Screenshot,
+    /// Start or stop recording a video
+    ToggleVideo,
+    /// Switch to the next scaler
+    CycleScaler,
+    ToggleFrameBlending,
+    /// Turn all cheats on or off
+    ToggleCheats,
+}
+
+impl Action {
+    /// The joypad key pressed while this action is held
+    fn key(self) -> Option<Key> {
+        match self {
+            Action::Up => Some(Key::Up),
+            Action::Down => Some(Key::Down),
+            Action::Left => Some(Key::Left),
+            Action::Right => Some(Key::Right),
+            Action::A => Some(Key::A),
+            Action::B => Some(Key::B),
+            Action::Start => Some(Key::Start),
+            Action::Select => Some(Key::Select),
+            _ => None,
+        }
+    }
+
+    /// The joypad key toggled every autofire period while this action is held
+    fn autofire_key(self) -> Option<Key> {
+        match self {
+            Action::AutofireA => Some(Key::A),
+            Action::AutofireB => Some(Key::B),
+            _ => None,
+        }
+    }
+}
+
+/// The input config file, every field is optional and falls back to the defaults
+/// A binding table only replaces the default bindings of the actions it lists
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    /// Keyboard keys bound to each action, using winit's key names
+    pub keyboard: HashMap<Action, Vec<VirtualKeyCode>>,
+    /// Gamepad buttons bound to each action, using gilrs' button names
+    pub gamepad: HashMap<Action, Vec<Button>>,
+    /// Number of frames an autofire key stays pressed, and then released
+    pub autofire_period: u32,
+    /// Let left and right (or up and down) be pressed at the same time
+    /// When disabled the most recently pressed direction wins, as on a real D-Pad
+    pub allow_opposing_directions: bool,
+    /// How far the left stick has to be pushed to press a direction
+    pub stick_deadzone: f32,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            keyboard: default_keyboard_bindings(),
+            gamepad: default_gamepad_bindings(),
+            autofire_period: 2,
+            allow_opposing_directions: false,
+            stick_deadzone: 0.5,
+        }
+    }
+}
+
+fn default_keyboard_bindings() -> HashMap<Action, Vec<VirtualKeyCode>> {
+    HashMap::from([
+        (Action::Up, vec![VirtualKeyCode::Up]),
+        (Action::Down, vec![VirtualKeyCode::Down]),
+        (Action::Left, vec![VirtualKeyCode::Left]),
+        (Action::Right, vec![VirtualKeyCode::Right]),
+        (Action::A, vec![VirtualKeyCode::Z]),
+        (Action::B, vec![VirtualKeyCode::X]),
+        (Action::Start, vec![VirtualKeyCode::Return]),
+        (Action::Select, vec![VirtualKeyCode::Back]),
+        (Action::AutofireA, vec![VirtualKeyCode::A]),
+        (Action::AutofireB, vec![VirtualKeyCode::D]),
+        (Action::FastForward, vec![VirtualKeyCode::Tab]),
+        (Action::Turbo, vec![VirtualKeyCode::T]),
+        (Action::SlowMotion, vec![VirtualKeyCode::S]),
+        (Action::Screenshot, vec![VirtualKeyCode::F12]),
+        (Action::ToggleVideo, vec![VirtualKeyCode::F10]),
+        (Action::CycleScaler, vec![VirtualKeyCode::F8]),
+        (Action::ToggleFrameBlending, vec![VirtualKeyCode::F9]),
+        (Action::ToggleCheats, vec![VirtualKeyCode::F7]),
+    ])
+}
+
+fn default_gamepad_bindings() -> HashMap<Action, Vec<Button>> {
+    HashMap::from([
+        (Action::Up, vec![Button::DPadUp]),
+        (Action::Down, vec![Button::DPadDown]),
+        (Action::Left, vec![Button::DPadLeft]),
+        (Action::Right, vec![Button::DPadRight]),
+        (Action::A, vec![Button::East]),
+        (Action::B, vec![Button::South]),
+        (Action::Start, vec![Button::Start]),
+        (Action::Select, vec![Button::Select]),
+        (Action::AutofireA, vec![Button::North]),
+        (Action::AutofireB, vec![Button::West]),
+        (Action::FastForward, vec![Button::RightTrigger]),
+    ])
+}
+
+impl InputConfig {
+    /// Load the config from a TOML file, the bindings it lists replace the default ones
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("Invalid input config {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        let parsed: InputConfig = toml::from_str(text)?;
+        let mut config = InputConfig::default();
+        merge_bindings(&mut config.keyboard, parsed.keyboard);
+        merge_bindings(&mut config.gamepad, parsed.gamepad);
+        config.autofire_period = parsed.autofire_period.max(1);
+        config.allow_opposing_directions = parsed.allow_opposing_directions;
+        config.stick_deadzone = parsed.stick_deadzone;
+        Ok(config)
+    }
+}
+
+/// Replace the default bindings of the actions listed in the config
+/// Inputs bound in the config are also removed from the default bindings of other actions
+fn merge_bindings<T: PartialEq>(bindings: &mut HashMap<Action, Vec<T>>, overrides: HashMap<Action, Vec<T>>) {
+    for inputs in bindings.values_mut() {
+        inputs.retain(|input| !overrides.values().any(|o| o.contains(input)));
+    }
+    bindings.extend(overrides);
+}
+
+/// A physical input that can be held down
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Source {
+    Keyboard(VirtualKeyCode),
+    Gamepad(GamepadId, Button),
+    /// The left stick pushed in the direction of a D-Pad button
+    Stick(GamepadId, Button),
+}
+
+/// Turns keyboard and gamepad events into joypad key presses
+pub struct InputMapper {
+    keyboard: HashMap<VirtualKeyCode, Action>,
+    gamepad: HashMap<Button, Action>,
+    autofire_period: u32,
+    allow_opposing_directions: bool,
+    stick_deadzone: f32,
+    /// The sources currently held down, with the order in which they were pressed
+    held: HashMap<Source, u64>,
+    /// Incremented on every press, used to find the most recently pressed direction
+    press_count: u64,
+    /// The joypad keys as they were last sent to the joypad
+    pressed: HashSet<Key>,
+    /// Frames emulated since the mapper was created, drives autofire
+    frame: u64,
+}
+
+impl InputMapper {
+    pub fn new(config: InputConfig) -> Self {
+        let mut keyboard = HashMap::new();
+        for (action, keys) in config.keyboard {
+            for key in keys {
+                if let Some(other) = keyboard.insert(key, action) {
+                    log::warn!("Key {:?} is bound to both {:?} and {:?}", key, other, action);
+                }
+            }
+        }
+        let mut gamepad = HashMap::new();
+        for (action, buttons) in config.gamepad {
+            for button in buttons {
+                if let Some(other) = gamepad.insert(button, action) {
+                    log::warn!("Button {:?} is bound to both {:?} and {:?}", button, other, action);
+                }
+            }
+        }
+
+        InputMapper {
+            keyboard,
+            gamepad,
+            autofire_period: config.autofire_period,
+            allow_opposing_directions: config.allow_opposing_directions,
+            stick_deadzone: config.stick_deadzone,
+            held: HashMap::new(),
+            press_count: 0,
+            pressed: HashSet::new(),
+            frame: 0,
+        }
+    }
+
+    fn action(&self, source: Source) -> Option<Action> {
+        match source {
+            Source::Keyboard(key) => self.keyboard.get(&key).copied(),
+            Source::Gamepad(_, button) | Source::Stick(_, button) => self.gamepad.get(&button).copied(),
+        }
+    }
+
+    /// Update the state of a source, returns the bound action if the source changed state
+    /// Repeated presses, like the ones sent by keyboard auto repeat, are ignored
+    pub fn set_held(&mut self, source: Source, held: bool) -> Option<Action> {
+        let changed = if held {
+            if self.held.contains_key(&source) {
+                false
+            } else {
+                self.press_count += 1;
+                self.held.insert(source, self.press_count);
+                true
+            }
+        } else {
+            self.held.remove(&source).is_some()
+        };
+
+        if changed {
+            self.action(source)
+        } else {
+            None
+        }
+    }
+
+    /// Update the direction pressed by a stick axis
+    pub fn set_axis(&mut self, id: GamepadId, axis: Axis, value: f32) {
+        let (negative, positive) = match axis {
+            Axis::LeftStickX => (Button::DPadLeft, Button::DPadRight),
+            // The Y axis points up
+            Axis::LeftStickY => (Button::DPadDown, Button::DPadUp),
+            _ => return,
+        };
+        self.set_held(Source::Stick(id, negative), value < -self.stick_deadzone);
+        self.set_held(Source::Stick(id, positive), value > self.stick_deadzone);
+    }
+
+    /// Release everything held on a gamepad, used when it disconnects
+    pub fn release_gamepad(&mut self, id: GamepadId) {
+        self.held.retain(|source, _| match source {
+            Source::Gamepad(gamepad, _) | Source::Stick(gamepad, _) => *gamepad != id,
+            Source::Keyboard(_) => true,
+        });
+    }
+
+    /// Is any source bound to the action held down
+    pub fn is_held(&self, action: Action) -> bool {
+        self.last_pressed(action).is_some()
+    }
+
+    /// When the most recent source still holding the action was pressed
+    fn last_pressed(&self, action: Action) -> Option<u64> {
+        self.held
+            .iter()
+            .filter(|(source, _)| self.action(**source) == Some(action))
+            .map(|(_, order)| *order)
+            .max()
+    }
+
+    /// The joypad keys that should be pressed during the next frame
+    fn keys(&self) -> HashSet<Key> {
+        let autofire_on = (self.frame / self.autofire_period as u64).is_multiple_of(2);
+        let mut keys = HashSet::new();
+        for action in self.held.keys().filter_map(|source| self.action(*source)) {
+            if let Some(key) = action.key() {
+                keys.insert(key);
+            }
+            if let Some(key) = action.autofire_key() {
+                if autofire_on {
+                    keys.insert(key);
+                }
+            }
+        }
+
+        if !self.allow_opposing_directions {
+            self.filter_opposing(&mut keys, Action::Left, Action::Right);
+            self.filter_opposing(&mut keys, Action::Up, Action::Down);
+        }
+        keys
+    }
+
+    /// Only keep the most recently pressed of two opposing directions
+    fn filter_opposing(&self, keys: &mut HashSet<Key>, first: Action, second: Action) {
+        if let (Some(first_order), Some(second_order)) = (self.last_pressed(first), self.last_pressed(second)) {
+            let older = if first_order < second_order { first } else { second };
+            keys.remove(&older.key().unwrap());
+        }
+    }
+
+    /// Send the held keys to the joypad, called once before every emulated frame
+    pub fn update(&mut self, joypad: &mut Joypad) {
+        let keys = self.keys();
+        for key in KEYS {
+            match (self.pressed.contains(&key), keys.contains(&key)) {
+                (false, true) => joypad.push_key(key),
+                (true, false) => joypad.release_key(key),
+                _ => {}
+            }
+        }
+        self.pressed = keys;
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_mapper(text: &str) -> InputMapper {
+        InputMapper::new(InputConfig::parse(text).unwrap())
+    }
+
+    fn press(mapper: &mut InputMapper, key: VirtualKeyCode) {
+        mapper.set_held(Source::Keyboard(key), true);
+    }
+
+    fn release(mapper: &mut InputMapper, key: VirtualKeyCode) {
+        mapper.set_held(Source::Keyboard(key), false);
+    }
+
+    /// The keys pressed on the joypad after the next update, as a `Joypad::pressed_keys` mask
+    fn update(mapper: &mut InputMapper, joypad: &mut Joypad) -> u8 {
+        mapper.update(joypad);
+        joypad.pressed_keys()
+    }
+
+    #[test]
+    fn test_merge_bindings() {
+        let mut bindings = HashMap::from([(Action::A, vec![1, 2]), (Action::B, vec![3]), (Action::Start, vec![4])]);
+        merge_bindings(&mut bindings, HashMap::from([(Action::B, vec![2, 5])]));
+        // B's bindings are replaced and 2 is taken away from A, Start is left alone
+        assert_eq!(bindings[&Action::A], [1]);
+        assert_eq!(bindings[&Action::B], [2, 5]);
+        assert_eq!(bindings[&Action::Start], [4]);
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = InputConfig::parse(
+            r#"
+            autofire_period = 0
+            allow_opposing_directions = true
+
+            [keyboard]
+            a = ["X", "Space"]
+
+            [gamepad]
+            fast_forward = ["LeftTrigger"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keyboard[&Action::A], [VirtualKeyCode::X, VirtualKeyCode::Space]);
+        // X moved from B to A
+        assert!(config.keyboard[&Action::B].is_empty());
+        assert_eq!(config.keyboard[&Action::Start], [VirtualKeyCode::Return]);
+        assert_eq!(config.gamepad[&Action::FastForward], [Button::LeftTrigger]);
+        assert_eq!(config.gamepad[&Action::A], [Button::East]);
+        // A period of 0 would divide by zero
+        assert_eq!(config.autofire_period, 1);
+        assert!(config.allow_opposing_directions);
+        assert_eq!(config.stick_deadzone, 0.5);
+
+        let defaults = InputConfig::parse("").unwrap();
+        assert_eq!(defaults.keyboard, default_keyboard_bindings());
+        assert_eq!(defaults.autofire_period, 2);
+        assert!(!defaults.allow_opposing_directions);
+
+        assert!(InputConfig::parse("autofire = 3").is_err());
+        assert!(InputConfig::parse("[keyboard]\njump = [\"Space\"]").is_err());
+        assert!(InputConfig::parse("[keyboard]\na = [\"NotAKey\"]").is_err());
+    }
+
+    #[test]
+    fn test_opposing_directions() {
+        let mut mapper = new_mapper("");
+        let mut joypad = Joypad::new();
+        press(&mut mapper, VirtualKeyCode::Left);
+        assert_eq!(update(&mut mapper, &mut joypad), 0x20);
+        // The most recently pressed direction wins
+        press(&mut mapper, VirtualKeyCode::Right);
+        assert_eq!(update(&mut mapper, &mut joypad), 0x10);
+        release(&mut mapper, VirtualKeyCode::Right);
+        assert_eq!(update(&mut mapper, &mut joypad), 0x20);
+        press(&mut mapper, VirtualKeyCode::Up);
+        press(&mut mapper, VirtualKeyCode::Down);
+        assert_eq!(update(&mut mapper, &mut joypad), 0xA0);
+
+        let mut both = new_mapper("allow_opposing_directions = true");
+        let mut joypad = Joypad::new();
+        press(&mut both, VirtualKeyCode::Left);
+        press(&mut both, VirtualKeyCode::Right);
+        assert_eq!(update(&mut both, &mut joypad), 0x30);
+    }
+
+    #[test]
+    fn test_autofire() {
+        let mut mapper = new_mapper("autofire_period = 2");
+        let mut joypad = Joypad::new();
+        press(&mut mapper, VirtualKeyCode::A);
+        let frames: Vec<u8> = (0..6).map(|_| update(&mut mapper, &mut joypad)).collect();
+        assert_eq!(frames, [0x01, 0x01, 0x00, 0x00, 0x01, 0x01]);
+
+        // Holding A itself keeps it pressed through the autofire gaps
+        press(&mut mapper, VirtualKeyCode::Z);
+        assert!((0..4).all(|_| update(&mut mapper, &mut joypad) == 0x01));
+        release(&mut mapper, VirtualKeyCode::Z);
+        release(&mut mapper, VirtualKeyCode::A);
+        assert_eq!(update(&mut mapper, &mut joypad), 0x00);
+    }
+
+    #[test]
+    fn test_set_held() {
+        let mut mapper = new_mapper("");
+        assert_eq!(mapper.set_held(Source::Keyboard(VirtualKeyCode::Tab), true), Some(Action::FastForward));
+        // Auto repeat doesn't trigger the action again
+        assert_eq!(mapper.set_held(Source::Keyboard(VirtualKeyCode::Tab), true), None);
+        assert!(mapper.is_held(Action::FastForward));
+        assert_eq!(mapper.set_held(Source::Keyboard(VirtualKeyCode::Tab), false), Some(Action::FastForward));
+        assert!(!mapper.is_held(Action::FastForward));
+        assert_eq!(mapper.set_held(Source::Keyboard(VirtualKeyCode::Q), true), None);
+    }
+}
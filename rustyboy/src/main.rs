@@ -1,14 +1,46 @@
+mod capture;
+mod filters;
+mod input;
+mod overlay;
+mod pacing;
+mod repl;
+mod script;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use clap::Parser;
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
 use env_logger::Env;
+use gilrs::EventType;
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
-    event::{Event, WindowEvent, VirtualKeyCode, ElementState},
+    event::{Event, WindowEvent, ElementState, VirtualKeyCode},
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
-use rustyboy_core::{gameboy::Gameboy, io::joypad::Key, ppu::ppu::Color};
+use rustyboy_core::{
+    cheats::Cheats,
+    gameboy::Gameboy,
+    model::Model,
+    movie::{Movie, MoviePlayer},
+    ppu::{
+        oam::{self, OAM_ENTRIES},
+        palette::Palette,
+        ppu::{Color, Ppu},
+        tilemap::{TileData, TileMap, TileMapView, TILEMAP_SIZE},
+    },
+};
+
+use crate::capture::Capture;
+use crate::filters::{Scaler, ScreenFilters};
+use crate::input::{Action, InputConfig, InputMapper, Source};
+use crate::overlay::Overlay;
+use crate::pacing::{parse_speed, FramePacer, SpeedControl};
+use crate::repl::Repl;
+use crate::script::Script;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -19,22 +51,190 @@ struct Args {
     tiles: bool,
     #[arg(short, long)]
     objects: bool,
+    /// Show the background and window tile maps
+    #[arg(long)]
+    tilemap: bool,
+    /// Hardware model to emulate (auto, dmg0, dmg, mgb, sgb, cgb, agb)
+    // Written out in full so clap doesn't treat the argument as optional, auto parses to None
+    #[arg(short, long, default_value = "auto", value_parser = parse_model)]
+    model: std::option::Option<Model>,
+    /// Don't open an audio device, frames are paced with the system clock instead
+    #[arg(long)]
+    no_audio: bool,
+    /// Emulation speed multiplier
+    #[arg(short, long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+    /// Emulation speed multiplier while fast forwarding
+    #[arg(long, default_value_t = 4.0, value_parser = parse_speed)]
+    fast_forward_speed: f64,
+    /// Key and gamepad bindings file, defaults to input.toml in the working directory if it exists
+    #[arg(short, long)]
+    input_config: Option<String>,
+    /// Record the inputs of every frame to a movie file, saved when the emulator is closed
+    #[arg(long, conflicts_with = "play")]
+    record: Option<String>,
+    /// Play back a movie file recorded with --record, control returns to the player once it ends
+    #[arg(long)]
+    play: Option<String>,
+    /// Colours of the four shades, a preset (gray, dmg, pocket, light) or four hex colours from light to dark
+    #[arg(short, long, default_value = "gray")]
+    palette: Palette,
+    /// Scaler applied to the screen, can be cycled with F8
+    #[arg(long, value_enum, default_value_t = Scaler::Nearest)]
+    scaler: Scaler,
+    /// Mix every frame with the previous one to emulate the DMG's slow LCD, can be toggled with F9
+    #[arg(long)]
+    frame_blending: bool,
+    /// Directory screenshots and videos are saved to
+    #[arg(long, default_value = ".")]
+    capture_dir: PathBuf,
+    /// Integer factor screenshots and videos are scaled up by
+    #[arg(long, default_value_t = 1)]
+    capture_scale: usize,
+    /// Cheat file with Game Genie and GameShark codes, defaults to the ROM's path with a .cht
+    /// extension if it exists
+    #[arg(long)]
+    cheats: Option<String>,
+    /// Read memory viewer and editor commands from stdin while the game runs
+    #[arg(long)]
+    repl: bool,
+    /// Lua script run alongside the game, with hooks on frames and memory accesses and an overlay
+    /// to draw on
+    #[arg(long)]
+    script: Option<String>,
+}
+
+/// Parse the model argument, auto picks the model from the cartridge header
+fn parse_model(s: &str) -> Result<Option<Model>, String> {
+    match s {
+        "auto" => Ok(None),
+        _ => s.parse().map(Some),
+    }
+}
+
+/// Which tile map the tile map window shows
+enum TileMapMode {
+    /// The map LCDC selects for the background
+    Background,
+    /// The map LCDC selects for the window
+    Window,
+    /// A map and addressing picked by the user
+    Manual(TileMapView),
 }
 
-fn generate_pixels(frame: &mut [u8], &framebuffer: &[Color; 160 * 144]) {
-    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-        let color = framebuffer[i];
-        let mut rgb = match color {
-            Color::White => [255, 255, 255, 255],
-            Color::LightGray => [192, 192, 192, 255],
-            Color::DarkGray => [96, 96, 96, 255],
-            Color::Black => [0, 0, 0, 255],
+impl TileMapMode {
+    fn view(&self, gb: &Gameboy) -> TileMapView {
+        match self {
+            TileMapMode::Background => TileMapView::background(&gb.mmu.ppu),
+            TileMapMode::Window => TileMapView::window(&gb.mmu.ppu),
+            TileMapMode::Manual(view) => *view,
+        }
+    }
+
+    /// Handle a key pressed while the tile map window is focused
+    fn handle_key(&mut self, key: VirtualKeyCode, gb: &Gameboy) {
+        let mut view = self.view(gb);
+        *self = match key {
+            VirtualKeyCode::B => TileMapMode::Background,
+            VirtualKeyCode::W => TileMapMode::Window,
+            VirtualKeyCode::M => {
+                view.map = match view.map {
+                    TileMap::Low => TileMap::High,
+                    TileMap::High => TileMap::Low,
+                };
+                TileMapMode::Manual(view)
+            }
+            VirtualKeyCode::D => {
+                view.data = match view.data {
+                    TileData::Unsigned => TileData::Signed,
+                    TileData::Signed => TileData::Unsigned,
+                };
+                TileMapMode::Manual(view)
+            }
+            VirtualKeyCode::V => {
+                view.show_viewport = !view.show_viewport;
+                TileMapMode::Manual(view)
+            }
+            _ => return,
         };
-        pixel.copy_from_slice(&mut rgb);
+        log::info!("Tile map view: {:?}", self.view(gb));
+    }
+}
+
+/// Load the input config, an explicitly passed config has to exist
+fn load_input_config(path: Option<&str>) -> InputConfig {
+    let result = match path {
+        Some(path) => InputConfig::load(Path::new(path)),
+        None if Path::new(input::DEFAULT_CONFIG_PATH).exists() => {
+            InputConfig::load(Path::new(input::DEFAULT_CONFIG_PATH))
+        }
+        None => Ok(InputConfig::default()),
+    };
+    match result {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn generate_tiles(frame: &mut [u8], vram: &[u8]) {
+/// Load the cheat file, an explicitly passed file has to exist
+fn load_cheats(path: Option<&str>, rom: &str) -> Cheats {
+    let default_path = Path::new(rom).with_extension("cht");
+    let result = match path {
+        Some(path) => Cheats::load(path),
+        None if default_path.exists() => Cheats::load(&default_path.to_string_lossy()),
+        None => Ok(Cheats::new()),
+    };
+    match result {
+        Ok(cheats) => {
+            if !cheats.cheats.is_empty() {
+                log::info!("Loaded {} cheats", cheats.cheats.len());
+            }
+            cheats
+        }
+        Err(e) => {
+            eprintln!("Failed to load cheats: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Apply the frontend hotkeys when the state of an input bound to them changes
+fn handle_hotkey(
+    action: Action,
+    pressed: bool,
+    input: &InputMapper,
+    speed: &mut SpeedControl,
+    capture: &mut Capture,
+    filters: &mut ScreenFilters,
+    gb: &mut Gameboy,
+) {
+    match action {
+        Action::FastForward => speed.fast_forward_held = input.is_held(Action::FastForward),
+        Action::Turbo if pressed => speed.turbo = !speed.turbo,
+        Action::SlowMotion if pressed => speed.slow_motion = !speed.slow_motion,
+        Action::Screenshot if pressed => capture.screenshot(&gb.mmu.cart_title, &gb.mmu.ppu.frame_buffer),
+        Action::ToggleVideo if pressed => capture.toggle_video(&gb.mmu.cart_title),
+        Action::CycleScaler if pressed => {
+            filters.scaler = filters.scaler.next();
+            log::info!("Scaler: {:?}", filters.scaler);
+        }
+        Action::ToggleFrameBlending if pressed => {
+            filters.frame_blending = !filters.frame_blending;
+            log::info!("Frame blending {}", if filters.frame_blending { "on" } else { "off" });
+        }
+        Action::ToggleCheats if pressed => {
+            let cheats = &mut gb.mmu.cheats;
+            cheats.enabled = !cheats.enabled;
+            log::info!("Cheats {}", if cheats.enabled { "on" } else { "off" });
+        }
+        _ => (),
+    }
+}
+
+fn generate_tiles(frame: &mut [u8], vram: &[u8], palette: &Palette) {
     for i in 0..384 {
         let tile = &vram[i * 16..(i + 1) * 16];
         let tile_x = (i % 16) * 8;
@@ -50,52 +250,121 @@ fn generate_tiles(frame: &mut [u8], vram: &[u8]) {
                     (1, 1) => Color::Black,
                     _ => unreachable!(),
                 };
-                let mut rgb = match color {
-                    Color::White => [255, 255, 255, 255],
-                    Color::LightGray => [192, 192, 192, 255],
-                    Color::DarkGray => [96, 96, 96, 255],
-                    Color::Black => [0, 0, 0, 255],
-                };
+                let rgba = palette.rgba(color);
                 let pixel_x = tile_x + k;
                 let pixel_y = tile_y + j;
                 let pixel_index = (pixel_y * 16 * 8 + pixel_x) * 4;
-                frame[pixel_index..pixel_index + 4].copy_from_slice(&mut rgb);
+                frame[pixel_index..pixel_index + 4].copy_from_slice(&rgba);
             }
         }
     }
 }
 
-fn generate_objects(frame: &mut [u8], oam: &[u8], vram: &[u8]) {
-    for i in 0..40 {
-        let object = &oam[i * 4..(i + 1) * 4];
-        let tile_addr = object[2];
-        let tile = &vram[tile_addr as usize * 16..(tile_addr as usize + 1) * 16];
-        for j in 0..8 {
-            let lo = tile[j * 2];
-            let hi = tile[j * 2 + 1];
-            for k in 0..8 {
-                let color = match ((hi >> (7 - k)) & 1, (lo >> (7 - k)) & 1) {
-                    (0, 0) => Color::White,
-                    (0, 1) => Color::LightGray,
-                    (1, 0) => Color::DarkGray,
-                    (1, 1) => Color::Black,
-                    _ => unreachable!(),
-                };
-                let mut rgb = match color {
-                    Color::White => [255, 255, 255, 255],
-                    Color::LightGray => [192, 192, 192, 255],
-                    Color::DarkGray => [96, 96, 96, 255],
-                    Color::Black => [0, 0, 0, 255],
+/// Size of an object's cell in the objects window, an 8x16 object with a 1 pixel border
+const OBJECT_CELL_WIDTH: usize = 10;
+const OBJECT_CELL_HEIGHT: usize = 18;
+/// Objects per row in the objects window
+const OBJECT_COLUMNS: usize = 8;
+const OBJECTS_WIDTH: usize = OBJECT_CELL_WIDTH * OBJECT_COLUMNS;
+const OBJECTS_HEIGHT: usize = OBJECT_CELL_HEIGHT * (OAM_ENTRIES / OBJECT_COLUMNS);
+
+/// Border of objects dropped on some line by the 10 objects per line limit
+const DROPPED_BORDER: [u8; 4] = [255, 0, 0, 255];
+const OBJECT_BORDER: [u8; 4] = [64, 64, 64, 255];
+const OBJECT_BACKGROUND: [u8; 4] = [0, 128, 128, 255];
+
+fn generate_objects(frame: &mut [u8], ppu: &Ppu, palette: &Palette) {
+    let dropped: Vec<usize> = oam::dropped_objects(ppu)
+        .into_iter()
+        .flat_map(|(_, indices)| indices)
+        .collect();
+    let height = ppu.object_height() as usize;
+    for i in 0..OAM_ENTRIES {
+        let cell_x = i % OBJECT_COLUMNS * OBJECT_CELL_WIDTH;
+        let cell_y = i / OBJECT_COLUMNS * OBJECT_CELL_HEIGHT;
+        let border = if dropped.contains(&i) { DROPPED_BORDER } else { OBJECT_BORDER };
+        let image = oam::render_object(ppu, i, palette).unwrap();
+        for y in 0..OBJECT_CELL_HEIGHT {
+            for x in 0..OBJECT_CELL_WIDTH {
+                let rgba = if x == 0 || y == 0 || x == OBJECT_CELL_WIDTH - 1 || y == OBJECT_CELL_HEIGHT - 1 {
+                    border
+                } else if y - 1 < height {
+                    let i = ((y - 1) * 8 + x - 1) * 4;
+                    // Transparent pixels show the background of the cell
+                    if image[i + 3] == 0 { OBJECT_BACKGROUND } else { image[i..i + 4].try_into().unwrap() }
+                } else {
+                    OBJECT_BORDER
                 };
-                let pixel_x = i % 7 * 8 + k as usize;
-                let pixel_y = i / 7 * 8 + j as usize;
-                if pixel_x < 56 && pixel_y < 96 {
-                    let pixel_index = (pixel_y as usize * 56 + pixel_x as usize) * 4;
-                    frame[pixel_index..pixel_index + 4].copy_from_slice(&mut rgb);
+                let pixel_index = ((cell_y + y) * OBJECTS_WIDTH + cell_x + x) * 4;
+                frame[pixel_index..pixel_index + 4].copy_from_slice(&rgba);
+            }
+        }
+    }
+}
+
+/// Log the attributes of every object and the objects dropped on each line
+fn log_objects(ppu: &Ppu) {
+    log::info!("#  X    Y    Tile Pal XFlip YFlip BgPri");
+    for entry in oam::oam_entries(ppu) {
+        let (x, y) = entry.screen_position();
+        log::info!(
+            "{:<2} {:<4} {:<4} {:02X}   {}   {:<5} {:<5} {}",
+            entry.index, x, y, entry.tile, entry.palette, entry.x_flip, entry.y_flip, entry.bg_priority
+        );
+    }
+    for (ly, indices) in oam::dropped_objects(ppu) {
+        log::info!("Line {}: dropped objects {:?}", ly, indices);
+    }
+}
+
+/// Open the default audio output, returning the stream and its sample rate
+/// The stream counts the frames of samples it plays, which is used to pace the emulation
+fn start_audio(frames_played: Arc<AtomicU64>) -> Option<(cpal::Stream, u32)> {
+    let host = cpal::default_host();
+    let device = match host.default_output_device() {
+        Some(device) => device,
+        None => {
+            eprintln!("Failed to get default output device, running without audio");
+            return None;
+        }
+    };
+    let supported_config = match device.supported_output_configs() {
+        Ok(mut configs) => configs.next()?.with_max_sample_rate(),
+        Err(err) => {
+            eprintln!("Error while querying configs: {}, running without audio", err);
+            return None;
+        }
+    };
+    let config: cpal::StreamConfig = supported_config.into();
+    let channels = config.channels as usize;
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            for frame in data.chunks_mut(channels) {
+                for sample in frame.iter_mut() {
+                    *sample = 0.00;
                 }
             }
+            frames_played.fetch_add((data.len() / channels) as u64, Ordering::Relaxed);
+        },
+        move |err| {
+            eprintln!("Error while playing audio: {}", err);
+        },
+        None
+    );
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("Failed to open audio stream: {}, running without audio", err);
+            return None;
         }
+    };
+    if let Err(err) = stream.play() {
+        eprintln!("Failed to play audio stream: {}, running without audio", err);
+        return None;
     }
+
+    Some((stream, config.sample_rate.0))
 }
 
 fn main() {
@@ -117,10 +386,20 @@ fn main() {
 
     let object_window = WindowBuilder::new()
         .with_title("Objects")
-        .with_inner_size(winit::dpi::LogicalSize::new(56 * 6, 48 * 6))
+        .with_inner_size(winit::dpi::LogicalSize::new(OBJECTS_WIDTH as u32 * 4, OBJECTS_HEIGHT as u32 * 4))
+        .build(&event_loop)
+        .unwrap();
+
+    let tilemap_window = WindowBuilder::new()
+        .with_title("Tile map")
+        .with_inner_size(winit::dpi::LogicalSize::new(TILEMAP_SIZE as u32 * 2, TILEMAP_SIZE as u32 * 2))
         .build(&event_loop)
         .unwrap();
 
+    if !args.tilemap {
+        tilemap_window.set_visible(false);
+    }
+
     if !args.tiles {
         tile_window.set_visible(false);
     }
@@ -129,10 +408,12 @@ fn main() {
         object_window.set_visible(false);
     }
 
+    let mut filters = ScreenFilters::new(args.scaler, args.frame_blending);
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(160, 144, surface_texture).unwrap()
+        let (width, height) = filters.size();
+        Pixels::new(width, height, surface_texture).unwrap()
     };
 
     let mut tile_pixels = {
@@ -142,34 +423,33 @@ fn main() {
         Pixels::new(16 * 8, 24 * 8, surface_texture).unwrap()
     };
 
+    let mut tilemap_pixels = {
+        let window_size = tilemap_window.inner_size();
+        let surface_texture =
+            SurfaceTexture::new(window_size.width, window_size.height, &tilemap_window);
+        Pixels::new(TILEMAP_SIZE as u32, TILEMAP_SIZE as u32, surface_texture).unwrap()
+    };
+    let mut tilemap_mode = TileMapMode::Background;
+
     let mut object_pixels = {
         let window_size = object_window.inner_size();
         let surface_texture =
             SurfaceTexture::new(window_size.width, window_size.height, &object_window);
-        Pixels::new(56, 48, surface_texture).unwrap()
+        Pixels::new(OBJECTS_WIDTH as u32, OBJECTS_HEIGHT as u32, surface_texture).unwrap()
     };
 
-    let host = cpal::default_host();
-    let device = host.default_output_device().expect("Failed to get default output device!");
-    let mut supported_configs_range = device.supported_output_configs().expect("Error while querying configs");
-    let supported_config = supported_configs_range.next().expect("No supported config?").with_max_sample_rate();
-    let config = supported_config.into();
-    let stream = device.build_output_stream(
-        &config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            for frame in data.chunks_mut(config.channels as usize) {
-                for sample in frame.iter_mut() {
-                    *sample = 0.00;
-                }
-            }
-        },
-        move |err| {
-            eprintln!("Error while playing audio: {}", err);
-        },
+    // Keep the stream alive for as long as the event loop runs
+    let frames_played = Arc::new(AtomicU64::new(0));
+    let audio = if args.no_audio {
         None
-    ).unwrap();
-
-    stream.play().unwrap();
+    } else {
+        start_audio(frames_played.clone())
+    };
+    let mut pacer = match &audio {
+        Some((_, sample_rate)) => FramePacer::new_with_audio(frames_played, *sample_rate),
+        None => FramePacer::new(),
+    };
+    let mut speed = SpeedControl::new(args.speed, args.fast_forward_speed);
 
     tile_pixels.render().expect("Failed to render tiles!");
     object_pixels.render().expect("Failed to render objects!");
@@ -177,11 +457,59 @@ fn main() {
     // If no log level is specified, default to info or above
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let mut gb = Gameboy::new(&args.rom);
+    let mut input_mapper = InputMapper::new(load_input_config(args.input_config.as_deref()));
+    let mut gilrs = match gilrs::Gilrs::new() {
+        Ok(gilrs) => Some(gilrs),
+        Err(e) => {
+            log::warn!("Failed to initialise gamepad support: {}", e);
+            None
+        }
+    };
+
+    // A movie has to be played back on the model it was recorded on
+    let movie = args.play.as_ref().map(|path| match Movie::load(path) {
+        Ok(movie) => movie,
+        Err(e) => {
+            eprintln!("Failed to load movie {}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+    let model = match &movie {
+        Some(movie) => Some(movie.model),
+        None => args.model,
+    };
+
+    let mut gb = match Gameboy::new_with_model(&args.rom, model) {
+        Ok(gb) => gb,
+        Err(e) => {
+            eprintln!("Failed to load ROM {}: {}", args.rom, e);
+            std::process::exit(1);
+        }
+    };
+    let mut player = movie.map(|movie| {
+        if let Err(e) = movie.check(&gb) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        MoviePlayer::new(movie)
+    });
+    gb.mmu.cheats = load_cheats(args.cheats.as_deref(), &args.rom);
+    let mut recording = args.record.as_ref().map(|_| Movie::new(&gb));
+    let mut capture = Capture::new(args.capture_dir.clone(), args.capture_scale, args.palette);
     Window::set_title(
         &window,
         ("RustyBoy - ".to_owned() + &gb.mmu.cart_title).as_str(),
     );
+    let mut repl = args.repl.then(Repl::spawn);
+    let mut script = args.script.as_ref().map(|path| match Script::load(path, &mut gb) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("Failed to load script {}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+    // Set once the emulated machine stops, the last frame stays on screen
+    let mut stopped = false;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -202,79 +530,145 @@ fn main() {
                 event: WindowEvent::KeyboardInput { input, .. },
                 window_id
             } if window.id() == window_id => {
-                match input.virtual_keycode {
-                    Some(VirtualKeyCode::Up) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::Up);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::Up);
-                        }
-                    },
-                    Some(VirtualKeyCode::Down) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::Down);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::Down);
-                        }
-                    },
-                    Some(VirtualKeyCode::Left) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::Left);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::Left);
-                        }
-                    },
-                    Some(VirtualKeyCode::Right) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::Right);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::Right);
+                if let Some(key) = input.virtual_keycode {
+                    let pressed = input.state == ElementState::Pressed;
+                    if let Some(action) = input_mapper.set_held(Source::Keyboard(key), pressed) {
+                        handle_hotkey(action, pressed, &input_mapper, &mut speed, &mut capture, &mut filters, &mut gb);
+                    }
+                }
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input, .. },
+                window_id
+            } if tilemap_window.id() == window_id => {
+                if let (Some(key), ElementState::Pressed) = (input.virtual_keycode, input.state) {
+                    tilemap_mode.handle_key(key, &gb);
+                }
+            }
+
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input, .. },
+                window_id
+            } if object_window.id() == window_id => {
+                if let (Some(VirtualKeyCode::I), ElementState::Pressed) = (input.virtual_keycode, input.state) {
+                    log_objects(&gb.mmu.ppu);
+                }
+            }
+
+            Event::MainEventsCleared => {
+                if let Some(gilrs) = &mut gilrs {
+                    while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+                        let (source, pressed) = match event {
+                            EventType::ButtonPressed(button, _) => (Source::Gamepad(id, button), true),
+                            EventType::ButtonReleased(button, _) => (Source::Gamepad(id, button), false),
+                            EventType::AxisChanged(axis, value, _) => {
+                                input_mapper.set_axis(id, axis, value);
+                                continue;
+                            }
+                            EventType::Disconnected => {
+                                input_mapper.release_gamepad(id);
+                                speed.fast_forward_held = input_mapper.is_held(Action::FastForward);
+                                continue;
+                            }
+                            _ => continue,
+                        };
+                        if let Some(action) = input_mapper.set_held(source, pressed) {
+                            handle_hotkey(action, pressed, &input_mapper, &mut speed, &mut capture, &mut filters, &mut gb);
                         }
-                    },
-                    Some(VirtualKeyCode::Z) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::A);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::A);
+                    }
+                }
+
+                if let Some(repl) = &mut repl {
+                    repl.poll(&mut gb.mmu);
+                }
+
+                let multiplier = speed.multiplier();
+                let frames = pacer.frames_due(multiplier);
+                if frames > 0 && !stopped {
+                    for _ in 0..frames {
+                        let playing = match &mut player {
+                            Some(player) => player.play_frame(&mut gb.mmu.joypad),
+                            None => false,
+                        };
+                        if !playing {
+                            if let Some(player) = player.take() {
+                                log::info!("Movie finished after {} frames", player.frame());
+                            }
+                            input_mapper.update(&mut gb.mmu.joypad);
                         }
-                    },
-                    Some(VirtualKeyCode::X) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::B);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::B);
+                        if let Some(script) = &mut script {
+                            script.apply_input(&mut gb.mmu.joypad);
                         }
-                    },
-                    Some(VirtualKeyCode::Return) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::Start);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::Start);
+                        filters.remember_frame(&gb.mmu.ppu.frame_buffer);
+                        if let Some(movie) = &mut recording {
+                            movie.record_frame(&gb.mmu.joypad);
                         }
-                    },
-                    Some(VirtualKeyCode::Back) => {
-                        if input.state == ElementState::Pressed {
-                            gb.mmu.joypad.push_key(Key::Select);
-                        } else {
-                            gb.mmu.joypad.release_key(Key::Select);
+                        let result = match &mut script {
+                            Some(script) => script.run_frame(&mut gb),
+                            None => gb.step(),
+                        };
+                        if let Err(err) = result {
+                            log::error!("Emulation stopped: {}", err);
+                            window.set_title(&format!("RustyBoy - {} (stopped: {})", gb.mmu.cart_title, err));
+                            stopped = true;
+                            break;
                         }
-                    },
-                    _ => (),
+                        capture.write_frame(&gb.mmu.ppu.frame_buffer);
+                    }
+                    let tilemap = args.tilemap.then(|| (&mut tilemap_pixels, tilemap_mode.view(&gb)));
+                    let overlay = script.as_ref().map(|script| script.overlay());
+                    render(&mut gb, &args.palette, &mut filters, (&mut pixels, overlay.as_deref()), &mut object_pixels, &mut tile_pixels, tilemap);
+                }
+                if *control_flow != ControlFlow::Exit {
+                    *control_flow = ControlFlow::WaitUntil(pacer.next_check(multiplier));
                 }
             }
 
-            _ => (),
-        }
+            Event::LoopDestroyed => {
+                capture.stop_video();
+                if let (Some(movie), Some(path)) = (&recording, &args.record) {
+                    match movie.save(path) {
+                        Ok(()) => log::info!("Saved {} frame movie to {}", movie.frames.len(), path),
+                        Err(e) => log::error!("Failed to save movie to {}: {}", path, e),
+                    }
+                }
+            }
 
-        gb.step();
-        generate_pixels(pixels.frame_mut(), &gb.mmu.ppu.frame_buffer);
-        generate_objects(object_pixels.frame_mut(), &gb.mmu.ppu.oam, &gb.mmu.ppu.vram);
-        pixels.render().expect("Failed to render!");
-        object_pixels.render().expect("Failed to render objects!");
-        if gb.mmu.ppu.vram_changed {
-            generate_tiles(tile_pixels.frame_mut(), &gb.mmu.ppu.vram);
-            tile_pixels.render().expect("Failed to render tiles!");
-            gb.mmu.ppu.vram_changed = false;
+            _ => (),
         }
     });
 }
+
+/// Draw the screen with the script overlay on top, and the debug windows
+fn render(
+    gb: &mut Gameboy,
+    palette: &Palette,
+    filters: &mut ScreenFilters,
+    (pixels, overlay): (&mut Pixels, Option<&Overlay>),
+    object_pixels: &mut Pixels,
+    tile_pixels: &mut Pixels,
+    tilemap: Option<(&mut Pixels, TileMapView)>,
+) {
+    let (width, height) = filters.size();
+    if pixels.frame().len() != (width * height * 4) as usize {
+        pixels.resize_buffer(width, height).expect("Failed to resize buffer!");
+    }
+    filters.draw(&gb.mmu.ppu.frame_buffer, palette, pixels.frame_mut());
+    // Drawn after scaling so that text stays sharp
+    if let Some(overlay) = overlay {
+        overlay.composite(pixels.frame_mut(), filters.scaler.factor());
+    }
+    generate_objects(object_pixels.frame_mut(), &gb.mmu.ppu, palette);
+    pixels.render().expect("Failed to render!");
+    object_pixels.render().expect("Failed to render objects!");
+    if let Some((tilemap_pixels, view)) = tilemap {
+        tilemap_pixels.frame_mut().copy_from_slice(&view.render(&gb.mmu.ppu, palette));
+        tilemap_pixels.render().expect("Failed to render tile map!");
+    }
+    if gb.mmu.ppu.vram_changed {
+        generate_tiles(tile_pixels.frame_mut(), &gb.mmu.ppu.vram, palette);
+        tile_pixels.render().expect("Failed to render tiles!");
+        gb.mmu.ppu.vram_changed = false;
+    }
+}
@@ -0,0 +1,360 @@
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// Glyphs of a 3x5 font for ASCII 0x20-0x7E, 3 bits per row from the top with the left pixel
+/// in the highest bit
+const FONT: [u16; 95] = [
+    0b000_000_000_000_000, // space
+    0b010_010_010_000_010, // !
+    0b101_101_000_000_000, // "
+    0b101_111_101_111_101, // #
+    0b011_110_010_011_110, // $
+    0b101_001_010_100_101, // %
+    0b010_101_010_101_011, // &
+    0b010_010_000_000_000, // '
+    0b001_010_010_010_001, // (
+    0b100_010_010_010_100, // )
+    0b000_101_010_101_000, // *
+    0b000_010_111_010_000, // +
+    0b000_000_000_010_100, // ,
+    0b000_000_111_000_000, // -
+    0b000_000_000_000_010, // .
+    0b001_001_010_100_100, // /
+    0b111_101_101_101_111, // 0
+    0b010_110_010_010_111, // 1
+    0b111_001_111_100_111, // 2
+    0b111_001_011_001_111, // 3
+    0b101_101_111_001_001, // 4
+    0b111_100_111_001_111, // 5
+    0b111_100_111_101_111, // 6
+    0b111_001_010_010_010, // 7
+    0b111_101_111_101_111, // 8
+    0b111_101_111_001_111, // 9
+    0b000_010_000_010_000, // :
+    0b000_010_000_010_100, // ;
+    0b001_010_100_010_001, // <
+    0b000_111_000_111_000, // =
+    0b100_010_001_010_100, // >
+    0b111_001_010_000_010, // ?
+    0b010_101_111_100_011, // @
+    0b010_101_111_101_101, // A
+    0b110_101_110_101_110, // B
+    0b011_100_100_100_011, // C
+    0b110_101_101_101_110, // D
+    0b111_100_110_100_111, // E
+    0b111_100_110_100_100, // F
+    0b011_100_101_101_011, // G
+    0b101_101_111_101_101, // H
+    0b111_010_010_010_111, // I
+    0b001_001_001_101_010, // J
+    0b101_101_110_101_101, // K
+    0b100_100_100_100_111, // L
+    0b101_111_111_101_101, // M
+    0b110_101_101_101_101, // N
+    0b010_101_101_101_010, // O
+    0b110_101_110_100_100, // P
+    0b010_101_101_110_011, // Q
+    0b110_101_110_101_101, // R
+    0b011_100_010_001_110, // S
+    0b111_010_010_010_010, // T
+    0b101_101_101_101_111, // U
+    0b101_101_101_010_010, // V
+    0b101_101_111_111_101, // W
+    0b101_101_010_101_101, // X
+    0b101_101_010_010_010, // Y
+    0b111_001_010_100_111, // Z
+    0b011_010_010_010_011, // [
+    0b100_100_010_001_001, // \
+    0b110_010_010_010_110, // ]
+    0b010_101_000_000_000, // ^
+    0b000_000_000_000_111, // _
+    0b100_010_000_000_000, // `
+    // a-z use the uppercase glyphs
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0b011_010_110_010_011, // {
+    0b010_010_010_010_010, // |
+    0b110_010_011_010_110, // }
+    0b000_011_110_000_000, // ~
+];
+
+/// Width and height of a character including the space after it
+const CHAR_WIDTH: usize = 4;
+const CHAR_HEIGHT: usize = 6;
+
+/// The rows of a character's glyph, unknown characters are drawn as ?
+fn glyph(c: char) -> u16 {
+    let c = c.to_ascii_uppercase();
+    match c {
+        ' '..='~' => FONT[c as usize - 0x20],
+        _ => FONT['?' as usize - 0x20],
+    }
+}
+
+/// Cut a line down to the part that's on the screen with the Liang-Barsky algorithm
+/// Returns None if none of it is
+fn clip_line(x1: i32, y1: i32, x2: i32, y2: i32) -> Option<(i32, i32, i32, i32)> {
+    let (dx, dy) = ((x2 - x1) as f64, (y2 - y1) as f64);
+    let (mut start, mut end) = (0.0, 1.0);
+    let edges = [
+        (-dx, x1 as f64),
+        (dx, (WIDTH - 1) as f64 - x1 as f64),
+        (-dy, y1 as f64),
+        (dy, (HEIGHT - 1) as f64 - y1 as f64),
+    ];
+    for (p, q) in edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            start = f64::max(start, q / p);
+        } else {
+            end = f64::min(end, q / p);
+        }
+    }
+    if start > end {
+        return None;
+    }
+    let point = |t: f64| ((x1 as f64 + t * dx).round() as i32, (y1 as f64 + t * dy).round() as i32);
+    let ((x1, y1), (x2, y2)) = (point(start), point(end));
+    Some((x1, y1, x2, y2))
+}
+
+/// Text and shapes drawn by scripts on top of the screen
+/// Colours are RGBA packed as 0xRRGGBBAA, drawing blends them over what's already there
+pub struct Overlay {
+    /// RGBA pixels with straight alpha, transparent where nothing was drawn
+    pixels: Vec<u8>,
+    /// Whether anything has been drawn since the overlay was cleared
+    dirty: bool,
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Overlay {
+            pixels: vec![0; WIDTH * HEIGHT * 4],
+            dirty: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.dirty {
+            self.pixels.fill(0);
+            self.dirty = false;
+        }
+    }
+
+    /// Blend a pixel over the overlay, pixels off the screen are ignored
+    pub fn pixel(&mut self, x: i32, y: i32, color: u32) {
+        if !(0..WIDTH as i32).contains(&x) || !(0..HEIGHT as i32).contains(&y) {
+            return;
+        }
+        let [r, g, b, a] = color.to_be_bytes();
+        if a == 0 {
+            return;
+        }
+        let i = (y as usize * WIDTH + x as usize) * 4;
+        let dst = &mut self.pixels[i..i + 4];
+        let (src_a, dst_a) = (a as u32, dst[3] as u32);
+        // Porter-Duff over with straight alpha
+        let out_a = src_a + dst_a * (255 - src_a) / 255;
+        for (d, s) in dst.iter_mut().zip([r, g, b]) {
+            *d = ((s as u32 * src_a + *d as u32 * dst_a * (255 - src_a) / 255) / out_a) as u8;
+        }
+        dst[3] = out_a as u8;
+        self.dirty = true;
+    }
+
+    /// Draw a line with Bresenham's algorithm
+    pub fn line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, color: u32) {
+        let Some((x1, y1, x2, y2)) = clip_line(x1, y1, x2, y2) else {
+            return;
+        };
+        let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+        let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+        let (mut x, mut y, mut error) = (x1, y1, dx + dy);
+        loop {
+            self.pixel(x, y, color);
+            if x == x2 && y == y2 {
+                break;
+            }
+            // Both steps are decided on the error from before either of them
+            let double_error = 2 * error;
+            if double_error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if double_error <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draw a rectangle between two corners, including both
+    pub fn rect(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, outline: u32, fill: u32) {
+        let (left, right) = (x1.min(x2), x1.max(x2));
+        let (top, bottom) = (y1.min(y2), y1.max(y2));
+        // Only the part on the screen is drawn
+        for y in top.max(0)..=bottom.min(HEIGHT as i32 - 1) {
+            for x in left.max(0)..=right.min(WIDTH as i32 - 1) {
+                let edge = x == left || x == right || y == top || y == bottom;
+                self.pixel(x, y, if edge { outline } else { fill });
+            }
+        }
+    }
+
+    /// Draw text with its top left corner at a position, lines are split at newlines
+    /// The background is drawn behind every character cell
+    pub fn text(&mut self, x: i32, y: i32, text: &str, color: u32, background: u32) {
+        for (row, line) in text.lines().enumerate() {
+            let top = y + (row * CHAR_HEIGHT) as i32;
+            for (column, c) in line.chars().enumerate() {
+                let left = x + (column * CHAR_WIDTH) as i32;
+                let glyph = glyph(c);
+                for cy in 0..CHAR_HEIGHT as i32 {
+                    for cx in 0..CHAR_WIDTH as i32 {
+                        let set = cx < 3 && cy < 5 && glyph & (1 << (14 - cy * 3 - cx)) != 0;
+                        self.pixel(left + cx, top + cy, if set { color } else { background });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Blend the overlay over an RGBA frame scaled up by an integer factor
+    pub fn composite(&self, out: &mut [u8], factor: usize) {
+        if !self.dirty {
+            return;
+        }
+        for (y, row) in out.chunks_exact_mut(WIDTH * factor * 4).enumerate() {
+            for (x, dst) in row.chunks_exact_mut(4).enumerate() {
+                let i = ((y / factor) * WIDTH + x / factor) * 4;
+                let src = &self.pixels[i..i + 4];
+                let a = src[3] as u32;
+                for c in 0..3 {
+                    dst[c] = ((src[c] as u32 * a + dst[c] as u32 * (255 - a)) / 255) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = 0xFF0000FF;
+
+    /// The positions of the pixels that have been drawn
+    fn drawn(overlay: &Overlay) -> Vec<(usize, usize)> {
+        let pixels = overlay.pixels.chunks_exact(4).enumerate();
+        pixels.filter(|(_, pixel)| pixel[3] != 0).map(|(i, _)| (i % WIDTH, i / WIDTH)).collect()
+    }
+
+    fn rgba(overlay: &Overlay, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * WIDTH + x) * 4;
+        overlay.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_clip_line() {
+        assert_eq!(clip_line(10, 10, 20, 30), Some((10, 10, 20, 30)));
+        assert_eq!(clip_line(-10, 5, 10, 5), Some((0, 5, 10, 5)));
+        assert_eq!(clip_line(80, -20, 80, 200), Some((80, 0, 80, 143)));
+        assert_eq!(clip_line(-10, -10, 200, 200), Some((0, 0, 143, 143)));
+        // Lines entirely off the screen, including ones parallel to an edge
+        assert_eq!(clip_line(-5, -5, -1, -1), None);
+        assert_eq!(clip_line(-1, 0, -1, 100), None);
+        assert_eq!(clip_line(200, 10, 300, 10), None);
+        assert_eq!(clip_line(-10, 150, 170, 300), None);
+    }
+
+    #[test]
+    fn test_line() {
+        let mut overlay = Overlay::new();
+        overlay.line(0, 0, 4, 2, RED);
+        assert_eq!(drawn(&overlay), [(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]);
+
+        // Drawing in the other direction or steeply gives the same kind of line
+        let mut overlay = Overlay::new();
+        overlay.line(2, 4, 0, 0, RED);
+        assert_eq!(drawn(&overlay), [(0, 0), (0, 1), (1, 2), (1, 3), (2, 4)]);
+
+        let mut overlay = Overlay::new();
+        overlay.line(-5, 3, 200, 3, RED);
+        assert_eq!(drawn(&overlay).len(), WIDTH);
+    }
+
+    #[test]
+    fn test_rect() {
+        let mut overlay = Overlay::new();
+        overlay.rect(3, 3, 1, 1, RED, 0x00FF00FF);
+        assert_eq!(drawn(&overlay).len(), 9);
+        assert_eq!(rgba(&overlay, 1, 1), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(rgba(&overlay, 2, 2), [0, 0xFF, 0, 0xFF]);
+
+        let mut overlay = Overlay::new();
+        overlay.rect(-10, 5, 1000, 20, RED, 0);
+        // Only the top and bottom edges are on the screen
+        assert_eq!(drawn(&overlay).len(), 2 * WIDTH);
+    }
+
+    #[test]
+    fn test_pixel_blending() {
+        let mut overlay = Overlay::new();
+        overlay.pixel(0, 0, 0x0000FF80);
+        assert_eq!(rgba(&overlay, 0, 0), [0, 0, 0xFF, 0x80]);
+        // Half transparent over half transparent covers three quarters
+        overlay.pixel(0, 0, 0xFF000080);
+        assert_eq!(rgba(&overlay, 0, 0), [0xAA, 0, 0x55, 0xBF]);
+        // An opaque colour replaces what's there, a transparent one changes nothing
+        overlay.pixel(0, 0, 0x00FF00FF);
+        overlay.pixel(0, 0, 0xFFFFFF00);
+        assert_eq!(rgba(&overlay, 0, 0), [0, 0xFF, 0, 0xFF]);
+        overlay.pixel(-1, 0, RED);
+        overlay.pixel(0, HEIGHT as i32, RED);
+        assert_eq!(drawn(&overlay), [(0, 0)]);
+    }
+
+    #[test]
+    fn test_composite() {
+        let mut overlay = Overlay::new();
+        let mut out = vec![0x40; WIDTH * HEIGHT * 4 * 4];
+        // Nothing is blended while the overlay is empty
+        overlay.composite(&mut out, 2);
+        assert!(out.iter().all(|&c| c == 0x40));
+
+        overlay.pixel(1, 0, 0xFFFFFF80);
+        overlay.composite(&mut out, 2);
+        let row = WIDTH * 2 * 4;
+        for (x, y) in [(2, 0), (3, 0), (2, 1), (3, 1)] {
+            let i = y * row + x * 4;
+            assert_eq!(out[i..i + 4], [0x9F, 0x9F, 0x9F, 0x40]);
+        }
+        assert_eq!(out[..4], [0x40; 4]);
+        assert_eq!(out[row + 16..row + 20], [0x40; 4]);
+
+        overlay.clear();
+        assert!(drawn(&overlay).is_empty());
+    }
+
+    #[test]
+    fn test_glyph() {
+        // Rows go from the top with the left pixel in the highest bit
+        assert_eq!(glyph('L'), 0b100_100_100_100_111);
+        assert_eq!(glyph('l'), glyph('L'));
+        assert_eq!(glyph('é'), glyph('?'));
+
+        let mut overlay = Overlay::new();
+        overlay.text(0, 0, "L\n7", RED, 0);
+        let expected = [(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (1, 4), (2, 4)];
+        let below = [(0, 6), (1, 6), (2, 6), (2, 7), (1, 8), (1, 9), (1, 10)];
+        assert_eq!(drawn(&overlay), [&expected[..], &below[..]].concat());
+
+        // The background fills the whole cell, including the space after the glyph
+        let mut overlay = Overlay::new();
+        overlay.text(0, 0, "AB", RED, 0x000000FF);
+        assert_eq!(drawn(&overlay).len(), 2 * CHAR_WIDTH * CHAR_HEIGHT);
+    }
+}
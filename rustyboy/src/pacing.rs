@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The Gameboy's refresh rate, 4194304 Hz / 70224 clock cycles per frame
+pub const FRAME_RATE: f64 = 4194304.0 / 70224.0;
+
+/// Speed used by slow motion
+const SLOW_MOTION_SPEED: f64 = 0.25;
+
+/// Speeds are clamped to this range, so pacing never divides by zero or overflows a `Duration`
+const MIN_SPEED: f64 = 0.01;
+const MAX_SPEED: f64 = 100.0;
+
+/// The most frames emulated at once when catching up, anything beyond this is dropped
+/// so a stall (like dragging the window) doesn't cause a long burst of fast emulation
+const MAX_CATCH_UP_FRAMES: u32 = 10;
+
+/// The clock emulation is synchronised to
+enum PaceClock {
+    /// The system clock
+    Wall { last: Instant },
+    /// The number of audio frames consumed by the output stream
+    Audio {
+        frames_played: Arc<AtomicU64>,
+        sample_rate: u32,
+        last: u64,
+    },
+}
+
+/// Decides how many frames to emulate to keep the emulation running at the right speed
+pub struct FramePacer {
+    clock: PaceClock,
+    /// Frames owed to the emulation, the fractional part carries over to the next check
+    budget: f64,
+}
+
+impl FramePacer {
+    /// Pace the emulation using the system clock
+    pub fn new() -> Self {
+        FramePacer {
+            clock: PaceClock::Wall {
+                last: Instant::now(),
+            },
+            budget: 0.0,
+        }
+    }
+
+    /// Pace the emulation using the audio output stream
+    /// `frames_played` must be increased by the audio callback for every frame of samples it writes
+    pub fn new_with_audio(frames_played: Arc<AtomicU64>, sample_rate: u32) -> Self {
+        let last = frames_played.load(Ordering::Relaxed);
+        FramePacer {
+            clock: PaceClock::Audio {
+                frames_played,
+                sample_rate,
+                last,
+            },
+            budget: 0.0,
+        }
+    }
+
+    /// The number of frames to emulate now to keep up with the clock at a given speed
+    pub fn frames_due(&mut self, speed: f64) -> u32 {
+        let elapsed = match &mut self.clock {
+            PaceClock::Wall { last } => {
+                let now = Instant::now();
+                let elapsed = now.duration_since(*last).as_secs_f64();
+                *last = now;
+                elapsed
+            }
+            PaceClock::Audio {
+                frames_played,
+                sample_rate,
+                last,
+            } => {
+                let now = frames_played.load(Ordering::Relaxed);
+                let elapsed = now.saturating_sub(*last) as f64 / *sample_rate as f64;
+                *last = now;
+                elapsed
+            }
+        };
+
+        self.budget += elapsed * FRAME_RATE * speed;
+        let frames = self.budget.floor() as u32;
+        if frames > MAX_CATCH_UP_FRAMES {
+            self.budget = 0.0;
+            return MAX_CATCH_UP_FRAMES;
+        }
+        self.budget -= frames as f64;
+        frames
+    }
+
+    /// When to check for due frames again at a given speed
+    pub fn next_check(&self, speed: f64) -> Instant {
+        let remaining = (1.0 - self.budget).max(0.0) / (FRAME_RATE * speed);
+        Instant::now() + Duration::from_secs_f64(remaining)
+    }
+}
+
+/// The speed controls of the frontend
+pub struct SpeedControl {
+    /// Speed of normal emulation
+    pub base_speed: f64,
+    /// Speed while fast forwarding
+    pub fast_forward_speed: f64,
+    /// Is the fast forward key held down
+    pub fast_forward_held: bool,
+    /// Is turbo mode toggled on, which fast forwards until toggled off
+    pub turbo: bool,
+    /// Is slow motion toggled on
+    pub slow_motion: bool,
+}
+
+/// Parse a speed multiplier argument, only finite speeds above 0 are accepted
+pub fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|_| format!("{} is not a number", s))?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!("Speed has to be above 0, got {}", s));
+    }
+    Ok(speed)
+}
+
+fn clamp_speed(speed: f64) -> f64 {
+    if speed.is_nan() {
+        1.0
+    } else {
+        speed.clamp(MIN_SPEED, MAX_SPEED)
+    }
+}
+
+impl SpeedControl {
+    pub fn new(base_speed: f64, fast_forward_speed: f64) -> Self {
+        SpeedControl {
+            base_speed: clamp_speed(base_speed),
+            fast_forward_speed: clamp_speed(fast_forward_speed),
+            fast_forward_held: false,
+            turbo: false,
+            slow_motion: false,
+        }
+    }
+
+    /// The current speed multiplier, fast forwarding takes precedence over slow motion
+    pub fn multiplier(&self) -> f64 {
+        if self.fast_forward_held || self.turbo {
+            self.fast_forward_speed
+        } else if self.slow_motion {
+            self.base_speed * SLOW_MOTION_SPEED
+        } else {
+            self.base_speed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pacer driven by an audio clock the test advances by hand
+    fn audio_pacer() -> (FramePacer, Arc<AtomicU64>) {
+        let frames_played = Arc::new(AtomicU64::new(0));
+        (FramePacer::new_with_audio(frames_played.clone(), 48000), frames_played)
+    }
+
+    /// Advance the audio clock by a number of emulated frames at normal speed
+    fn advance(frames_played: &AtomicU64, frames: f64) {
+        let samples = (frames * 48000.0 / FRAME_RATE).round() as u64;
+        frames_played.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_frames_due() {
+        let (mut pacer, frames_played) = audio_pacer();
+        assert_eq!(pacer.frames_due(1.0), 0);
+        advance(&frames_played, 1.5);
+        assert_eq!(pacer.frames_due(1.0), 1);
+        // The half frame left over carries over
+        advance(&frames_played, 0.6);
+        assert_eq!(pacer.frames_due(1.0), 1);
+    }
+
+    #[test]
+    fn test_frames_due_catch_up_capped() {
+        let (mut pacer, frames_played) = audio_pacer();
+        advance(&frames_played, 100.0);
+        assert_eq!(pacer.frames_due(1.0), MAX_CATCH_UP_FRAMES);
+        // The rest of the backlog is dropped
+        assert_eq!(pacer.frames_due(1.0), 0);
+    }
+
+    #[test]
+    fn test_frames_due_speed() {
+        let (mut pacer, frames_played) = audio_pacer();
+        advance(&frames_played, 2.1);
+        assert_eq!(pacer.frames_due(4.0), 8);
+        advance(&frames_played, 4.0);
+        assert_eq!(pacer.frames_due(0.25), 1);
+    }
+
+    #[test]
+    fn test_next_check() {
+        let (mut pacer, frames_played) = audio_pacer();
+        advance(&frames_played, 0.5);
+        pacer.frames_due(1.0);
+        let wait = pacer.next_check(1.0) - Instant::now();
+        assert!(wait <= Duration::from_secs_f64(0.5 / FRAME_RATE));
+        assert!(wait > Duration::from_secs_f64(0.4 / FRAME_RATE));
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut speed = SpeedControl::new(1.5, 4.0);
+        assert_eq!(speed.multiplier(), 1.5);
+        speed.slow_motion = true;
+        assert_eq!(speed.multiplier(), 1.5 * SLOW_MOTION_SPEED);
+        // Fast forwarding takes precedence over slow motion
+        speed.fast_forward_held = true;
+        assert_eq!(speed.multiplier(), 4.0);
+        speed.fast_forward_held = false;
+        speed.turbo = true;
+        assert_eq!(speed.multiplier(), 4.0);
+        speed.turbo = false;
+        assert_eq!(speed.multiplier(), 1.5 * SLOW_MOTION_SPEED);
+    }
+
+    #[test]
+    fn test_speed_clamped() {
+        let speed = SpeedControl::new(0.0, f64::NAN);
+        assert_eq!(speed.base_speed, MIN_SPEED);
+        assert_eq!(speed.fast_forward_speed, 1.0);
+        let speed = SpeedControl::new(-2.0, f64::INFINITY);
+        assert_eq!(speed.base_speed, MIN_SPEED);
+        assert_eq!(speed.fast_forward_speed, MAX_SPEED);
+        // The slowest speed still gives a valid wait
+        FramePacer::new().next_check(speed.base_speed * SLOW_MOTION_SPEED);
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert!(parse_speed("0").is_err());
+        assert!(parse_speed("-1").is_err());
+        assert!(parse_speed("NaN").is_err());
+        assert!(parse_speed("inf").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+
+use rustyboy_core::cheats::Cheat;
+use rustyboy_core::mmu::Memory;
+use rustyboy_core::ram_search::{Comparison, RamSearch, Width};
+
+/// Number of bytes dumped when no length is given
+const DEFAULT_DUMP_LENGTH: usize = 0x40;
+/// Stop listing search results after this many matches
+const MAX_MATCHES: usize = 32;
+
+/// The regions searched by `find`, as the start address, length and first bank
+/// Only the bank counts depend on the cartridge and model, so they're looked up when searching
+const SEARCH_REGIONS: [(u16, u16, usize); 8] = [
+    (0x0000, 0x4000, 0),
+    (0x4000, 0x4000, 1),
+    (0x8000, 0x2000, 0),
+    (0xA000, 0x2000, 0),
+    (0xC000, 0x1000, 0),
+    (0xD000, 0x1000, 1),
+    (0xFE00, 0x00A0, 0),
+    (0xFF80, 0x007F, 0),
+];
+
+const HELP: &str = "\
+Addresses are hex and can be prefixed with a bank, like 3:4000
+  x <addr> [len]        dump memory, also dump
+  w <addr> <bytes...>   write bytes, ROM is patched in place
+  find <bytes...>       search every bank of ROM, VRAM, cartridge RAM, WRAM, OAM and HRAM
+  ptr <addr> [len]      read a little endian pointer and dump the memory it points to
+  bank <addr>           show the bank mapped at an address and how many there are
+  cheats                list the cheats
+  cheat add <codes> [name]  add a Game Genie or GameShark cheat, codes are separated by +
+  cheat on|off <n>      enable or disable a cheat
+  search new [8|16]     start a RAM search over cartridge RAM, WRAM and HRAM with 8 or 16 bit values
+  search equal|changed|increased|decreased
+                        keep the addresses whose value compares like this to the last search
+  search value <n>      keep the addresses with a specific value
+  search list           show the addresses left
+  help                  show this message";
+
+/// A memory viewer and editor reading commands from stdin while the emulator runs
+/// Memory is read and written without side effects, so it doesn't disturb the game
+pub struct Repl {
+    lines: Receiver<String>,
+    /// The running RAM search
+    search: Option<RamSearch>,
+}
+
+impl Repl {
+    /// Start reading commands from stdin on a separate thread
+    pub fn spawn() -> Self {
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("Memory REPL ready, type help for a list of commands");
+        Repl { lines, search: None }
+    }
+
+    /// Run the commands entered since the last call, called between frames
+    pub fn poll(&mut self, mem: &mut Memory) {
+        while let Ok(line) = self.lines.try_recv() {
+            match self.run_command(&line, mem) {
+                Ok(output) => print!("{}", output),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+/// An address with an optional bank, no bank means the one currently mapped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Location {
+    bank: Option<usize>,
+    addr: u16,
+}
+
+impl Location {
+    fn offset(self, n: usize) -> Self {
+        Location {
+            bank: self.bank,
+            addr: self.addr.wrapping_add(n as u16),
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Result<usize, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches('$');
+    usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex number {}", s))
+}
+
+fn parse_location(s: &str) -> Result<Location, String> {
+    let (bank, addr) = match s.split_once(':') {
+        Some((bank, addr)) => (Some(parse_hex(bank)?), addr),
+        None => (None, s),
+    };
+    let addr = parse_hex(addr)?;
+    if addr > 0xFFFF {
+        return Err(format!("Address {} is out of range", s));
+    }
+    Ok(Location { bank, addr: addr as u16 })
+}
+
+fn next_location<'a>(args: &mut impl Iterator<Item = &'a str>) -> Result<Location, String> {
+    match args.next() {
+        Some(arg) => parse_location(arg),
+        None => Err(String::from("Expected an address")),
+    }
+}
+
+fn parse_bytes<'a>(args: impl Iterator<Item = &'a str>) -> Result<Vec<u8>, String> {
+    let bytes = args
+        .map(|s| match parse_hex(s)? {
+            v if v <= 0xFF => Ok(v as u8),
+            _ => Err(format!("{} doesn't fit in a byte", s)),
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if bytes.is_empty() {
+        return Err(String::from("Expected at least one byte"));
+    }
+    Ok(bytes)
+}
+
+/// Format an address, with its bank if the memory there is banked
+fn format_location(mem: &Memory, loc: Location) -> String {
+    if mem.banks(loc.addr) > 1 {
+        let bank = loc.bank.unwrap_or_else(|| mem.current_bank(loc.addr));
+        format!("{:02X}:{:04X}", bank, loc.addr)
+    } else {
+        format!("{:04X}", loc.addr)
+    }
+}
+
+/// Hex dump with 16 bytes and their ASCII on every line
+fn dump(mem: &Memory, start: Location, len: usize) -> String {
+    let mut output = String::new();
+    for line in (0..len).step_by(16) {
+        let loc = start.offset(line);
+        let bytes: Vec<u8> = (0..16.min(len - line))
+            .map(|i| mem.peek(start.offset(line + i).addr, start.bank))
+            .collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        output += &format!("{}  {:<47}  {}\n", format_location(mem, loc), hex.join(" "), ascii);
+    }
+    output
+}
+
+/// Every location the bytes appear at, across all banks
+fn find(mem: &Memory, needle: &[u8]) -> Vec<Location> {
+    let mut matches = Vec::new();
+    for (start, len, first_bank) in SEARCH_REGIONS {
+        for bank in first_bank..mem.banks(start) {
+            let haystack: Vec<u8> = (0..len).map(|i| mem.peek(start + i, Some(bank))).collect();
+            for (i, window) in haystack.windows(needle.len()).enumerate() {
+                if window == needle {
+                    matches.push(Location {
+                        bank: Some(bank),
+                        addr: start + i as u16,
+                    });
+                }
+            }
+        }
+    }
+    matches
+}
+
+impl Repl {
+    fn run_command(&mut self, line: &str, mem: &mut Memory) -> Result<String, String> {
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(String::new()),
+        };
+
+        match command {
+            "x" | "dump" => {
+                let start = next_location(&mut args)?;
+                let len = args.next().map(parse_hex).transpose()?.unwrap_or(DEFAULT_DUMP_LENGTH);
+                Ok(dump(mem, start, len))
+            }
+            "w" => {
+                let start = next_location(&mut args)?;
+                let bytes = parse_bytes(args)?;
+                for (i, v) in bytes.iter().enumerate() {
+                    mem.poke(start.offset(i).addr, start.bank, *v);
+                }
+                Ok(format!("Wrote {} bytes at {}\n", bytes.len(), format_location(mem, start)))
+            }
+            "find" => {
+                let matches = find(mem, &parse_bytes(args)?);
+                let mut output = format!("{} matches\n", matches.len());
+                for loc in matches.iter().take(MAX_MATCHES) {
+                    output += &format!("  {}\n", format_location(mem, *loc));
+                }
+                if matches.len() > MAX_MATCHES {
+                    output += "  ...\n";
+                }
+                Ok(output)
+            }
+            "ptr" => {
+                let loc = next_location(&mut args)?;
+                let lo = mem.peek(loc.addr, loc.bank);
+                let hi = mem.peek(loc.offset(1).addr, loc.bank);
+                let len = args.next().map(parse_hex).transpose()?.unwrap_or(DEFAULT_DUMP_LENGTH);
+                let target = Location {
+                    bank: None,
+                    addr: u16::from_le_bytes([lo, hi]),
+                };
+                Ok(format!(
+                    "{} -> {}\n{}",
+                    format_location(mem, loc),
+                    format_location(mem, target),
+                    dump(mem, target, len)
+                ))
+            }
+            "bank" => {
+                let loc = next_location(&mut args)?;
+                Ok(format!(
+                    "{:04X}: bank {} of {}\n",
+                    loc.addr,
+                    mem.current_bank(loc.addr),
+                    mem.banks(loc.addr)
+                ))
+            }
+            "cheats" => {
+                let cheats = &mem.cheats;
+                let mut output = format!("Cheats are {}\n", if cheats.enabled { "on" } else { "off" });
+                for (i, cheat) in cheats.cheats.iter().enumerate() {
+                    let state = if cheat.enabled { "on" } else { "off" };
+                    output += &format!("  {:>2} {:<3} {} {}\n", i, state, cheat.code, cheat.name);
+                }
+                Ok(output)
+            }
+            "cheat" => match args.next() {
+                Some("add") => {
+                    let code = args.next().ok_or("Expected a cheat code")?;
+                    let name = args.collect::<Vec<_>>().join(" ");
+                    let cheat = Cheat::new(code, &name).map_err(|e| e.to_string())?;
+                    mem.cheats.add(cheat);
+                    Ok(format!("Added cheat {}\n", mem.cheats.cheats.len() - 1))
+                }
+                Some(state @ ("on" | "off")) => {
+                    let index = args.next().map(str::parse::<usize>).ok_or("Expected a cheat number")?;
+                    let cheat = index
+                        .ok()
+                        .and_then(|i| mem.cheats.cheats.get_mut(i))
+                        .ok_or("No such cheat, see cheats for a list")?;
+                    cheat.enabled = state == "on";
+                    Ok(format!("Turned {} {}\n", state, cheat.code))
+                }
+                _ => Err(String::from("Expected add, on or off")),
+            },
+            "search" => self.search(args, mem),
+            "help" => Ok(format!("{}\n", HELP)),
+            _ => Err(format!("Unknown command {}, type help for a list of commands", command)),
+        }
+    }
+
+    fn search<'a>(&mut self, mut args: impl Iterator<Item = &'a str>, mem: &Memory) -> Result<String, String> {
+        let comparison = match args.next() {
+            Some("new") => {
+                let width = match args.next() {
+                    None | Some("8") => Width::Byte,
+                    Some("16") => Width::Word,
+                    Some(width) => return Err(format!("Invalid width {}, expected 8 or 16", width)),
+                };
+                let search = RamSearch::new(mem, width);
+                let output = format!("Started search with {} addresses\n", search.candidates().len());
+                self.search = Some(search);
+                return Ok(output);
+            }
+            Some("list") => None,
+            Some("equal") => Some(Comparison::Equal),
+            Some("changed") => Some(Comparison::Changed),
+            Some("increased") => Some(Comparison::Increased),
+            Some("decreased") => Some(Comparison::Decreased),
+            Some("value") => {
+                let value = args.next().ok_or("Expected a value")?;
+                match parse_hex(value)? {
+                    v if v <= 0xFFFF => Some(Comparison::Value(v as u16)),
+                    _ => return Err(format!("{} doesn't fit in 16 bits", value)),
+                }
+            }
+            _ => return Err(String::from("Expected new, equal, changed, increased, decreased, value or list")),
+        };
+
+        let search = self.search.as_mut().ok_or("No search running, start one with search new")?;
+        if let Some(comparison) = comparison {
+            search.filter(mem, comparison);
+        }
+        let candidates = search.candidates();
+        let mut output = format!("{} addresses left\n", candidates.len());
+        for candidate in candidates.iter().take(MAX_MATCHES) {
+            let loc = Location {
+                bank: Some(candidate.bank),
+                addr: candidate.addr,
+            };
+            output += &format!("  {}  {:X}\n", format_location(mem, loc), candidate.previous);
+        }
+        if candidates.len() > MAX_MATCHES {
+            output += "  ...\n";
+        }
+        Ok(output)
+    }
+}
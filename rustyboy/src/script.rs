@@ -0,0 +1,601 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use mlua::{Function, Lua, RegistryKey, Table};
+
+use rustyboy_core::{
+    cpu::registers::Registers,
+    gameboy::{EmulationError, Gameboy},
+    hooks::{Access, Hit},
+    io::joypad::Joypad,
+};
+
+use crate::overlay::Overlay;
+
+/// Button names in the bit order of `Joypad::pressed_keys`
+const BUTTONS: [&str; 8] = ["a", "b", "select", "start", "right", "left", "up", "down"];
+
+const DEFAULT_TEXT_COLOR: u32 = 0xFFFFFFFF;
+const DEFAULT_TEXT_BACKGROUND: u32 = 0x000000A0;
+const DEFAULT_OUTLINE: u32 = 0xFFFFFFFF;
+
+/// A function a script registered with the event table
+struct Callback {
+    id: u32,
+    function: RegistryKey,
+}
+
+/// State shared between the script host and the functions it gives to Lua
+struct Shared {
+    frame_callbacks: Vec<Callback>,
+    /// Memory callbacks by the access and address that call them
+    memory_callbacks: HashMap<(Access, u16), Vec<Callback>>,
+    next_id: u32,
+    /// Set when memory callbacks were added or removed, so the core's hooks need updating
+    hooks_changed: bool,
+    overlay: Overlay,
+    /// Buttons set with joypad.set for the next frame, as a mask of the buttons and their state
+    input: (u8, u8),
+    frames: u64,
+}
+
+/// A Lua script run alongside the emulator
+///
+/// Scripts get these tables:
+/// - `memory`: `read(addr [, bank])`, `write(addr, value [, bank])`, `read16` and `write16`, which
+///   access memory without side effects like the REPL does
+/// - `registers`: the CPU registers by name, `a` to `l`, `af` to `hl`, `sp` and `pc`, can be assigned
+/// - `event`: `onframe(fn)` runs after every frame, `onread(addr, fn)`, `onwrite(addr, fn)` and
+///   `onexec(addr, fn)` run after the instruction accessing the address with the address and value,
+///   all of them return an id for `remove(id)`
+/// - `gui`: `text(x, y, text [, color [, background]])`, `pixel(x, y, color)`,
+///   `line(x1, y1, x2, y2, color)`, `box(x1, y1, x2, y2 [, outline [, fill]])` and `clear()`, drawn
+///   on an overlay that's cleared before every frame, colours are 0xRRGGBBAA
+/// - `joypad`: `get()` returns the held buttons as a table of booleans, `set(buttons)` presses or
+///   releases the buttons in the table for the next frame
+/// - `emu`: `framecount()`
+///
+/// An error stops the script, the game keeps running
+pub struct Script {
+    lua: Lua,
+    shared: Rc<RefCell<Shared>>,
+    /// Set once the script raised an error, nothing of it is run after that
+    stopped: Cell<bool>,
+}
+
+impl Script {
+    /// Load a script and run its main chunk, which usually registers callbacks
+    pub fn load(path: &str, gb: &mut Gameboy) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let shared = Rc::new(RefCell::new(Shared {
+            frame_callbacks: Vec::new(),
+            memory_callbacks: HashMap::new(),
+            next_id: 0,
+            hooks_changed: false,
+            overlay: Overlay::new(),
+            input: (0, 0),
+            frames: 0,
+        }));
+        let lua = Lua::new();
+        register_globals(&lua, &shared).map_err(|e| e.to_string())?;
+        let script = Script {
+            lua,
+            shared,
+            stopped: Cell::new(false),
+        };
+        // The @ makes Lua show the path in error messages instead of the start of the source
+        let chunk = script.lua.load(&source).set_name(format!("@{}", path));
+        let result = with_gameboy(&script.lua, gb, |_| chunk.exec());
+        script.sync_hooks(gb);
+        result.map_err(|e| e.to_string())?;
+        Ok(script)
+    }
+
+    /// Press and release the buttons the script set with joypad.set, called before every frame
+    pub fn apply_input(&mut self, joypad: &mut Joypad) {
+        let (mask, buttons) = std::mem::take(&mut self.shared.borrow_mut().input);
+        joypad.set_pressed_keys((joypad.pressed_keys() & !mask) | buttons);
+    }
+
+    /// Run a frame, calling memory callbacks as their addresses are accessed and frame callbacks
+    /// once it's done
+    pub fn run_frame(&mut self, gb: &mut Gameboy) -> Result<(), EmulationError> {
+        if self.stopped.get() {
+            return gb.step();
+        }
+        self.shared.borrow_mut().overlay.clear();
+        // The functions accessing the Gameboy are set up once for the whole frame
+        let result = with_gameboy(&self.lua, gb, |gb| {
+            let result = loop {
+                let frame_done = gb.borrow_mut().run_instruction();
+                if gb.borrow().mmu.hooks.has_hits() {
+                    let hits = gb.borrow_mut().mmu.hooks.take_hits();
+                    for hit in hits {
+                        self.call_memory_callbacks(gb, hit);
+                    }
+                }
+                match frame_done {
+                    Ok(false) => {}
+                    result => break result.map(|_| ()),
+                }
+            };
+            self.shared.borrow_mut().frames += 1;
+            self.call_frame_callbacks(gb);
+            Ok(result)
+        });
+        // Lua only fails here if it can't set up the functions, which leaves the frame unrun
+        result.unwrap_or_else(|e| {
+            self.stop(e);
+            self.sync_hooks(gb);
+            gb.step()
+        })
+    }
+
+    /// The text and shapes drawn by the script
+    pub fn overlay(&self) -> Ref<'_, Overlay> {
+        Ref::map(self.shared.borrow(), |shared| &shared.overlay)
+    }
+
+    fn call_memory_callbacks(&self, gb: &RefCell<&mut Gameboy>, hit: Hit) {
+        let functions = self.functions(|shared| shared.memory_callbacks.get(&(hit.access, hit.addr)));
+        for function in functions {
+            self.call(gb, || function.call((hit.addr, hit.value)));
+        }
+    }
+
+    fn call_frame_callbacks(&self, gb: &RefCell<&mut Gameboy>) {
+        for function in self.functions(|shared| Some(&shared.frame_callbacks)) {
+            self.call(gb, || function.call(()));
+        }
+    }
+
+    /// The functions of a list of callbacks
+    /// They're collected up front so that callbacks can add and remove callbacks
+    fn functions(&self, callbacks: impl Fn(&Shared) -> Option<&Vec<Callback>>) -> Vec<Function<'_>> {
+        let shared = self.shared.borrow();
+        let Some(callbacks) = callbacks(&shared) else {
+            return Vec::new();
+        };
+        callbacks
+            .iter()
+            .filter_map(|callback| self.lua.registry_value(&callback.function).ok())
+            .collect()
+    }
+
+    /// Call into the script from inside `with_gameboy`, stopping it if it raises an error
+    fn call(&self, gb: &RefCell<&mut Gameboy>, f: impl FnOnce() -> mlua::Result<()>) {
+        if self.stopped.get() {
+            return;
+        }
+        if let Err(e) = f() {
+            self.stop(e);
+        }
+        self.sync_hooks(&mut gb.borrow_mut());
+    }
+
+    /// Stop the script after an error, dropping its callbacks
+    fn stop(&self, e: mlua::Error) {
+        log::error!("Script stopped: {}", e);
+        self.stopped.set(true);
+        let mut shared = self.shared.borrow_mut();
+        shared.frame_callbacks.clear();
+        shared.memory_callbacks.clear();
+        shared.hooks_changed = true;
+    }
+
+    /// Hook the addresses of the memory callbacks in the core
+    fn sync_hooks(&self, gb: &mut Gameboy) {
+        let mut shared = self.shared.borrow_mut();
+        if !shared.hooks_changed {
+            return;
+        }
+        shared.hooks_changed = false;
+        gb.mmu.hooks.clear();
+        for &(access, addr) in shared.memory_callbacks.keys() {
+            gb.mmu.hooks.add(access, addr);
+        }
+    }
+}
+
+/// Set up the tables that don't need the Gameboy, and empty ones for `with_gameboy` to fill in
+fn register_globals(lua: &Lua, shared: &Rc<RefCell<Shared>>) -> mlua::Result<()> {
+    let globals = lua.globals();
+    globals.set("memory", lua.create_table()?)?;
+    let registers = lua.create_table()?;
+    registers.set_metatable(Some(lua.create_table()?));
+    globals.set("registers", registers)?;
+
+    let event = lua.create_table()?;
+    let s = shared.clone();
+    event.set(
+        "onframe",
+        lua.create_function(move |lua, function: Function| add_callback(lua, &s, None, function))?,
+    )?;
+    for (name, access) in [("onread", Access::Read), ("onwrite", Access::Write), ("onexec", Access::Execute)] {
+        let s = shared.clone();
+        event.set(
+            name,
+            lua.create_function(move |lua, (addr, function): (u16, Function)| {
+                add_callback(lua, &s, Some((access, addr)), function)
+            })?,
+        )?;
+    }
+    let s = shared.clone();
+    event.set(
+        "remove",
+        lua.create_function(move |_, id: u32| {
+            let mut shared = s.borrow_mut();
+            if let Some(i) = shared.frame_callbacks.iter().position(|callback| callback.id == id) {
+                shared.frame_callbacks.remove(i);
+                return Ok(true);
+            }
+            let Some((&hook, callbacks)) = shared
+                .memory_callbacks
+                .iter_mut()
+                .find(|(_, callbacks)| callbacks.iter().any(|callback| callback.id == id))
+            else {
+                return Ok(false);
+            };
+            callbacks.retain(|callback| callback.id != id);
+            if callbacks.is_empty() {
+                shared.memory_callbacks.remove(&hook);
+                shared.hooks_changed = true;
+            }
+            Ok(true)
+        })?,
+    )?;
+    globals.set("event", event)?;
+
+    let gui = lua.create_table()?;
+    let s = shared.clone();
+    gui.set(
+        "text",
+        lua.create_function(
+            move |_, (x, y, text, color, background): (i32, i32, String, Option<u32>, Option<u32>)| {
+                s.borrow_mut().overlay.text(
+                    x,
+                    y,
+                    &text,
+                    color.unwrap_or(DEFAULT_TEXT_COLOR),
+                    background.unwrap_or(DEFAULT_TEXT_BACKGROUND),
+                );
+                Ok(())
+            },
+        )?,
+    )?;
+    let s = shared.clone();
+    gui.set(
+        "pixel",
+        lua.create_function(move |_, (x, y, color): (i32, i32, u32)| {
+            s.borrow_mut().overlay.pixel(x, y, color);
+            Ok(())
+        })?,
+    )?;
+    let s = shared.clone();
+    gui.set(
+        "line",
+        lua.create_function(move |_, (x1, y1, x2, y2, color): (i32, i32, i32, i32, u32)| {
+            s.borrow_mut().overlay.line(x1, y1, x2, y2, color);
+            Ok(())
+        })?,
+    )?;
+    let s = shared.clone();
+    gui.set(
+        "box",
+        lua.create_function(
+            move |_, (x1, y1, x2, y2, outline, fill): (i32, i32, i32, i32, Option<u32>, Option<u32>)| {
+                s.borrow_mut()
+                    .overlay
+                    .rect(x1, y1, x2, y2, outline.unwrap_or(DEFAULT_OUTLINE), fill.unwrap_or(0));
+                Ok(())
+            },
+        )?,
+    )?;
+    let s = shared.clone();
+    gui.set(
+        "clear",
+        lua.create_function(move |_, ()| {
+            s.borrow_mut().overlay.clear();
+            Ok(())
+        })?,
+    )?;
+    globals.set("gui", gui)?;
+
+    let joypad = lua.create_table()?;
+    let s = shared.clone();
+    joypad.set(
+        "set",
+        lua.create_function(move |_, buttons: Table| {
+            let mut shared = s.borrow_mut();
+            for pair in buttons.pairs::<String, bool>() {
+                let (name, pressed) = pair?;
+                let bit = button_bit(&name)?;
+                shared.input.0 |= bit;
+                if pressed {
+                    shared.input.1 |= bit;
+                } else {
+                    shared.input.1 &= !bit;
+                }
+            }
+            Ok(())
+        })?,
+    )?;
+    globals.set("joypad", joypad)?;
+
+    let emu = lua.create_table()?;
+    let s = shared.clone();
+    emu.set("framecount", lua.create_function(move |_, ()| Ok(s.borrow().frames))?)?;
+    globals.set("emu", emu)
+}
+
+fn add_callback(
+    lua: &Lua,
+    shared: &RefCell<Shared>,
+    hook: Option<(Access, u16)>,
+    function: Function,
+) -> mlua::Result<u32> {
+    let function = lua.create_registry_value(function)?;
+    let mut shared = shared.borrow_mut();
+    let id = shared.next_id;
+    shared.next_id += 1;
+    let callback = Callback { id, function };
+    match hook {
+        Some(hook) => {
+            shared.hooks_changed = true;
+            shared.memory_callbacks.entry(hook).or_default().push(callback);
+        }
+        None => shared.frame_callbacks.push(callback),
+    }
+    Ok(id)
+}
+
+fn button_bit(name: &str) -> mlua::Result<u8> {
+    BUTTONS
+        .iter()
+        .position(|button| button.eq_ignore_ascii_case(name))
+        .map(|bit| 1 << bit)
+        .ok_or_else(|| mlua::Error::runtime(format!("Unknown button {}", name)))
+}
+
+fn read_register(reg: &Registers, name: &str) -> Option<u16> {
+    Some(match name {
+        "a" => reg.a as u16,
+        "b" => reg.b as u16,
+        "c" => reg.c as u16,
+        "d" => reg.d as u16,
+        "e" => reg.e as u16,
+        "f" => reg.f as u16,
+        "h" => reg.h as u16,
+        "l" => reg.l as u16,
+        "af" => reg.af(),
+        "bc" => reg.bc(),
+        "de" => reg.de(),
+        "hl" => reg.hl(),
+        "sp" => reg.sp,
+        "pc" => reg.pc,
+        _ => return None,
+    })
+}
+
+fn write_register(reg: &mut Registers, name: &str, value: u16) -> mlua::Result<()> {
+    let byte = || u8::try_from(value).map_err(|_| mlua::Error::runtime(format!("{} is out of range for {}", value, name)));
+    match name {
+        "a" => reg.a = byte()?,
+        "b" => reg.b = byte()?,
+        "c" => reg.c = byte()?,
+        "d" => reg.d = byte()?,
+        "e" => reg.e = byte()?,
+        // The lower nibble of F is always 0
+        "f" => reg.f = byte()? & 0xF0,
+        "h" => reg.h = byte()?,
+        "l" => reg.l = byte()?,
+        "af" => reg.set_af(value),
+        "bc" => reg.set_bc(value),
+        "de" => reg.set_de(value),
+        "hl" => reg.set_hl(value),
+        "sp" => reg.sp = value,
+        "pc" => reg.pc = value,
+        _ => return Err(mlua::Error::runtime(format!("Unknown register {}", name))),
+    }
+    Ok(())
+}
+
+/// Run Lua code with the functions that access the Gameboy pointing at it
+/// They only live as long as the call, calling them later raises an error
+/// The Gameboy is passed on to `f`, which mustn't keep it borrowed while calling into Lua
+fn with_gameboy<R>(
+    lua: &Lua,
+    gb: &mut Gameboy,
+    f: impl FnOnce(&RefCell<&mut Gameboy>) -> mlua::Result<R>,
+) -> mlua::Result<R> {
+    let gb = RefCell::new(gb);
+    lua.scope(|scope| {
+        let globals = lua.globals();
+        let memory: Table = globals.get("memory")?;
+        memory.set(
+            "read",
+            scope.create_function(|_, (addr, bank): (u16, Option<usize>)| Ok(gb.borrow().mmu.peek(addr, bank)))?,
+        )?;
+        memory.set(
+            "write",
+            scope.create_function(|_, (addr, value, bank): (u16, u8, Option<usize>)| {
+                gb.borrow_mut().mmu.poke(addr, bank, value);
+                Ok(())
+            })?,
+        )?;
+        memory.set(
+            "read16",
+            scope.create_function(|_, (addr, bank): (u16, Option<usize>)| {
+                let mmu = &gb.borrow().mmu;
+                Ok(u16::from_le_bytes([mmu.peek(addr, bank), mmu.peek(addr.wrapping_add(1), bank)]))
+            })?,
+        )?;
+        memory.set(
+            "write16",
+            scope.create_function(|_, (addr, value, bank): (u16, u16, Option<usize>)| {
+                let mmu = &mut gb.borrow_mut().mmu;
+                let [l, h] = value.to_le_bytes();
+                mmu.poke(addr, bank, l);
+                mmu.poke(addr.wrapping_add(1), bank, h);
+                Ok(())
+            })?,
+        )?;
+
+        let registers: Table = globals.get("registers")?;
+        let meta = registers.get_metatable().unwrap();
+        meta.set(
+            "__index",
+            scope.create_function(|_, (_, name): (Table, String)| Ok(read_register(gb.borrow().registers(), &name)))?,
+        )?;
+        meta.set(
+            "__newindex",
+            scope.create_function(|_, (_, name, value): (Table, String, u16)| {
+                write_register(gb.borrow_mut().registers_mut(), &name, value)
+            })?,
+        )?;
+
+        let joypad: Table = globals.get("joypad")?;
+        joypad.set(
+            "get",
+            scope.create_function(|lua, ()| {
+                let pressed = gb.borrow().mmu.joypad.pressed_keys();
+                let buttons = lua.create_table()?;
+                for (bit, name) in BUTTONS.iter().enumerate() {
+                    buttons.set(*name, pressed & (1 << bit) != 0)?;
+                }
+                Ok(buttons)
+            })?,
+        )?;
+
+        f(&gb)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ROM: &str = "../rustyboy-core/resources/test-rom.gb";
+
+    /// A Gameboy looping on LD A,$42; LD ($C100),A; JR -2 in WRAM
+    fn looping_gameboy() -> Gameboy {
+        let mut gb = Gameboy::new(TEST_ROM).unwrap();
+        for (i, byte) in [0x3E, 0x42, 0xEA, 0x00, 0xC1, 0x18, 0xFB].into_iter().enumerate() {
+            gb.mmu.poke(0xC000 + i as u16, None, byte);
+        }
+        gb.registers_mut().pc = 0xC000;
+        gb
+    }
+
+    fn load(name: &str, source: &str, gb: &mut Gameboy) -> Result<Script, String> {
+        let path = std::env::temp_dir().join(format!("rustyboy-{}-{}.lua", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let script = Script::load(path.to_str().unwrap(), gb);
+        std::fs::remove_file(path).unwrap();
+        script
+    }
+
+    #[test]
+    fn test_read_register() {
+        let mut reg = Registers::new();
+        reg.a = 0x12;
+        reg.f = 0xB0;
+        reg.set_bc(0x3456);
+        reg.set_de(0x789A);
+        reg.set_hl(0xBCDE);
+        reg.sp = 0xFFFE;
+        reg.pc = 0x0150;
+        let expected = [
+            ("a", 0x12),
+            ("b", 0x34),
+            ("c", 0x56),
+            ("d", 0x78),
+            ("e", 0x9A),
+            ("f", 0xB0),
+            ("h", 0xBC),
+            ("l", 0xDE),
+            ("af", 0x12B0),
+            ("bc", 0x3456),
+            ("de", 0x789A),
+            ("hl", 0xBCDE),
+            ("sp", 0xFFFE),
+            ("pc", 0x0150),
+        ];
+        for (name, value) in expected {
+            assert_eq!(read_register(&reg, name), Some(value), "{}", name);
+        }
+        assert_eq!(read_register(&reg, "ix"), None);
+    }
+
+    #[test]
+    fn test_write_register() {
+        let mut reg = Registers::new();
+        write_register(&mut reg, "b", 0x12).unwrap();
+        write_register(&mut reg, "l", 0x34).unwrap();
+        assert_eq!((reg.b, reg.l), (0x12, 0x34));
+        write_register(&mut reg, "de", 0x5678).unwrap();
+        assert_eq!((reg.d, reg.e), (0x56, 0x78));
+        write_register(&mut reg, "pc", 0xC000).unwrap();
+        assert_eq!(reg.pc, 0xC000);
+
+        // The lower nibble of F can't be set, through F or AF
+        write_register(&mut reg, "f", 0xFF).unwrap();
+        assert_eq!(reg.f, 0xF0);
+        write_register(&mut reg, "af", 0x9AFF).unwrap();
+        assert_eq!((reg.a, reg.f), (0x9A, 0xF0));
+
+        assert!(write_register(&mut reg, "a", 0x100).is_err());
+        assert!(write_register(&mut reg, "ix", 0).is_err());
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let mut gb = looping_gameboy();
+        let source = r#"
+            local writes = 0
+            event.onwrite(0xC100, function(addr, value)
+                writes = writes + 1
+                memory.write(0xC101, value)
+            end)
+            local id = event.onread(0xC001, function() error("removed callback was called") end)
+            event.remove(id)
+            event.onframe(function()
+                memory.write16(0xC102, writes)
+                registers.b = registers.a
+                gui.pixel(0, 0, 0xFF0000FF)
+            end)
+        "#;
+        let mut script = load("callbacks", source, &mut gb).unwrap();
+        assert!(gb.mmu.hooks.is_hooked(Access::Write, 0xC100));
+        assert!(!gb.mmu.hooks.is_hooked(Access::Read, 0xC001));
+
+        script.run_frame(&mut gb).unwrap();
+        assert_eq!(gb.mmu.peek(0xC101, None), 0x42);
+        let writes = u16::from_le_bytes([gb.mmu.peek(0xC102, None), gb.mmu.peek(0xC103, None)]);
+        assert!(writes > 100);
+        assert_eq!(gb.registers().b, 0x42);
+        assert!(!script.stopped.get());
+    }
+
+    #[test]
+    fn test_error_stops_script() {
+        let mut gb = looping_gameboy();
+        let source = r#"
+            event.onwrite(0xC100, function() end)
+            event.onframe(function() memory.write(0xC101, 1) error("oops") end)
+        "#;
+        let mut script = load("error", source, &mut gb).unwrap();
+        script.run_frame(&mut gb).unwrap();
+        assert!(script.stopped.get());
+        assert!(!gb.mmu.hooks.is_hooked(Access::Write, 0xC100));
+
+        // Nothing of the script runs afterwards
+        gb.mmu.poke(0xC101, None, 0);
+        script.run_frame(&mut gb).unwrap();
+        assert_eq!(gb.mmu.peek(0xC101, None), 0);
+        assert!(!gb.mmu.hooks.has_hits());
+
+        assert!(load("syntax", "event.onframe(", &mut gb).is_err());
+    }
+}